@@ -0,0 +1,17 @@
+use std::path::Path;
+
+// Bravura is built into the binary when its font and metadata are in
+// assets/fonts. A checkout without them still builds, drawing notation from a
+// user-installed font or the vector fallbacks.
+fn main() {
+    let dir = Path::new("assets").join("fonts");
+    let font = dir.join("Bravura.otf");
+    let metadata = dir.join("bravura_metadata.json");
+    
+    println!("cargo::rustc-check-cfg=cfg(bundled_music_font)");
+    println!("cargo::rerun-if-changed={}", font.display());
+    println!("cargo::rerun-if-changed={}", metadata.display());
+    if font.exists() && metadata.exists() {
+        println!("cargo::rustc-cfg=bundled_music_font");
+    }
+}
\ No newline at end of file
@@ -1,37 +1,252 @@
 use eframe::egui;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
 use crate::midi::{MidiInput, MidiEvent, MidiDevice};
-use crate::notation::NotationRenderer;
-use crate::game::GameEngine;
-use crate::music::MusicLibrary;
+use crate::notation::{glyphs, Hand, NotationRenderer, ScoreLayout};
+use crate::game::{FeedbackSystem, GameEngine, GameState, PracticeMode, ProgressTracker, TimingJudgement};
+use crate::music::{export, MusicLibrary};
+use crate::music::scanner::SONG_EXTENSIONS;
+use crate::ui::settings::AppSettings;
+use crate::ui::{ImportAction, MainWindow, SettingsWindow, SongBrowser, SongImportDialog};
 
 pub struct PianoApp {
     midi_input: Arc<Mutex<MidiInput>>,
     notation_renderer: NotationRenderer,
     game_engine: GameEngine,
     music_library: MusicLibrary,
+    progress_tracker: ProgressTracker,
+    progress_path: Option<PathBuf>, // None disables saving
+    main_window: MainWindow,
+    song_browser: SongBrowser,
+    settings_window: SettingsWindow,
+    song_import: Option<SongImportDialog>,
+    feedback_system: FeedbackSystem,
     midi_events: Arc<Mutex<Vec<MidiEvent>>>,
     available_devices: Vec<MidiDevice>,
     selected_device_index: Option<usize>,
-    show_device_selector: bool,
+    score_scroll: f32, // Offset of the score area along its scrolling direction
 }
 
+// Rate at which the score scrolls towards the cursor, per second
+const SCROLL_SMOOTHING: f32 = 6.0;
+// Room kept above the active system, and the share of a ticker's width kept
+// left of the cursor
+const SCROLL_TOP_MARGIN: f32 = 10.0;
+const TICKER_CURSOR_FRACTION: f32 = 0.3;
+
 impl PianoApp {
-    pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
+    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        glyphs::install(&cc.egui_ctx);
         let midi_events = Arc::new(Mutex::new(Vec::new()));
         let midi_input = Arc::new(Mutex::new(MidiInput::new(midi_events.clone())));
         let available_devices = MidiDevice::list_available();
+        let (progress_tracker, progress_path) = Self::load_progress();
+        
+        let settings_path = AppSettings::default_path();
+        let settings = match &settings_path {
+            Some(path) => AppSettings::load(path).unwrap_or_else(|e| {
+                log::error!("Failed to load settings, using defaults: {:#}", e);
+                AppSettings::default()
+            }),
+            None => AppSettings::default(),
+        };
         
-        Self {
+        let mut app = Self {
             midi_input,
             notation_renderer: NotationRenderer::new(),
             game_engine: GameEngine::new(),
             music_library: MusicLibrary::new(),
+            progress_tracker,
+            progress_path,
+            main_window: MainWindow::new(),
+            song_browser: SongBrowser::new(),
+            settings_window: SettingsWindow::new(settings, settings_path),
+            song_import: None,
+            feedback_system: FeedbackSystem::new(),
             midi_events,
             available_devices,
             selected_device_index: None,
-            show_device_selector: false,
+            score_scroll: 0.0,
+        };
+        
+        app.apply_settings();
+        if let Some(first_song) = app.music_library.get_songs().first() {
+            let id = first_song.id.clone();
+            app.load_song(&id);
+        }
+        app
+    }
+    
+    fn load_song(&mut self, song_id: &str) {
+        match self.music_library.select_song_by_id(song_id) {
+            Some(song) => {
+                log::info!("Loaded song: {}", song.title);
+                self.game_engine.load_song(song);
+            }
+            None => log::error!("Unknown song: {}", song_id),
+        }
+    }
+    
+    // Pushes the current settings into the components that use them
+    fn apply_settings(&mut self) {
+        let settings = self.settings_window.get_settings().clone();
+        
+        if let Ok(midi_input) = self.midi_input.lock() {
+            midi_input.set_latency_compensation(settings.midi_latency_compensation);
+        }
+        self.feedback_system.set_duration(settings.visual_feedback_duration);
+        self.notation_renderer.set_show_note_names(settings.show_note_names);
+        self.notation_renderer.set_layout(settings.score_layout);
+        self.game_engine.set_auto_advance(settings.auto_advance);
+        self.game_engine.set_chord_tolerance(settings.chord_tolerance_ms);
+        
+        // The metronome tempo drives timed practice
+        if let PracticeMode::Timed { bpm } = self.game_engine.get_mode() {
+            if bpm != settings.metronome_bpm as f32 {
+                self.game_engine.set_mode(PracticeMode::Timed { bpm: settings.metronome_bpm as f32 });
+            }
+        }
+    }
+    
+    // Beat pulse for the visual metronome, following the beat clock in timed mode
+    fn draw_metronome(&self, ui: &mut egui::Ui) {
+        let settings = self.settings_window.get_settings();
+        let beat = self.game_engine.get_playhead_beats().unwrap_or_else(|| {
+            let seconds = crate::midi::current_timestamp() as f64 / 1_000_000.0;
+            (seconds * settings.metronome_bpm as f64 / 60.0) as f32
+        });
+        
+        let beats_per_measure = self.music_library.get_current_song()
+            .map(|song| song.signatures.time_signature_at(beat.max(0.0)).beats_per_measure())
+            .unwrap_or(4.0);
+        
+        let on_beat = beat.rem_euclid(1.0) < 0.15;
+        let downbeat = beat.rem_euclid(beats_per_measure) < 1.0;
+        let color = match (on_beat, downbeat) {
+            (true, true) => egui::Color32::from_rgb(200, 0, 0),
+            (true, false) => egui::Color32::from_rgb(0, 150, 0),
+            _ => egui::Color32::LIGHT_GRAY,
+        };
+        
+        let (rect, _) = ui.allocate_exact_size(egui::Vec2::splat(16.0), egui::Sense::hover());
+        ui.painter().circle_filled(rect.center(), 7.0, color);
+        ui.label(format!("{} BPM", settings.metronome_bpm));
+    }
+    
+    fn load_progress() -> (ProgressTracker, Option<PathBuf>) {
+        let Some(path) = ProgressTracker::default_path() else {
+            log::warn!("No data directory available, progress will not be saved");
+            return (ProgressTracker::new(), None);
+        };
+        
+        match ProgressTracker::load(&path) {
+            Ok(tracker) => (tracker, Some(path)),
+            Err(e) => {
+                // Keep the unreadable file intact rather than overwriting it
+                log::error!("Failed to load progress, saving disabled for this session: {:#}", e);
+                (ProgressTracker::new(), None)
+            }
+        }
+    }
+    
+    fn pick_song_file(&mut self) {
+        let file = rfd::FileDialog::new()
+            .set_title("Import Song")
+            .add_filter("MIDI, MusicXML and ABC files", &SONG_EXTENSIONS)
+            .pick_file();
+        
+        if let Some(path) = file {
+            self.song_import = Some(SongImportDialog::open(path));
+        }
+    }
+    
+    fn export_song(&self) {
+        let Some(song) = self.music_library.get_current_song() else {
+            return;
+        };
+        let file = rfd::FileDialog::new()
+            .set_title("Export Song")
+            .add_filter("MusicXML", &["musicxml"])
+            .add_filter("MIDI", &["mid"])
+            .set_file_name(format!("{}.musicxml", song.id))
+            .save_file();
+        
+        if let Some(path) = file {
+            if let Err(e) = export::export_song(song, &path) {
+                log::error!("Song export failed: {:#}", e);
+            }
+        }
+    }
+    
+    // Saves the notes played in the current or last attempt
+    fn export_take(&self) {
+        let (Some(take), Some(song)) = (self.game_engine.get_take(), self.music_library.get_current_song()) else {
+            return;
+        };
+        let file = rfd::FileDialog::new()
+            .set_title("Export Take")
+            .add_filter("MIDI", &["mid"])
+            .set_file_name(format!("{}-take.mid", song.id))
+            .save_file();
+        
+        if let Some(path) = file {
+            if let Err(e) = export::export_take(take, &song.title, &path) {
+                log::error!("Take export failed: {:#}", e);
+            }
+        }
+    }
+    
+    fn show_song_import(&mut self, ctx: &egui::Context) {
+        let Some(dialog) = &mut self.song_import else {
+            return;
+        };
+        
+        match dialog.show(ctx) {
+            ImportAction::None => {}
+            ImportAction::Cancel => self.song_import = None,
+            ImportAction::Import => {
+                let Some(parsed) = dialog.get_parsed() else {
+                    return;
+                };
+                
+                let result = self.music_library.import_song(
+                    dialog.get_source(),
+                    dialog.get_title(),
+                    dialog.get_artist(),
+                    parsed,
+                    &dialog.get_selection(),
+                );
+                
+                match result {
+                    Ok(song_id) => {
+                        self.song_import = None;
+                        self.load_song(&song_id);
+                    }
+                    Err(e) => {
+                        log::error!("Song import failed: {:#}", e);
+                        dialog.set_error(format!("Import failed: {:#}", e));
+                    }
+                }
+            }
+        }
+    }
+    
+    fn record_completed_attempt(&mut self) {
+        let Some(attempt) = self.game_engine.take_completed_attempt() else {
+            return;
+        };
+        
+        let Some(song_id) = self.music_library.get_current_song().map(|song| song.id.clone()) else {
+            return;
+        };
+        self.progress_tracker.update_song_progress(song_id, attempt.correct_notes, attempt.total_notes);
+        self.progress_tracker.add_practice_time(attempt.duration_secs);
+        
+        if let Some(path) = &self.progress_path {
+            if let Err(e) = self.progress_tracker.save(path) {
+                log::error!("Failed to save progress: {:#}", e);
+            }
         }
     }
     
@@ -67,17 +282,50 @@ impl eframe::App for PianoApp {
                 self.game_engine.process_midi_event(&event);
             }
         }
-
+        self.game_engine.update();
+        self.record_completed_attempt();
+        
+        for feedback in self.game_engine.take_feedback() {
+            self.feedback_system.add_feedback(feedback);
+        }
+        self.feedback_system.update();
+        
+        // Pick up songs added to or removed from the library folder
+        self.music_library.poll_changes();
+        
         // Set white background color scheme
         ctx.set_visuals(egui::Visuals {
             dark_mode: false,
             override_text_color: Some(egui::Color32::BLACK),
             ..egui::Visuals::light()
         });
-
+        
+        // Menu bar
+        let device_selector_was_open = self.main_window.should_show_device_selector();
+        egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
+            let has_song = self.music_library.get_current_song().is_some();
+            let has_take = self.game_engine.get_take().is_some();
+            self.main_window.show_menu_bar(ui, has_song, has_take);
+        });
+        if self.main_window.should_show_device_selector() && !device_selector_was_open {
+            self.refresh_devices();
+        }
+        if self.main_window.take_import_song_request() {
+            self.pick_song_file();
+        }
+        if self.main_window.take_export_song_request() {
+            self.export_song();
+        }
+        if self.main_window.take_export_take_request() {
+            self.export_take();
+        }
+        
         // Main application UI
         egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading("Piano Sight Reading");
+            let title = self.music_library.get_current_song()
+                .map(|song| song.title.as_str())
+                .unwrap_or("Piano Sight Reading");
+            ui.heading(title);
             
             // MIDI device selection and status
             ui.horizontal(|ui| {
@@ -95,7 +343,7 @@ impl eframe::App for PianoApp {
                 };
                 
                 if ui.button(&current_device_name).clicked() {
-                    self.show_device_selector = true;
+                    self.main_window.open_device_selector();
                     self.refresh_devices();
                 }
                 
@@ -121,16 +369,40 @@ impl eframe::App for PianoApp {
             let notation_height = available_rect.height() - 100.0; // Leave space for controls
             
             if notation_height > 200.0 {
-                egui::ScrollArea::vertical()
+                let settings = self.settings_window.get_settings();
+                let layout = settings.score_layout;
+                let content_width = available_rect.width() - 20.0;
+                let content_size = self.notation_renderer.calculate_content_size(&self.game_engine, content_width);
+                
+                // While playing, glide towards the cursor: the active system at the
+                // top of a page, or the cursor near the left of a ticker
+                let playing = self.game_engine.get_state() == GameState::Playing;
+                let cursor = self.game_engine.get_cursor_beat()
+                    .and_then(|beat| self.notation_renderer.cursor_rect(beat));
+                let mut scroll_area = match layout {
+                    ScoreLayout::Page => egui::ScrollArea::vertical(),
+                    ScoreLayout::Ticker => egui::ScrollArea::horizontal(),
+                };
+                if let (true, true, Some(cursor)) = (settings.follow_cursor, playing, cursor) {
+                    let (target, max_scroll) = match layout {
+                        ScoreLayout::Page => (cursor.top() - SCROLL_TOP_MARGIN, content_size.y - notation_height),
+                        ScoreLayout::Ticker => (cursor.center().x - content_width * TICKER_CURSOR_FRACTION, content_size.x - content_width),
+                    };
+                    let target = target.clamp(0.0, max_scroll.max(0.0));
+                    let dt = ctx.input(|input| input.stable_dt).min(0.1);
+                    self.score_scroll += (target - self.score_scroll) * (1.0 - (-SCROLL_SMOOTHING * dt).exp());
+                    scroll_area = match layout {
+                        ScoreLayout::Page => scroll_area.vertical_scroll_offset(self.score_scroll),
+                        ScoreLayout::Ticker => scroll_area.horizontal_scroll_offset(self.score_scroll),
+                    };
+                }
+                
+                let output = scroll_area
                     .max_height(notation_height)
                     .show(ui, |ui| {
                         // Allocate space for multiple staff systems
-                        let content_height = self.notation_renderer.calculate_content_height(&self.game_engine);
                         let notation_response = ui.allocate_rect(
-                            egui::Rect::from_min_size(
-                                ui.cursor().min,
-                                egui::Vec2::new(available_rect.width() - 20.0, content_height)
-                            ),
+                            egui::Rect::from_min_size(ui.cursor().min, content_size),
                             egui::Sense::hover()
                         );
                         
@@ -143,6 +415,12 @@ impl eframe::App for PianoApp {
                         
                         self.notation_renderer.render(ui, notation_response.rect, &self.game_engine);
                     });
+                
+                // Manual scrolling is where following picks up from
+                self.score_scroll = match layout {
+                    ScoreLayout::Page => output.state.offset.y,
+                    ScoreLayout::Ticker => output.state.offset.x,
+                };
             }
             
             ui.separator();
@@ -160,6 +438,27 @@ impl eframe::App for PianoApp {
                 if ui.button("Reset").clicked() {
                     self.game_engine.reset();
                 }
+                
+                ui.separator();
+                
+                // Practice mode selection
+                let mode = self.game_engine.get_mode();
+                let mut timed = matches!(mode, PracticeMode::Timed { .. });
+                if ui.checkbox(&mut timed, "Timed (rhythm)").changed() {
+                    self.game_engine.set_mode(if timed {
+                        PracticeMode::Timed { bpm: self.settings_window.get_settings().metronome_bpm as f32 }
+                    } else {
+                        PracticeMode::NoteByNote
+                    });
+                }
+                
+                if self.settings_window.get_settings().metronome_enabled {
+                    ui.separator();
+                    self.draw_metronome(ui);
+                }
+                
+                ui.separator();
+                self.feedback_system.render(ui);
             });
             
             // Progress display
@@ -167,11 +466,119 @@ impl eframe::App for PianoApp {
                 ui.label("Progress:");
                 let progress = self.game_engine.get_progress();
                 ui.add(egui::ProgressBar::new(progress).show_percentage());
+                
+                let (correct, total) = self.game_engine.get_score();
+                ui.label(format!("Score: {}/{}", correct, total));
+                let stray_keys = self.game_engine.get_stray_keys();
+                if stray_keys > 0 {
+                    ui.label(format!("Stray keys: {}", stray_keys));
+                }
+                
+                // Each hand separately, for pieces written for both
+                let hand_scores: Vec<(Hand, (u32, u32))> = [Hand::Right, Hand::Left].into_iter()
+                    .filter_map(|hand| self.game_engine.get_hand_score(hand).map(|score| (hand, score)))
+                    .collect();
+                if hand_scores.len() > 1 {
+                    for (hand, (correct, total)) in hand_scores {
+                        ui.label(format!("{}: {}/{}", hand.as_str(), correct, total));
+                    }
+                }
+                
+                // Pedal indicators
+                let pedals = self.game_engine.get_pedal_state();
+                for (down, name) in [(pedals.sustain, "Sustain"), (pedals.sostenuto, "Sostenuto"), (pedals.soft, "Soft")] {
+                    ui.colored_label(
+                        if down { egui::Color32::from_rgb(0, 150, 0) } else { egui::Color32::GRAY },
+                        name
+                    );
+                }
+                
+                // Graded pedal passages
+                let pedal_results = self.game_engine.get_pedal_results();
+                let graded = pedal_results.iter().flatten().count();
+                if graded > 0 {
+                    let correct = pedal_results.iter().flatten().filter(|&&ok| ok).count();
+                    ui.label(format!("Pedal passages: {}/{}", correct, graded));
+                }
             });
+            
+            // Saved history
+            ui.horizontal(|ui| {
+                let song_progress = self.music_library.get_current_song()
+                    .and_then(|song| self.progress_tracker.get_song_progress(&song.id));
+                if let Some(song_progress) = song_progress {
+                    ui.label(format!(
+                        "Best accuracy: {:.0}% ({} attempts)",
+                        song_progress.best_accuracy * 100.0,
+                        song_progress.attempts
+                    ));
+                    ui.separator();
+                }
+                
+                let stats = self.progress_tracker.get_player_stats();
+                ui.label(format!(
+                    "Overall accuracy: {:.0}% · Songs completed: {} · Practice time: {} min",
+                    self.progress_tracker.get_overall_accuracy() * 100.0,
+                    stats.songs_completed,
+                    stats.total_practice_time / 60
+                ));
+            });
+            
+            // Timing breakdown in timed mode
+            if matches!(self.game_engine.get_mode(), PracticeMode::Timed { .. }) {
+                ui.horizontal(|ui| {
+                    let timings = self.game_engine.get_note_timings();
+                    for judgement in [TimingJudgement::Perfect, TimingJudgement::Good, TimingJudgement::Early, TimingJudgement::Late, TimingJudgement::Miss] {
+                        let count = timings.iter()
+                            .flatten()
+                            .filter(|timing| timing.judgement == judgement)
+                            .count();
+                        ui.colored_label(judgement.color(), format!("{}: {}", judgement.as_str(), count));
+                    }
+                    
+                    // Mean signed offset of the notes that were hit (negative = rushing)
+                    let hits: Vec<f32> = timings.iter()
+                        .flatten()
+                        .filter(|timing| timing.judgement != TimingJudgement::Miss)
+                        .map(|timing| timing.deviation_ms)
+                        .collect();
+                    if !hits.is_empty() {
+                        let mean = hits.iter().sum::<f32>() / hits.len() as f32;
+                        ui.label(format!("Average offset: {:+.0} ms", mean));
+                    }
+                });
+            }
         });
-
+        
+        // Song browser
+        if self.main_window.should_show_song_browser() {
+            let mut open = true;
+            let selected = self.song_browser.show(ctx, &mut self.music_library, &self.progress_tracker, &mut open);
+            if let Some(song_id) = selected {
+                self.load_song(&song_id);
+                open = false;
+            }
+            if !open {
+                self.main_window.close_song_browser();
+            }
+        }
+        
+        // Settings window
+        if self.main_window.should_show_settings() {
+            let mut open = true;
+            if self.settings_window.show(ctx, &mut open) {
+                self.apply_settings();
+            }
+            if !open {
+                self.main_window.close_settings();
+            }
+        }
+        
+        self.main_window.show_about_dialog(ctx);
+        self.show_song_import(ctx);
+        
         // Device selector popup
-        if self.show_device_selector {
+        if self.main_window.should_show_device_selector() {
             egui::Window::new("Select MIDI Device")
                 .collapsible(false)
                 .resizable(false)
@@ -193,11 +600,9 @@ impl eframe::App for PianoApp {
                     
                     ui.separator();
                     ui.horizontal(|ui| {
-                        if ui.button("Connect").clicked() {
-                            if self.selected_device_index.is_some() {
-                                self.connect_to_selected_device();
-                                self.show_device_selector = false;
-                            }
+                        if ui.button("Connect").clicked() && self.selected_device_index.is_some() {
+                            self.connect_to_selected_device();
+                            self.main_window.close_device_selector();
                         }
                         
                         if ui.button("Refresh").clicked() {
@@ -205,12 +610,12 @@ impl eframe::App for PianoApp {
                         }
                         
                         if ui.button("Cancel").clicked() {
-                            self.show_device_selector = false;
+                            self.main_window.close_device_selector();
                         }
                     });
                 });
         }
-
+        
         // Request repaint for real-time updates
         ctx.request_repaint();
     }
@@ -0,0 +1,80 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::music::export::export_song;
+use crate::music::library::{built_in_songs, Song};
+use crate::music::scanner::LibraryScanner;
+use crate::notation::export_score;
+use crate::storage;
+
+const USAGE: &str = "Usage: piano export <song id or file> <output.svg|output.png|output.musicxml|output.mid> [--width N] [--scale N]";
+
+const DEFAULT_WIDTH: f32 = 1000.0;
+const DEFAULT_SCALE: f32 = 2.0;
+
+// Runs a command-line subcommand instead of the app. Returns None when the
+// arguments don't name one.
+pub fn run(args: &[String]) -> Option<Result<()>> {
+    match args.first().map(String::as_str) {
+        Some("export") => Some(export(&args[1..])),
+        _ => None,
+    }
+}
+
+fn export(args: &[String]) -> Result<()> {
+    let mut positional = Vec::new();
+    let mut width = DEFAULT_WIDTH;
+    let mut scale = DEFAULT_SCALE;
+    
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--width" => width = parse_number(args.next(), "--width")?,
+            "--scale" => scale = parse_number(args.next(), "--scale")?,
+            _ if arg.starts_with("--") => bail!("Unknown option {}\n{}", arg, USAGE),
+            _ => positional.push(arg),
+        }
+    }
+    let [song, output] = positional[..] else {
+        bail!(USAGE);
+    };
+    
+    let song = find_song(song)?;
+    
+    // Song formats write the notes themselves; anything else is an engraving
+    let output = PathBuf::from(output);
+    let extension = output.extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_lowercase);
+    match extension.as_deref() {
+        Some("musicxml" | "xml" | "mid" | "midi") => export_song(&song, &output),
+        _ => export_score(&song.notes, &song.rests, &song.signatures, &output, width, scale),
+    }
+}
+
+// Loads just the named song, leaving the library directory, its cache and
+// everything the app sets up untouched
+fn find_song(name: &str) -> Result<Song> {
+    if let Some(song) = built_in_songs().into_iter().find(|song| song.id == name) {
+        return Ok(song);
+    }
+    
+    let mut scanner = LibraryScanner::uncached();
+    let path = Path::new(name);
+    if path.is_file() {
+        return scanner.load_file(path);
+    }
+    storage::library_dir()
+        .and_then(|dir| scanner.scan(&dir).into_iter().find(|song| song.id == name))
+        .ok_or_else(|| anyhow!("No song with id '{}' and no such file", name))
+}
+
+fn parse_number(value: Option<&String>, option: &str) -> Result<f32> {
+    let value = value.ok_or_else(|| anyhow!("{} needs a value", option))?;
+    let number: f32 = value.parse()
+        .with_context(|| format!("Invalid value for {}: {}", option, value))?;
+    if number <= 0.0 {
+        bail!("{} must be positive", option);
+    }
+    Ok(number)
+}
\ No newline at end of file
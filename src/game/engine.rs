@@ -1,6 +1,16 @@
-use crate::midi::{MidiEvent, EventType};
-use crate::notation::{Note, NoteType};
-use std::collections::HashSet;
+use crate::midi::{self, MidiEvent, MidiRecording, EventType, Pedal};
+use crate::music::library::Song;
+use crate::music::TempoMap;
+use crate::notation::{Hand, Note, PedalMarking, SignatureTimeline, WrittenRest};
+use super::feedback::NoteFeedback;
+use super::rhythm::{BeatClock, NoteTiming, TimingJudgement, TimingWindows};
+use std::collections::{HashMap, HashSet};
+
+// Notes whose positions differ by less than this (in beats) are played together
+const CHORD_POSITION_EPSILON: f32 = 0.001;
+const DEFAULT_CHORD_TOLERANCE_MS: u64 = 250;
+// One bar of 4/4 count-in before beat 0 in timed mode
+const TIMED_LEAD_IN_BEATS: f32 = 4.0;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum GameState {
@@ -9,76 +19,196 @@ pub enum GameState {
     Paused,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PracticeMode {
+    NoteByNote,         // Wait for the right keys before advancing
+    Timed { bpm: f32 }, // Play along with a beat clock and judge timing
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PedalState {
+    pub sustain: bool,
+    pub sostenuto: bool,
+    pub soft: bool,
+}
+
+// Outcome of a play-through that reached the end of the piece
+#[derive(Debug, Clone, Copy)]
+pub struct AttemptResult {
+    pub correct_notes: u32,
+    pub total_notes: u32,
+    pub duration_secs: u64, // Not counting pauses
+}
+
 pub struct GameEngine {
     state: GameState,
+    mode: PracticeMode,
+    beat_clock: Option<BeatClock>,
+    tempo_map: TempoMap, // Of the loaded song
+    signatures: SignatureTimeline,
+    timing_windows: TimingWindows,
+    note_timings: Vec<Option<NoteTiming>>, // Parallel to current_notes, timed mode only
     current_notes: Vec<Note>,
-    current_position: usize,
+    current_rests: Vec<WrittenRest>,
+    chord_steps: Vec<Vec<usize>>, // Indices into current_notes, grouped by position
+    current_position: usize,      // Index into chord_steps
     pressed_keys: HashSet<u8>,
+    satisfied_keys: HashMap<u8, u64>, // Pitch -> press timestamp for the current step
+    pedals: PedalState,
+    sustained_keys: HashSet<u8>, // Released while the sustain pedal was down
+    sostenuto_keys: HashSet<u8>, // Held when the sostenuto pedal went down
+    pedal_markings: Vec<PedalMarking>,
+    step_pedaled: Vec<bool>, // Parallel to chord_steps
+    chord_tolerance_ms: u64,
+    auto_advance: bool,
+    awaiting_release: bool, // Step is complete but auto-advance is off
+    feedback: Vec<NoteFeedback>,
     correct_notes: u32,
     total_notes: u32,
+    stray_keys: u32, // Key presses outside every timing window in timed mode
+    attempt_clock: Option<BeatClock>, // Times the attempt, stopped while paused
+    completed_attempt: Option<AttemptResult>,
+    take: MidiRecording, // What was played in the latest attempt at this song
 }
 
 impl GameEngine {
     pub fn new() -> Self {
-        // Create a test sequence specifically for ledger line testing
-        let practice_notes = vec![
-            // System 1 - Treble clef ledger lines above
-            Note::new(72, NoteType::Quarter, 0.0),  // C5 (first line above treble staff)
-            Note::new(74, NoteType::Quarter, 1.0),  // D5 (space above treble staff)
-            Note::new(76, NoteType::Quarter, 2.0),  // E5 (second line above treble staff)
-            Note::new(77, NoteType::Quarter, 3.0),  // F5 (space)
-            Note::new(79, NoteType::Quarter, 4.0),  // G5 (third line above)
-            Note::new(81, NoteType::Quarter, 5.0),  // A5 (space)
-            Note::new(83, NoteType::Quarter, 6.0),  // B5 (fourth line above)
-            Note::new(84, NoteType::Quarter, 7.0),  // C6 (space)
-            
-            // System 2 - Notes on treble staff (no ledger lines needed)
-            Note::new(64, NoteType::Quarter, 8.0),  // E4 (top staff line)
-            Note::new(65, NoteType::Quarter, 9.0),  // F4 (space)
-            Note::new(67, NoteType::Quarter, 10.0), // G4 (line)
-            Note::new(69, NoteType::Quarter, 11.0), // A4 (space)
-            Note::new(71, NoteType::Quarter, 12.0), // B4 (line)
-            Note::new(60, NoteType::Quarter, 13.0), // C4 (Middle C - needs ledger line)
-            Note::new(62, NoteType::Quarter, 14.0), // D4 (space below treble staff)
-            Note::new(59, NoteType::Quarter, 15.0), // B3 (space)
-            
-            // System 3 - Bass clef notes and ledger lines below
-            Note::new(57, NoteType::Quarter, 16.0), // A3 (top bass staff line)
-            Note::new(55, NoteType::Quarter, 17.0), // G3 (space)
-            Note::new(53, NoteType::Quarter, 18.0), // F3 (line)
-            Note::new(52, NoteType::Quarter, 19.0), // E3 (space)
-            Note::new(50, NoteType::Quarter, 20.0), // D3 (line)
-            Note::new(48, NoteType::Quarter, 21.0), // C3 (bottom bass staff line)
-            Note::new(47, NoteType::Quarter, 22.0), // B2 (first ledger line below bass)
-            Note::new(45, NoteType::Quarter, 23.0), // A2 (space below bass)
-        ];
-        
         Self {
             state: GameState::Stopped,
-            current_notes: practice_notes,
+            mode: PracticeMode::NoteByNote,
+            beat_clock: None,
+            tempo_map: TempoMap::default(),
+            signatures: SignatureTimeline::default(),
+            timing_windows: TimingWindows::default(),
+            note_timings: Vec::new(),
+            current_notes: Vec::new(),
+            current_rests: Vec::new(),
+            chord_steps: Vec::new(),
             current_position: 0,
             pressed_keys: HashSet::new(),
+            satisfied_keys: HashMap::new(),
+            pedals: PedalState::default(),
+            sustained_keys: HashSet::new(),
+            sostenuto_keys: HashSet::new(),
+            pedal_markings: Vec::new(),
+            step_pedaled: Vec::new(),
+            chord_tolerance_ms: DEFAULT_CHORD_TOLERANCE_MS,
+            auto_advance: true,
+            awaiting_release: false,
+            feedback: Vec::new(),
             correct_notes: 0,
-            total_notes: 24,
+            total_notes: 0,
+            stray_keys: 0,
+            attempt_clock: None,
+            completed_attempt: None,
+            take: MidiRecording::default(),
         }
     }
     
+    pub fn load_song(&mut self, song: &Song) {
+        self.current_notes = song.notes.clone();
+        self.current_rests = song.rests.clone();
+        self.chord_steps = Self::build_chord_steps(&self.current_notes);
+        self.total_notes = self.current_notes.len() as u32;
+        self.pedal_markings = song.pedal_markings.clone();
+        self.tempo_map = song.tempo_map.clone();
+        self.signatures = song.signatures.clone();
+        self.take = MidiRecording::default();
+        self.reset();
+    }
+    
     pub fn start_practice(&mut self) {
         self.state = GameState::Playing;
         self.current_position = 0;
         self.correct_notes = 0;
+        self.stray_keys = 0;
         self.pressed_keys.clear();
+        self.satisfied_keys.clear();
+        self.awaiting_release = false;
+        self.clear_note_results();
+        let started_at = midi::current_timestamp();
+        self.attempt_clock = Some(BeatClock::new(TempoMap::default(), started_at, 0.0));
+        self.completed_attempt = None;
+        self.take = MidiRecording::new(started_at);
         
-        // Reset all note states
+        self.beat_clock = match self.mode {
+            PracticeMode::NoteByNote => None,
+            PracticeMode::Timed { bpm } => {
+                // The chosen tempo sets the pace of the song's opening; later tempo
+                // changes keep their proportions
+                let tempo_map = self.tempo_map.scaled(bpm.max(1.0) / self.tempo_map.initial_bpm());
+                Some(BeatClock::new(tempo_map, midi::current_timestamp(), TIMED_LEAD_IN_BEATS))
+            }
+        };
+    }
+    
+    pub fn set_mode(&mut self, mode: PracticeMode) {
+        self.mode = mode;
+        self.reset();
+    }
+    
+    pub fn get_state(&self) -> GameState {
+        self.state
+    }
+    
+    pub fn get_mode(&self) -> PracticeMode {
+        self.mode
+    }
+    
+    fn clear_note_results(&mut self) {
         for note in &mut self.current_notes {
             note.is_correct = None;
         }
+        self.note_timings = vec![None; self.current_notes.len()];
+        self.step_pedaled = vec![false; self.chord_steps.len()];
+        self.feedback.clear();
+    }
+    
+    // Groups notes sharing the same position into chord steps, in position order
+    fn build_chord_steps(notes: &[Note]) -> Vec<Vec<usize>> {
+        let mut order: Vec<usize> = (0..notes.len()).collect();
+        order.sort_by(|&a, &b| notes[a].position.total_cmp(&notes[b].position));
+        
+        let mut steps: Vec<Vec<usize>> = Vec::new();
+        for index in order {
+            match steps.last_mut() {
+                Some(step) if (notes[step[0]].position - notes[index].position).abs() < CHORD_POSITION_EPSILON => {
+                    step.push(index);
+                }
+                _ => steps.push(vec![index]),
+            }
+        }
+        
+        steps
+    }
+    
+    // Maximum spread between the first and last key of a rolled chord; keys
+    // released within this window still count towards the chord
+    pub fn set_chord_tolerance(&mut self, tolerance_ms: u64) {
+        self.chord_tolerance_ms = tolerance_ms;
+    }
+    
+    // When off, a correctly played step only advances once all keys are released
+    pub fn set_auto_advance(&mut self, auto_advance: bool) {
+        self.auto_advance = auto_advance;
     }
     
     pub fn pause(&mut self) {
+        let now = midi::current_timestamp();
+        
         match self.state {
-            GameState::Playing => self.state = GameState::Paused,
-            GameState::Paused => self.state = GameState::Playing,
+            GameState::Playing => {
+                self.state = GameState::Paused;
+                for clock in self.beat_clock.iter_mut().chain(&mut self.attempt_clock) {
+                    clock.pause(now);
+                }
+            }
+            GameState::Paused => {
+                self.state = GameState::Playing;
+                for clock in self.beat_clock.iter_mut().chain(&mut self.attempt_clock) {
+                    clock.resume(now);
+                }
+            }
             _ => {}
         }
     }
@@ -87,57 +217,541 @@ impl GameEngine {
         self.state = GameState::Stopped;
         self.current_position = 0;
         self.correct_notes = 0;
+        self.stray_keys = 0;
         self.pressed_keys.clear();
+        self.satisfied_keys.clear();
+        self.awaiting_release = false;
+        self.beat_clock = None;
+        self.attempt_clock = None;
+        self.clear_note_results();
+    }
+    
+    // Advances the beat clock in timed mode, marking notes the playhead has passed as missed
+    pub fn update(&mut self) {
+        if self.state != GameState::Playing {
+            return;
+        }
         
-        for note in &mut self.current_notes {
-            note.is_correct = None;
+        let Some(clock) = &self.beat_clock else {
+            return;
+        };
+        
+        let now = midi::current_timestamp();
+        for (i, note) in self.current_notes.iter_mut().enumerate() {
+            if self.note_timings[i].is_none() && clock.deviation_ms(note.position, now) > self.timing_windows.hit_ms {
+                self.note_timings[i] = Some(NoteTiming {
+                    judgement: TimingJudgement::Miss,
+                    deviation_ms: 0.0,
+                });
+                note.is_correct = Some(false);
+                self.feedback.push(NoteFeedback::Incorrect);
+            }
         }
+        
+        self.advance_judged_steps();
     }
     
     pub fn process_midi_event(&mut self, event: &MidiEvent) {
+        if self.state == GameState::Playing {
+            self.take.record(event);
+        } else {
+            self.take.record_release(event);
+        }
+        
+        // Pedal state is tracked even when not playing so it is right on start
+        if let Some((pedal, down)) = event.event_type.pedal() {
+            self.set_pedal(pedal, down);
+            return;
+        }
+        
         if self.state != GameState::Playing {
             return;
         }
         
         match event.event_type {
-            EventType::NoteOn => {
-                self.pressed_keys.insert(event.note);
-                self.check_current_note(event.note);
+            EventType::NoteOn { note, .. } => {
+                self.pressed_keys.insert(note);
+                self.sustained_keys.remove(&note);
+                match self.mode {
+                    PracticeMode::NoteByNote => self.check_current_step(note, event.timestamp),
+                    PracticeMode::Timed { .. } => self.judge_timed_note(note, event.timestamp),
+                }
+            }
+            EventType::NoteOff { note, .. } => {
+                self.pressed_keys.remove(&note);
+                if self.pedals.sustain {
+                    self.sustained_keys.insert(note);
+                }
+                if self.awaiting_release && self.pressed_keys.is_empty() {
+                    self.advance_step();
+                }
             }
-            EventType::NoteOff => {
-                self.pressed_keys.remove(&event.note);
+            _ => {}
+        }
+    }
+    
+    fn set_pedal(&mut self, pedal: Pedal, down: bool) {
+        match pedal {
+            Pedal::Sustain => {
+                self.pedals.sustain = down;
+                if !down {
+                    self.sustained_keys.clear();
+                }
+            }
+            Pedal::Sostenuto => {
+                // Sostenuto only holds the keys that are down at the moment it is pressed
+                if down && !self.pedals.sostenuto {
+                    self.sostenuto_keys = self.pressed_keys.clone();
+                } else if !down {
+                    self.sostenuto_keys.clear();
+                }
+                self.pedals.sostenuto = down;
             }
+            Pedal::Soft => self.pedals.soft = down,
         }
+        
+        self.observe_pedal();
     }
     
-    fn check_current_note(&mut self, pressed_note: u8) {
-        if self.current_position >= self.current_notes.len() {
+    // Records that the sustain pedal was down around the current step. Pedalling
+    // is usually changed just after striking the next chord, so the previous step
+    // is credited too.
+    fn observe_pedal(&mut self) {
+        if !self.pedals.sustain || self.current_position >= self.chord_steps.len() {
             return;
         }
         
-        let current_note = &mut self.current_notes[self.current_position];
+        self.step_pedaled[self.current_position] = true;
+        if self.current_position > 0 {
+            self.step_pedaled[self.current_position - 1] = true;
+        }
+    }
+    
+    // A released key keeps sounding while a pedal holds it
+    fn is_key_sounding(&self, pitch: u8) -> bool {
+        self.pressed_keys.contains(&pitch)
+            || self.sustained_keys.contains(&pitch)
+            || self.sostenuto_keys.contains(&pitch)
+    }
+    
+    fn check_current_step(&mut self, pressed_note: u8, timestamp: u64) {
+        if self.current_position >= self.chord_steps.len() || self.awaiting_release {
+            return;
+        }
+        
+        let step = &self.chord_steps[self.current_position];
+        
+        if !step.iter().any(|&i| self.current_notes[i].pitch == pressed_note) {
+            // Wrong key: flag the chord members that are still missing
+            for &i in step {
+                if !self.satisfied_keys.contains_key(&self.current_notes[i].pitch) {
+                    self.current_notes[i].is_correct = Some(false);
+                }
+            }
+            self.feedback.push(NoteFeedback::Incorrect);
+            return;
+        }
+        
+        self.satisfied_keys.insert(pressed_note, timestamp);
+        
+        // A member counts if it is still sounding, or was released but struck within
+        // the rolled-chord tolerance of this key press
+        let complete = step.iter().all(|&i| {
+            let pitch = self.current_notes[i].pitch;
+            match self.satisfied_keys.get(&pitch) {
+                Some(&pressed_at) => {
+                    self.is_key_sounding(pitch)
+                        || timestamp.saturating_sub(pressed_at) <= self.chord_tolerance_ms.saturating_mul(1000)
+                }
+                None => false,
+            }
+        });
+        
+        if complete {
+            for &i in step {
+                self.current_notes[i].is_correct = Some(true);
+            }
+            self.correct_notes += step.len() as u32;
+            self.feedback.push(NoteFeedback::Correct);
+            
+            if self.auto_advance || self.pressed_keys.is_empty() {
+                self.advance_step();
+            } else {
+                self.awaiting_release = true;
+            }
+        }
+    }
+    
+    fn advance_step(&mut self) {
+        self.current_position += 1;
+        self.satisfied_keys.clear();
+        self.awaiting_release = false;
+        self.observe_pedal();
+        self.check_completion();
+    }
+    
+    fn judge_timed_note(&mut self, pressed_note: u8, timestamp: u64) {
+        let Some(clock) = &self.beat_clock else {
+            return;
+        };
+        
+        // Closest unjudged note of this pitch whose hit window contains the key press
+        let candidate = self.current_notes.iter()
+            .enumerate()
+            .filter(|(i, note)| note.pitch == pressed_note && self.note_timings[*i].is_none())
+            .map(|(i, note)| (i, clock.deviation_ms(note.position, timestamp)))
+            .filter(|(_, deviation)| deviation.abs() <= self.timing_windows.hit_ms)
+            .min_by(|a, b| a.1.abs().total_cmp(&b.1.abs()));
         
-        if current_note.pitch == pressed_note {
-            current_note.is_correct = Some(true);
+        let Some((index, deviation_ms)) = candidate else {
+            log::debug!("Stray key press {} outside any timing window", pressed_note);
+            self.stray_keys += 1;
+            self.feedback.push(NoteFeedback::Incorrect);
+            return;
+        };
+        
+        if let Some(judgement) = self.timing_windows.judge(deviation_ms) {
+            self.note_timings[index] = Some(NoteTiming { judgement, deviation_ms });
+            self.current_notes[index].is_correct = Some(true);
             self.correct_notes += 1;
+            self.feedback.push(NoteFeedback::Correct);
+            self.advance_judged_steps();
+        }
+    }
+    
+    fn advance_judged_steps(&mut self) {
+        while self.current_position < self.chord_steps.len()
+            && self.chord_steps[self.current_position].iter().all(|&i| self.note_timings[i].is_some())
+        {
             self.current_position += 1;
-        } else {
-            current_note.is_correct = Some(false);
         }
+        self.observe_pedal();
+        self.check_completion();
+    }
+    
+    fn check_completion(&mut self) {
+        if self.state != GameState::Playing || self.chord_steps.is_empty() || self.current_position < self.chord_steps.len() {
+            return;
+        }
+        
+        self.state = GameState::Stopped;
+        self.beat_clock = None;
+        let duration = self.attempt_clock.take()
+            .map_or(0.0, |clock| clock.running_secs(midi::current_timestamp()));
+        self.completed_attempt = Some(AttemptResult {
+            correct_notes: self.correct_notes,
+            total_notes: self.total_notes,
+            duration_secs: duration.max(0.0) as u64,
+        });
+    }
+    
+    pub fn take_feedback(&mut self) -> Vec<NoteFeedback> {
+        std::mem::take(&mut self.feedback)
+    }
+    
+    // Returns the result of a finished play-through once
+    pub fn take_completed_attempt(&mut self) -> Option<AttemptResult> {
+        self.completed_attempt.take()
+    }
+    
+    // Everything played in the latest attempt; None before anything was played
+    pub fn get_take(&self) -> Option<&MidiRecording> {
+        (!self.take.is_empty()).then_some(&self.take)
+    }
+    
+    pub fn get_pedal_state(&self) -> PedalState {
+        self.pedals
+    }
+    
+    // One entry per pedal marking: None until every step inside it has been
+    // played, then whether the pedal was down for all of them
+    pub fn get_pedal_results(&self) -> Vec<Option<bool>> {
+        self.pedal_markings.iter()
+            .map(|marking| {
+                let steps: Vec<usize> = (0..self.chord_steps.len())
+                    .filter(|&step| marking.contains(self.current_notes[self.chord_steps[step][0]].position))
+                    .collect();
+                
+                if steps.is_empty() || steps.iter().any(|&step| step >= self.current_position) {
+                    None
+                } else {
+                    Some(steps.iter().all(|&step| self.step_pedaled[step]))
+                }
+            })
+            .collect()
+    }
+    
+    pub fn get_note_timings(&self) -> &[Option<NoteTiming>] {
+        &self.note_timings
+    }
+    
+    // Current beat clock position in timed mode (negative during the count-in)
+    pub fn get_playhead_beats(&self) -> Option<f32> {
+        self.beat_clock.as_ref().map(|clock| clock.beat_at(midi::current_timestamp()))
+    }
+    
+    // Beat the score cursor marks: the beat clock in timed mode, otherwise the
+    // chord waiting to be played. None when there is nothing left to play.
+    pub fn get_cursor_beat(&self) -> Option<f32> {
+        if let Some(beat) = self.get_playhead_beats() {
+            return Some(beat.max(0.0));
+        }
+        let step = self.chord_steps.get(self.current_position)?;
+        Some(self.current_notes[step[0]].position)
+    }
+    
+    pub fn get_signatures(&self) -> &SignatureTimeline {
+        &self.signatures
     }
     
     pub fn get_current_notes(&self) -> &[Note] {
         &self.current_notes
     }
     
+    pub fn get_current_rests(&self) -> &[WrittenRest] {
+        &self.current_rests
+    }
+    
     pub fn get_progress(&self) -> f32 {
-        if self.total_notes == 0 {
+        if self.chord_steps.is_empty() {
             return 0.0;
         }
-        self.current_position as f32 / self.total_notes as f32
+        self.current_position as f32 / self.chord_steps.len() as f32
     }
     
     pub fn get_score(&self) -> (u32, u32) {
         (self.correct_notes, self.total_notes)
     }
+    
+    pub fn get_stray_keys(&self) -> u32 {
+        self.stray_keys
+    }
+    
+    // Correct and total notes for one hand; None when the song has no notes for it
+    pub fn get_hand_score(&self, hand: Hand) -> Option<(u32, u32)> {
+        let notes: Vec<&Note> = self.current_notes.iter().filter(|note| note.hand == Some(hand)).collect();
+        if notes.is_empty() {
+            return None;
+        }
+        let correct = notes.iter().filter(|note| note.is_correct == Some(true)).count();
+        Some((correct as u32, notes.len() as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn engine_for(notes: Vec<Note>) -> GameEngine {
+        let song = Song::from_notes("test".to_string(), "Test".to_string(), String::new(), None, notes);
+        let mut engine = GameEngine::new();
+        engine.load_song(&song);
+        engine
+    }
+    
+    fn event(event_type: EventType, timestamp: u64) -> MidiEvent {
+        MidiEvent { channel: 0, timestamp, event_type }
+    }
+    
+    fn press(engine: &mut GameEngine, note: u8, timestamp: u64) {
+        engine.process_midi_event(&event(EventType::NoteOn { note, velocity: 64 }, timestamp));
+    }
+    
+    fn release(engine: &mut GameEngine, note: u8, timestamp: u64) {
+        engine.process_midi_event(&event(EventType::NoteOff { note, velocity: 0 }, timestamp));
+    }
+    
+    fn note_by_note(notes: Vec<Note>) -> GameEngine {
+        let mut engine = engine_for(notes);
+        engine.start_practice();
+        engine
+    }
+    
+    #[test]
+    fn notes_at_one_position_form_a_chord_step() {
+        let notes = vec![
+            Note::with_duration(62, 1.0, 1.0),
+            Note::with_duration(64, 0.0, 1.0),
+            Note::with_duration(60, 0.0, 1.0),
+            Note::with_duration(67, 0.0005, 1.0),
+        ];
+        assert_eq!(GameEngine::build_chord_steps(&notes), vec![vec![1, 2, 3], vec![0]]);
+        
+        let mut engine = note_by_note(notes);
+        press(&mut engine, 60, 0);
+        press(&mut engine, 64, 10_000);
+        assert!(engine.take_feedback().is_empty());
+        assert_eq!(engine.get_cursor_beat(), Some(0.0));
+        press(&mut engine, 67, 20_000);
+        assert_eq!(engine.take_feedback(), vec![NoteFeedback::Correct]);
+        assert_eq!(engine.get_cursor_beat(), Some(1.0));
+        assert_eq!(engine.get_score(), (3, 4));
+    }
+    
+    #[test]
+    fn wrong_keys_flag_the_missing_chord_members() {
+        let mut engine = note_by_note(vec![Note::with_duration(60, 0.0, 1.0), Note::with_duration(64, 0.0, 1.0)]);
+        press(&mut engine, 60, 0);
+        press(&mut engine, 61, 10_000);
+        assert_eq!(engine.take_feedback(), vec![NoteFeedback::Incorrect]);
+        let results: Vec<Option<bool>> = engine.get_current_notes().iter().map(|note| note.is_correct).collect();
+        assert_eq!(results, vec![None, Some(false)]);
+        assert_eq!(engine.get_progress(), 0.0);
+    }
+    
+    #[test]
+    fn rolled_chords_count_released_keys_within_the_tolerance() {
+        let chord = || vec![Note::with_duration(60, 0.0, 1.0), Note::with_duration(64, 0.0, 1.0), Note::with_duration(67, 1.0, 1.0)];
+        
+        // Released 100 ms after striking, second key 200 ms after the first
+        let mut engine = note_by_note(chord());
+        engine.set_chord_tolerance(250);
+        press(&mut engine, 60, 1_000_000);
+        release(&mut engine, 60, 1_100_000);
+        press(&mut engine, 64, 1_200_000);
+        assert_eq!(engine.take_feedback(), vec![NoteFeedback::Correct]);
+        assert_eq!(engine.get_cursor_beat(), Some(1.0));
+        
+        // 300 ms is too wide a roll, so the released key has to be struck again
+        let mut engine = note_by_note(chord());
+        engine.set_chord_tolerance(250);
+        press(&mut engine, 60, 1_000_000);
+        release(&mut engine, 60, 1_100_000);
+        press(&mut engine, 64, 1_300_000);
+        assert!(engine.take_feedback().is_empty());
+        assert_eq!(engine.get_cursor_beat(), Some(0.0));
+        press(&mut engine, 60, 1_310_000);
+        assert_eq!(engine.take_feedback(), vec![NoteFeedback::Correct]);
+        assert_eq!(engine.get_cursor_beat(), Some(1.0));
+    }
+    
+    #[test]
+    fn held_chord_members_count_however_long_ago_they_were_struck() {
+        let mut engine = note_by_note(vec![Note::with_duration(60, 0.0, 1.0), Note::with_duration(64, 0.0, 1.0)]);
+        engine.set_chord_tolerance(0);
+        press(&mut engine, 60, 0);
+        press(&mut engine, 64, 5_000_000);
+        assert_eq!(engine.take_feedback(), vec![NoteFeedback::Correct]);
+        assert_eq!(engine.get_state(), GameState::Stopped);
+    }
+    
+    #[test]
+    fn without_auto_advance_a_step_waits_for_every_key_to_be_released() {
+        let mut engine = engine_for(vec![Note::with_duration(60, 0.0, 1.0), Note::with_duration(62, 1.0, 1.0)]);
+        engine.set_auto_advance(false);
+        engine.start_practice();
+        
+        press(&mut engine, 60, 0);
+        assert_eq!(engine.take_feedback(), vec![NoteFeedback::Correct]);
+        assert_eq!(engine.get_cursor_beat(), Some(0.0));
+        
+        // The next note is ignored until the chord is let go
+        press(&mut engine, 62, 10_000);
+        assert!(engine.take_feedback().is_empty());
+        release(&mut engine, 62, 20_000);
+        assert_eq!(engine.get_cursor_beat(), Some(0.0));
+        release(&mut engine, 60, 30_000);
+        assert_eq!(engine.get_cursor_beat(), Some(1.0));
+        
+        press(&mut engine, 62, 40_000);
+        assert_eq!(engine.take_feedback(), vec![NoteFeedback::Correct]);
+        assert_eq!(engine.get_state(), GameState::Playing);
+        release(&mut engine, 62, 50_000);
+        assert_eq!(engine.get_state(), GameState::Stopped);
+        let attempt = engine.take_completed_attempt().unwrap();
+        assert_eq!((attempt.correct_notes, attempt.total_notes), (2, 2));
+    }
+    
+    #[test]
+    fn auto_advance_moves_on_while_keys_are_held() {
+        let mut engine = note_by_note(vec![Note::with_duration(60, 0.0, 1.0), Note::with_duration(62, 1.0, 1.0)]);
+        press(&mut engine, 60, 0);
+        assert_eq!(engine.get_cursor_beat(), Some(1.0));
+        press(&mut engine, 62, 10_000);
+        assert_eq!(engine.get_state(), GameState::Stopped);
+        assert_eq!(engine.get_progress(), 1.0);
+        assert!(engine.take_completed_attempt().is_some());
+        assert!(engine.take_completed_attempt().is_none());
+    }
+    
+    fn pedal(engine: &mut GameEngine, controller: u8, value: u8) {
+        engine.process_midi_event(&event(EventType::ControlChange { controller, value }, 0));
+    }
+    
+    // Plays three single notes under a pedal marking over the first two,
+    // with the sustain pedal at the given depth
+    fn pedal_results(depth: u8) -> (PedalState, Vec<Option<bool>>) {
+        let notes = (0..3).map(|i| Note::with_duration(60 + i, i as f32, 1.0)).collect();
+        let mut song = Song::from_notes("test".to_string(), "Test".to_string(), String::new(), None, notes);
+        song.pedal_markings = vec![PedalMarking { start: 0.0, end: 2.0 }];
+        let mut engine = GameEngine::new();
+        engine.load_song(&song);
+        engine.start_practice();
+        
+        pedal(&mut engine, midi::message::CC_SUSTAIN, depth);
+        let state = engine.get_pedal_state();
+        press(&mut engine, 60, 0);
+        assert_eq!(engine.get_pedal_results(), vec![None]);
+        press(&mut engine, 61, 100_000);
+        press(&mut engine, 62, 200_000);
+        (state, engine.get_pedal_results())
+    }
+    
+    #[test]
+    fn half_pedal_does_not_count_as_sustain() {
+        let (state, results) = pedal_results(40);
+        assert!(!state.sustain);
+        assert_eq!(results, vec![Some(false)]);
+        
+        let (state, results) = pedal_results(64);
+        assert!(state.sustain);
+        assert_eq!(results, vec![Some(true)]);
+    }
+    
+    #[test]
+    fn sostenuto_only_holds_keys_down_when_it_is_pressed() {
+        let chord = || vec![Note::with_duration(60, 0.0, 1.0), Note::with_duration(64, 0.0, 1.0), Note::with_duration(67, 1.0, 1.0)];
+        
+        let mut engine = note_by_note(chord());
+        engine.set_chord_tolerance(0);
+        press(&mut engine, 60, 0);
+        pedal(&mut engine, midi::message::CC_SOSTENUTO, 127);
+        release(&mut engine, 60, 100_000);
+        press(&mut engine, 64, 2_000_000);
+        assert_eq!(engine.take_feedback(), vec![NoteFeedback::Correct]);
+        assert!(engine.get_pedal_state().sostenuto);
+        
+        // Pressed before the key, it holds nothing
+        let mut engine = note_by_note(chord());
+        engine.set_chord_tolerance(0);
+        pedal(&mut engine, midi::message::CC_SOSTENUTO, 127);
+        press(&mut engine, 60, 0);
+        release(&mut engine, 60, 100_000);
+        press(&mut engine, 64, 2_000_000);
+        assert!(engine.take_feedback().is_empty());
+        
+        // Released, it lets go of what it held
+        let mut engine = note_by_note(chord());
+        engine.set_chord_tolerance(0);
+        press(&mut engine, 60, 0);
+        pedal(&mut engine, midi::message::CC_SOSTENUTO, 127);
+        release(&mut engine, 60, 100_000);
+        pedal(&mut engine, midi::message::CC_SOSTENUTO, 0);
+        press(&mut engine, 64, 2_000_000);
+        assert!(engine.take_feedback().is_empty());
+    }
+    
+    #[test]
+    fn timed_mode_flags_stray_key_presses() {
+        let mut engine = engine_for(vec![Note::with_duration(60, 0.0, 1.0)]);
+        engine.set_mode(PracticeMode::Timed { bpm: 120.0 });
+        engine.start_practice();
+        
+        // Beat 0 is still two seconds of count-in away
+        press(&mut engine, 60, 0);
+        assert_eq!(engine.take_feedback(), vec![NoteFeedback::Incorrect]);
+        assert_eq!(engine.get_stray_keys(), 1);
+        assert_eq!(engine.get_score(), (0, 1));
+        assert!(engine.get_note_timings()[0].is_none());
+    }
 }
\ No newline at end of file
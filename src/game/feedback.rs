@@ -8,14 +8,34 @@ pub struct FeedbackEvent {
     pub duration: Duration,
 }
 
+// Per-note outcome reported by the game engine
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoteFeedback {
+    Correct,
+    Incorrect,
+}
+
 pub struct FeedbackSystem {
     active_events: Vec<FeedbackEvent>,
+    duration: Duration,
 }
 
 impl FeedbackSystem {
     pub fn new() -> Self {
         Self {
             active_events: Vec::new(),
+            duration: Duration::from_secs(1),
+        }
+    }
+    
+    pub fn set_duration(&mut self, seconds: f32) {
+        self.duration = Duration::try_from_secs_f32(seconds.max(0.0)).unwrap_or(self.duration);
+    }
+    
+    pub fn add_feedback(&mut self, feedback: NoteFeedback) {
+        match feedback {
+            NoteFeedback::Correct => self.add_correct_note_feedback(),
+            NoteFeedback::Incorrect => self.add_incorrect_note_feedback(),
         }
     }
     
@@ -24,7 +44,7 @@ impl FeedbackSystem {
             message: "Correct!".to_string(),
             color: egui::Color32::from_rgb(0, 150, 0),
             timestamp: Instant::now(),
-            duration: Duration::from_secs(1),
+            duration: self.duration,
         });
     }
     
@@ -33,7 +53,7 @@ impl FeedbackSystem {
             message: "Try again".to_string(),
             color: egui::Color32::from_rgb(200, 0, 0),
             timestamp: Instant::now(),
-            duration: Duration::from_secs(1),
+            duration: self.duration,
         });
     }
     
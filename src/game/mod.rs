@@ -1,7 +1,9 @@
 pub mod engine;
 pub mod feedback;
 pub mod progress;
+pub mod rhythm;
 
-pub use engine::GameEngine;
+pub use engine::{GameEngine, GameState, PracticeMode};
 pub use feedback::FeedbackSystem;
-pub use progress::ProgressTracker;
\ No newline at end of file
+pub use progress::ProgressTracker;
+pub use rhythm::TimingJudgement;
\ No newline at end of file
@@ -1,7 +1,19 @@
+use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::storage;
+
+// Bump when the on-disk layout changes and add a step to `migrate`. Fields that
+// are merely added only need #[serde(default)].
+const PROGRESS_SCHEMA_VERSION: u32 = 1;
+const PROGRESS_FILE_NAME: &str = "progress.json";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SongProgress {
     pub song_id: String,
     pub completion_percentage: f32,
@@ -10,7 +22,8 @@ pub struct SongProgress {
     pub last_played: u64, // timestamp
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PlayerStats {
     pub total_notes_played: u32,
     pub correct_notes: u32,
@@ -18,6 +31,16 @@ pub struct PlayerStats {
     pub songs_completed: u32,
 }
 
+// On-disk representation of the tracker
+#[derive(Serialize, Deserialize)]
+struct ProgressFile {
+    version: u32,
+    #[serde(default)]
+    song_progress: HashMap<String, SongProgress>,
+    #[serde(default)]
+    player_stats: PlayerStats,
+}
+
 pub struct ProgressTracker {
     song_progress: HashMap<String, SongProgress>,
     player_stats: PlayerStats,
@@ -27,13 +50,70 @@ impl ProgressTracker {
     pub fn new() -> Self {
         Self {
             song_progress: HashMap::new(),
-            player_stats: PlayerStats {
-                total_notes_played: 0,
-                correct_notes: 0,
-                total_practice_time: 0,
-                songs_completed: 0,
-            },
+            player_stats: PlayerStats::default(),
+        }
+    }
+    
+    pub fn default_path() -> Option<PathBuf> {
+        storage::data_dir().map(|dir| dir.join(PROGRESS_FILE_NAME))
+    }
+    
+    // A missing file is a fresh start; anything unreadable is an error so the
+    // caller can avoid overwriting it
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::new());
         }
+        
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let value: Value = serde_json::from_str(&contents)
+            .with_context(|| format!("Malformed progress file {}", path.display()))?;
+        let file: ProgressFile = serde_json::from_value(Self::migrate(value)?)
+            .with_context(|| format!("Invalid progress file {}", path.display()))?;
+        
+        Ok(Self {
+            song_progress: file.song_progress,
+            player_stats: file.player_stats,
+        })
+    }
+    
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = ProgressFile {
+            version: PROGRESS_SCHEMA_VERSION,
+            song_progress: self.song_progress.clone(),
+            player_stats: self.player_stats.clone(),
+        };
+        let json = serde_json::to_string_pretty(&file)?;
+        storage::write_atomic(path, json.as_bytes())
+    }
+    
+    // Upgrades older layouts one version at a time
+    fn migrate(mut value: Value) -> Result<Value> {
+        let mut version = value.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+        
+        if version > PROGRESS_SCHEMA_VERSION {
+            bail!(
+                "Progress file version {} is newer than supported version {}",
+                version,
+                PROGRESS_SCHEMA_VERSION
+            );
+        }
+        
+        while version < PROGRESS_SCHEMA_VERSION {
+            value = match version {
+                // Unversioned files have the same layout, just without the marker
+                0 => value,
+                _ => unreachable!(),
+            };
+            version += 1;
+            
+            if let Some(object) = value.as_object_mut() {
+                object.insert("version".to_string(), Value::from(version));
+            }
+        }
+        
+        Ok(value)
     }
     
     pub fn update_song_progress(&mut self, song_id: String, correct: u32, total: u32) {
@@ -42,10 +122,7 @@ impl ProgressTracker {
         
         let progress = self.song_progress.entry(song_id.clone()).or_insert(SongProgress {
             song_id: song_id.clone(),
-            completion_percentage: 0.0,
-            best_accuracy: 0.0,
-            attempts: 0,
-            last_played: 0,
+            ..SongProgress::default()
         });
         
         progress.completion_percentage = completion.max(progress.completion_percentage);
@@ -65,6 +142,10 @@ impl ProgressTracker {
         }
     }
     
+    pub fn add_practice_time(&mut self, seconds: u64) {
+        self.player_stats.total_practice_time += seconds;
+    }
+    
     pub fn get_song_progress(&self, song_id: &str) -> Option<&SongProgress> {
         self.song_progress.get(song_id)
     }
@@ -80,4 +161,80 @@ impl ProgressTracker {
             0.0
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    // A progress file path of its own for each test
+    fn scratch_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("piano-progress-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(PROGRESS_FILE_NAME)
+    }
+    
+    fn load_json(name: &str, value: Value) -> Result<ProgressTracker> {
+        let path = scratch_file(name);
+        fs::write(&path, value.to_string()).unwrap();
+        let tracker = ProgressTracker::load(&path);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        tracker
+    }
+    
+    #[test]
+    fn saved_progress_loads_back() {
+        let mut tracker = ProgressTracker::new();
+        tracker.update_song_progress("twinkle".to_string(), 7, 14);
+        tracker.update_song_progress("twinkle".to_string(), 14, 14);
+        tracker.add_practice_time(90);
+        
+        let path = scratch_file("round-trip");
+        tracker.save(&path).unwrap();
+        let saved: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let loaded = ProgressTracker::load(&path).unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        
+        assert_eq!(saved["version"], json!(PROGRESS_SCHEMA_VERSION));
+        let progress = loaded.get_song_progress("twinkle").unwrap();
+        assert_eq!((progress.attempts, progress.best_accuracy, progress.completion_percentage), (2, 1.0, 100.0));
+        let stats = loaded.get_player_stats();
+        assert_eq!((stats.total_notes_played, stats.correct_notes, stats.total_practice_time, stats.songs_completed), (28, 21, 90, 1));
+    }
+    
+    #[test]
+    fn missing_files_start_fresh() {
+        let path = scratch_file("missing");
+        let tracker = ProgressTracker::load(&path).unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert_eq!(tracker.get_player_stats().total_notes_played, 0);
+    }
+    
+    #[test]
+    fn unversioned_and_version_zero_files_are_upgraded() {
+        let contents = json!({
+            "song_progress": { "scale": { "song_id": "scale", "attempts": 3, "best_accuracy": 0.5 } },
+            "player_stats": { "correct_notes": 12, "total_notes_played": 24 },
+        });
+        let unversioned = load_json("unversioned", contents.clone()).unwrap();
+        assert_eq!(unversioned.get_song_progress("scale").unwrap().attempts, 3);
+        assert_eq!(unversioned.get_overall_accuracy(), 0.5);
+        
+        let mut version_zero = contents;
+        version_zero["version"] = json!(0);
+        let version_zero = load_json("version-zero", version_zero).unwrap();
+        assert_eq!(version_zero.get_song_progress("scale").unwrap().best_accuracy, 0.5);
+        
+        let migrated = ProgressTracker::migrate(json!({})).unwrap();
+        assert_eq!(migrated, json!({ "version": PROGRESS_SCHEMA_VERSION }));
+    }
+    
+    #[test]
+    fn newer_versions_are_refused() {
+        let error = load_json("future", json!({ "version": PROGRESS_SCHEMA_VERSION + 1 })).err().unwrap();
+        assert!(format!("{:#}", error).contains("newer than supported"), "{:#}", error);
+        
+        assert!(load_json("malformed", json!("not progress")).is_err());
+    }
 }
\ No newline at end of file
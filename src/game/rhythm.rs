@@ -0,0 +1,193 @@
+use crate::music::TempoMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimingJudgement {
+    Perfect,
+    Good,
+    Early,
+    Late,
+    Miss,
+}
+
+impl TimingJudgement {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimingJudgement::Perfect => "Perfect",
+            TimingJudgement::Good => "Good",
+            TimingJudgement::Early => "Early",
+            TimingJudgement::Late => "Late",
+            TimingJudgement::Miss => "Miss",
+        }
+    }
+    
+    pub fn color(&self) -> egui::Color32 {
+        match self {
+            TimingJudgement::Perfect => egui::Color32::from_rgb(0, 150, 0),
+            TimingJudgement::Good => egui::Color32::from_rgb(100, 170, 0),
+            TimingJudgement::Early | TimingJudgement::Late => egui::Color32::from_rgb(255, 165, 0),
+            TimingJudgement::Miss => egui::Color32::from_rgb(200, 0, 0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NoteTiming {
+    pub judgement: TimingJudgement,
+    pub deviation_ms: f32, // Negative = played early, positive = played late
+}
+
+// Half-widths (in ms) of the windows around each expected onset
+#[derive(Debug, Clone, Copy)]
+pub struct TimingWindows {
+    pub perfect_ms: f32,
+    pub good_ms: f32,
+    pub hit_ms: f32, // Outside this a key press doesn't belong to the note at all
+}
+
+impl Default for TimingWindows {
+    fn default() -> Self {
+        Self {
+            perfect_ms: 35.0,
+            good_ms: 80.0,
+            hit_ms: 160.0,
+        }
+    }
+}
+
+impl TimingWindows {
+    pub fn judge(&self, deviation_ms: f32) -> Option<TimingJudgement> {
+        let distance = deviation_ms.abs();
+        
+        if distance <= self.perfect_ms {
+            Some(TimingJudgement::Perfect)
+        } else if distance <= self.good_ms {
+            Some(TimingJudgement::Good)
+        } else if distance <= self.hit_ms {
+            if deviation_ms < 0.0 {
+                Some(TimingJudgement::Early)
+            } else {
+                Some(TimingJudgement::Late)
+            }
+        } else {
+            None
+        }
+    }
+}
+
+// Maps between the MIDI event clock (microseconds) and beats, following the
+// song's tempo changes
+#[derive(Debug, Clone)]
+pub struct BeatClock {
+    tempo_map: TempoMap,
+    start_time: u64, // Timestamp of beat 0
+    started_at: u64, // Timestamp the clock was started; both move on by each pause
+    paused_at: Option<u64>,
+}
+
+impl BeatClock {
+    pub fn new(tempo_map: TempoMap, now: u64, lead_in_beats: f32) -> Self {
+        let mut clock = Self {
+            tempo_map,
+            start_time: now,
+            started_at: now,
+            paused_at: None,
+        };
+        clock.start_time = now + (-clock.beats_to_ms(-lead_in_beats) * 1000.0) as u64;
+        clock
+    }
+    
+    // Time from beat 0 to the given beat
+    pub fn beats_to_ms(&self, beats: f32) -> f32 {
+        self.tempo_map.beats_to_seconds(beats) * 1000.0
+    }
+    
+    // Beat position at the given timestamp; negative during the lead-in
+    pub fn beat_at(&self, time: u64) -> f32 {
+        let time = self.paused_at.unwrap_or(time);
+        self.tempo_map.seconds_to_beats(Self::elapsed_ms(self.start_time, time) / 1000.0)
+    }
+    
+    // Signed distance (ms) between a timestamp and the onset of the given beat
+    pub fn deviation_ms(&self, beat: f32, time: u64) -> f32 {
+        Self::elapsed_ms(self.start_time, time) - self.beats_to_ms(beat)
+    }
+    
+    // Seconds the clock has been running, leaving out the time it was paused
+    pub fn running_secs(&self, now: u64) -> f32 {
+        Self::elapsed_ms(self.started_at, self.paused_at.unwrap_or(now)) / 1000.0
+    }
+    
+    fn elapsed_ms(from: u64, to: u64) -> f32 {
+        ((to as f64 - from as f64) / 1000.0) as f32
+    }
+    
+    pub fn pause(&mut self, now: u64) {
+        if self.paused_at.is_none() {
+            self.paused_at = Some(now);
+        }
+    }
+    
+    pub fn resume(&mut self, now: u64) {
+        if let Some(paused_at) = self.paused_at.take() {
+            let paused = now.saturating_sub(paused_at);
+            self.start_time += paused;
+            self.started_at += paused;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn judgements_include_their_window_edges() {
+        let windows = TimingWindows::default();
+        assert_eq!(windows.judge(0.0), Some(TimingJudgement::Perfect));
+        assert_eq!(windows.judge(35.0), Some(TimingJudgement::Perfect));
+        assert_eq!(windows.judge(-35.0), Some(TimingJudgement::Perfect));
+        assert_eq!(windows.judge(35.5), Some(TimingJudgement::Good));
+        assert_eq!(windows.judge(-80.0), Some(TimingJudgement::Good));
+        assert_eq!(windows.judge(80.5), Some(TimingJudgement::Late));
+        assert_eq!(windows.judge(-80.5), Some(TimingJudgement::Early));
+        assert_eq!(windows.judge(160.0), Some(TimingJudgement::Late));
+        assert_eq!(windows.judge(-160.0), Some(TimingJudgement::Early));
+        assert_eq!(windows.judge(160.5), None);
+        assert_eq!(windows.judge(-160.5), None);
+    }
+    
+    #[test]
+    fn lead_in_counts_up_to_beat_zero() {
+        // Half a second per beat at 120 bpm
+        let clock = BeatClock::new(TempoMap::constant(120.0), 1_000_000, 4.0);
+        assert_eq!(clock.beat_at(1_000_000), -4.0);
+        assert_eq!(clock.beat_at(3_000_000), 0.0);
+        assert_eq!(clock.deviation_ms(1.0, 3_450_000), -50.0);
+        assert_eq!(clock.deviation_ms(1.0, 3_520_000), 20.0);
+    }
+    
+    #[test]
+    fn pausing_does_not_shift_the_beat() {
+        let mut clock = BeatClock::new(TempoMap::constant(120.0), 1_000_000, 4.0);
+        clock.pause(3_000_000);
+        assert_eq!(clock.beat_at(5_000_000), 0.0);
+        // Pausing again keeps the first pause
+        clock.pause(4_000_000);
+        clock.resume(5_000_000);
+        assert_eq!(clock.beat_at(5_000_000), 0.0);
+        assert_eq!(clock.beat_at(5_500_000), 1.0);
+        assert_eq!(clock.deviation_ms(1.0, 5_500_000), 0.0);
+        // Resuming a running clock changes nothing
+        clock.resume(6_000_000);
+        assert_eq!(clock.beat_at(6_000_000), 2.0);
+    }
+    
+    #[test]
+    fn running_time_leaves_out_pauses() {
+        let mut clock = BeatClock::new(TempoMap::default(), 1_000_000, 0.0);
+        clock.pause(2_000_000);
+        assert_eq!(clock.running_secs(9_000_000), 1.0);
+        clock.resume(9_000_000);
+        assert_eq!(clock.running_secs(10_500_000), 2.5);
+    }
+}
\ No newline at end of file
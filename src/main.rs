@@ -1,15 +1,26 @@
 use eframe::egui;
 
 mod app;
+mod cli;
 mod midi;
 mod notation;
 mod game;
 mod music;
+mod storage;
 mod ui;
 
 fn main() -> Result<(), eframe::Error> {
     env_logger::init();
     
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(result) = cli::run(&args) {
+        if let Err(e) = result {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([1200.0, 800.0])
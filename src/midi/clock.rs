@@ -0,0 +1,111 @@
+use std::sync::OnceLock;
+use std::time::Instant;
+
+// Device timestamps further than this from the current estimate mean the
+// device clock was reset (e.g. the port was reopened)
+const RESYNC_THRESHOLD_US: i64 = 1_000_000;
+
+static APP_CLOCK_EPOCH: OnceLock<Instant> = OnceLock::new();
+
+// Monotonic microseconds since the application clock was first read. This is
+// the clock domain of MidiEvent::timestamp.
+pub fn app_clock_micros() -> u64 {
+    let epoch = APP_CLOCK_EPOCH.get_or_init(Instant::now);
+    epoch.elapsed().as_micros() as u64
+}
+
+// Maps the microsecond timestamps midir reports (whose origin is backend
+// specific) onto the application clock
+#[derive(Debug, Default)]
+pub struct DeviceClock {
+    offset_us: Option<i64>, // app_time - device_time
+    last_timestamp: u64,
+}
+
+impl DeviceClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn map_timestamp(&mut self, device_us: u64, latency_compensation_us: u64) -> u64 {
+        self.map_timestamp_at(device_us, latency_compensation_us, app_clock_micros())
+    }
+    
+    // The mapping for an event that arrived at `now` on the application clock
+    fn map_timestamp_at(&mut self, device_us: u64, latency_compensation_us: u64, now: u64) -> u64 {
+        // Some backends don't provide timestamps; fall back to arrival time
+        let mapped = if device_us == 0 {
+            now
+        } else {
+            // Delivery delay is always positive, so the smallest observed offset is
+            // the closest to the true one and the least affected by jitter
+            let sample = now as i64 - device_us as i64;
+            let offset = match self.offset_us {
+                Some(offset) if (sample - offset).abs() < RESYNC_THRESHOLD_US => offset.min(sample),
+                _ => sample,
+            };
+            self.offset_us = Some(offset);
+            (device_us as i64 + offset).max(0) as u64
+        };
+        
+        let compensated = mapped.saturating_sub(latency_compensation_us);
+        
+        // Never let events run backwards
+        self.last_timestamp = self.last_timestamp.max(compensated);
+        self.last_timestamp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn jitter_only_ever_lowers_the_offset() {
+        let mut clock = DeviceClock::new();
+        assert_eq!(clock.map_timestamp_at(1_000_000, 0, 10_000_000), 10_000_000);
+        // Delivered 5 ms late: keeps the device's spacing
+        assert_eq!(clock.map_timestamp_at(1_100_000, 0, 10_105_000), 10_100_000);
+        // Delivered faster than before: the smaller offset wins from now on
+        assert_eq!(clock.map_timestamp_at(1_200_000, 0, 10_198_000), 10_198_000);
+        assert_eq!(clock.map_timestamp_at(1_300_000, 0, 10_310_000), 10_298_000);
+    }
+    
+    #[test]
+    fn latency_compensation_moves_events_earlier() {
+        let mut clock = DeviceClock::new();
+        assert_eq!(clock.map_timestamp_at(1_000_000, 20_000, 10_000_000), 9_980_000);
+        assert_eq!(clock.map_timestamp_at(1_100_000, 20_000, 10_100_000), 10_080_000);
+    }
+    
+    #[test]
+    fn drift_past_the_threshold_resyncs() {
+        let mut clock = DeviceClock::new();
+        clock.map_timestamp_at(1_000_000, 0, 10_000_000);
+        // Just inside the threshold the old offset still holds
+        assert_eq!(clock.map_timestamp_at(2_000_000, 0, 11_999_000), 11_000_000);
+        // Past it the device clock is taken to have jumped
+        assert_eq!(clock.map_timestamp_at(3_000_000, 0, 14_000_000), 14_000_000);
+        assert_eq!(clock.map_timestamp_at(3_100_000, 0, 14_100_000), 14_100_000);
+    }
+    
+    #[test]
+    fn device_clock_resets_and_wraps_resync() {
+        let mut clock = DeviceClock::new();
+        clock.map_timestamp_at(u32::MAX as u64, 0, 10_000_000);
+        // The port was reopened or the device counter wrapped to zero
+        assert_eq!(clock.map_timestamp_at(500, 0, 10_100_000), 10_100_000);
+        assert_eq!(clock.map_timestamp_at(100_500, 0, 10_200_000), 10_200_000);
+    }
+    
+    #[test]
+    fn mapped_timestamps_never_run_backwards() {
+        let mut clock = DeviceClock::new();
+        assert_eq!(clock.map_timestamp_at(1_000_000, 0, 10_000_000), 10_000_000);
+        // An event stamped earlier than one already delivered
+        assert_eq!(clock.map_timestamp_at(950_000, 0, 10_010_000), 10_000_000);
+        assert_eq!(clock.map_timestamp_at(1_050_000, 50_000, 10_060_000), 10_000_000);
+        // Backends without timestamps get the arrival time
+        assert_eq!(clock.map_timestamp_at(0, 0, 10_070_000), 10_070_000);
+    }
+}
\ No newline at end of file
@@ -1,4 +1,4 @@
-use midir::{MidiInput, MidiInputPort};
+use midir::MidiInput;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct MidiDevice {
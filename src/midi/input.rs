@@ -1,25 +1,19 @@
-use midir::{MidiInput as MidirInput, MidiInputConnection, MidiInputPort};
+use midir::{MidiInput as MidirInput, MidiInputConnection};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::{SystemTime, UNIX_EPOCH};
+use super::clock::{self, DeviceClock};
+use super::message::{MidiEvent, MidiMessageParser};
 use super::MidiDevice;
 
-#[derive(Debug, Clone)]
-pub enum EventType {
-    NoteOn,
-    NoteOff,
-}
-
-#[derive(Debug, Clone)]
-pub struct MidiEvent {
-    pub note: u8,
-    pub velocity: u8,
-    pub timestamp: u64,
-    pub event_type: EventType,
+// Current time in the clock domain used for MidiEvent::timestamp
+pub fn current_timestamp() -> u64 {
+    clock::app_clock_micros()
 }
 
 pub struct MidiInput {
-    _connection: Option<MidiInputConnection<()>>,
+    _connection: Option<MidiInputConnection<(DeviceClock, MidiMessageParser)>>,
     events: Arc<Mutex<Vec<MidiEvent>>>,
+    latency_compensation_us: Arc<AtomicU64>,
     connected: bool,
     current_device: Option<MidiDevice>,
 }
@@ -29,6 +23,7 @@ impl MidiInput {
         let mut midi_input = Self {
             _connection: None,
             events,
+            latency_compensation_us: Arc::new(AtomicU64::new(0)),
             connected: false,
             current_device: None,
         };
@@ -45,6 +40,12 @@ impl MidiInput {
         self.current_device.as_ref()
     }
     
+    // Subtracted from every event timestamp; takes effect immediately, even while connected
+    pub fn set_latency_compensation(&self, milliseconds: f32) {
+        let micros = (milliseconds.max(0.0) * 1000.0) as u64;
+        self.latency_compensation_us.store(micros, Ordering::Relaxed);
+    }
+    
     pub fn connect_to_device(&mut self, device: &MidiDevice) -> Result<(), String> {
         // Disconnect current connection
         self.disconnect();
@@ -64,17 +65,23 @@ impl MidiInput {
         log::info!("Connecting to MIDI port: {}", port_name);
         
         let events = self.events.clone();
+        let latency_compensation_us = self.latency_compensation_us.clone();
         let connection = midi_in.connect(
             port,
             "piano-input",
-            move |timestamp, message, _| {
-                if let Some(event) = Self::parse_midi_message(message, timestamp) {
+            move |device_timestamp, message, (device_clock, parser)| {
+                let timestamp = device_clock.map_timestamp(
+                    device_timestamp,
+                    latency_compensation_us.load(Ordering::Relaxed),
+                );
+                let parsed = parser.parse(message, timestamp);
+                if !parsed.is_empty() {
                     if let Ok(mut events) = events.lock() {
-                        events.push(event);
+                        events.extend(parsed);
                     }
                 }
             },
-            (),
+            (DeviceClock::new(), MidiMessageParser::new()),
         );
         
         match connection {
@@ -106,35 +113,4 @@ impl MidiInput {
             let _ = self.connect_to_device(device);
         }
     }
-    
-    fn parse_midi_message(message: &[u8], _timestamp: u64) -> Option<MidiEvent> {
-        if message.len() < 3 {
-            return None;
-        }
-        
-        let status = message[0];
-        let note = message[1];
-        let velocity = message[2];
-        
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64;
-        
-        match status & 0xF0 {
-            0x90 if velocity > 0 => Some(MidiEvent {
-                note,
-                velocity,
-                timestamp,
-                event_type: EventType::NoteOn,
-            }),
-            0x80 | 0x90 => Some(MidiEvent {
-                note,
-                velocity,
-                timestamp,
-                event_type: EventType::NoteOff,
-            }),
-            _ => None,
-        }
-    }
 }
\ No newline at end of file
@@ -0,0 +1,234 @@
+// Controller numbers for the piano pedals
+pub const CC_SUSTAIN: u8 = 64;
+pub const CC_SOSTENUTO: u8 = 66;
+pub const CC_SOFT: u8 = 67;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pedal {
+    Sustain,
+    Sostenuto,
+    Soft,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventType {
+    NoteOn { note: u8, velocity: u8 },
+    NoteOff { note: u8, velocity: u8 },
+    PolyAftertouch { note: u8, pressure: u8 },
+    ControlChange { controller: u8, value: u8 },
+    ProgramChange { program: u8 },
+    ChannelAftertouch { pressure: u8 },
+    PitchBend { value: i16 }, // -8192..=8191, 0 = centered
+}
+
+impl EventType {
+    // Pedal changes are continuous controllers; values of 64 and above mean "down"
+    pub fn pedal(&self) -> Option<(Pedal, bool)> {
+        match *self {
+            EventType::ControlChange { controller, value } => {
+                let pedal = match controller {
+                    CC_SUSTAIN => Pedal::Sustain,
+                    CC_SOSTENUTO => Pedal::Sostenuto,
+                    CC_SOFT => Pedal::Soft,
+                    _ => return None,
+                };
+                Some((pedal, value >= 64))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MidiEvent {
+    pub channel: u8, // 0-15
+    pub timestamp: u64, // Microseconds on the application clock, latency compensated
+    pub event_type: EventType,
+}
+
+// Decodes raw MIDI bytes into channel-voice events. Keeps running status between
+// packets, so it must live as long as the connection it parses.
+#[derive(Debug, Default)]
+pub struct MidiMessageParser {
+    running_status: Option<u8>,
+}
+
+impl MidiMessageParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn parse(&mut self, bytes: &[u8], timestamp: u64) -> Vec<MidiEvent> {
+        let mut events = Vec::new();
+        let mut i = 0;
+        
+        while i < bytes.len() {
+            let byte = bytes[i];
+            
+            // Real-time messages may appear anywhere and leave running status alone
+            if byte >= 0xF8 {
+                i += 1;
+                continue;
+            }
+            
+            let status = if byte & 0x80 != 0 {
+                i += 1;
+                
+                if byte >= 0xF0 {
+                    // SysEx and system common messages cancel running status
+                    self.running_status = None;
+                    while i < bytes.len() && bytes[i] & 0x80 == 0 {
+                        i += 1;
+                    }
+                    continue;
+                }
+                
+                self.running_status = Some(byte);
+                byte
+            } else {
+                match self.running_status {
+                    Some(status) => status,
+                    None => {
+                        // Stray data byte with nothing to attach it to
+                        i += 1;
+                        continue;
+                    }
+                }
+            };
+            
+            // Real-time messages may also fall between the data bytes
+            let length = Self::data_length(status);
+            let mut data = [0u8; 2];
+            let mut count = 0;
+            while count < length && i < bytes.len() && (bytes[i] & 0x80 == 0 || bytes[i] >= 0xF8) {
+                if bytes[i] & 0x80 == 0 {
+                    data[count] = bytes[i];
+                    count += 1;
+                }
+                i += 1;
+            }
+            
+            // Truncated by a new status byte or the end of the packet; drop the
+            // partial message
+            if count < length {
+                continue;
+            }
+            
+            if let Some(event) = Self::decode(status, &data[..length], timestamp) {
+                events.push(event);
+            }
+        }
+        
+        events
+    }
+    
+    fn data_length(status: u8) -> usize {
+        match status & 0xF0 {
+            0xC0 | 0xD0 => 1,
+            _ => 2,
+        }
+    }
+    
+    fn decode(status: u8, data: &[u8], timestamp: u64) -> Option<MidiEvent> {
+        let channel = status & 0x0F;
+        
+        let event_type = match status & 0xF0 {
+            0x80 => EventType::NoteOff { note: data[0], velocity: data[1] },
+            0x90 if data[1] > 0 => EventType::NoteOn { note: data[0], velocity: data[1] },
+            0x90 => EventType::NoteOff { note: data[0], velocity: 0 },
+            0xA0 => EventType::PolyAftertouch { note: data[0], pressure: data[1] },
+            0xB0 => EventType::ControlChange { controller: data[0], value: data[1] },
+            0xC0 => EventType::ProgramChange { program: data[0] },
+            0xD0 => EventType::ChannelAftertouch { pressure: data[0] },
+            0xE0 => EventType::PitchBend {
+                value: (((data[1] as i16) << 7) | data[0] as i16) - 8192,
+            },
+            _ => return None,
+        };
+        
+        Some(MidiEvent {
+            channel,
+            timestamp,
+            event_type,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn types(events: &[MidiEvent]) -> Vec<(u8, EventType)> {
+        events.iter().map(|event| (event.channel, event.event_type)).collect()
+    }
+    
+    #[test]
+    fn running_status_carries_across_packets() {
+        let mut parser = MidiMessageParser::new();
+        let first = parser.parse(&[0x91, 60, 100, 64, 90], 10);
+        assert_eq!(types(&first), vec![
+            (1, EventType::NoteOn { note: 60, velocity: 100 }),
+            (1, EventType::NoteOn { note: 64, velocity: 90 }),
+        ]);
+        let second = parser.parse(&[60, 0], 20);
+        assert_eq!(types(&second), vec![(1, EventType::NoteOff { note: 60, velocity: 0 })]);
+        assert_eq!(second[0].timestamp, 20);
+    }
+    
+    #[test]
+    fn real_time_bytes_leave_running_status_alone() {
+        let mut parser = MidiMessageParser::new();
+        let events = parser.parse(&[0x90, 60, 0xF8, 100, 0xFE, 62, 0xF8, 80], 0);
+        assert_eq!(types(&events), vec![
+            (0, EventType::NoteOn { note: 60, velocity: 100 }),
+            (0, EventType::NoteOn { note: 62, velocity: 80 }),
+        ]);
+        assert!(parser.parse(&[0xF8], 0).is_empty());
+        assert_eq!(types(&parser.parse(&[64, 70], 0)), vec![(0, EventType::NoteOn { note: 64, velocity: 70 })]);
+    }
+    
+    #[test]
+    fn sysex_cancels_running_status() {
+        let mut parser = MidiMessageParser::new();
+        let events = parser.parse(&[0x90, 60, 100, 0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7, 62, 100], 0);
+        assert_eq!(types(&events), vec![(0, EventType::NoteOn { note: 60, velocity: 100 })]);
+        // Data bytes with no status to attach them to are dropped
+        assert!(parser.parse(&[62, 100], 0).is_empty());
+        assert_eq!(types(&parser.parse(&[0x80, 60, 0], 0)), vec![(0, EventType::NoteOff { note: 60, velocity: 0 })]);
+    }
+    
+    #[test]
+    fn truncated_messages_are_dropped() {
+        let mut parser = MidiMessageParser::new();
+        // A note on cut short by a control change
+        let events = parser.parse(&[0x90, 60, 0xB0, 64, 127], 0);
+        assert_eq!(types(&events), vec![(0, EventType::ControlChange { controller: 64, value: 127 })]);
+        // Cut short by the end of the packet
+        assert!(parser.parse(&[0x90, 60], 0).is_empty());
+        assert!(parser.parse(&[0x90], 0).is_empty());
+    }
+    
+    #[test]
+    fn decodes_two_byte_messages_and_pitch_bend() {
+        let mut parser = MidiMessageParser::new();
+        let events = parser.parse(&[0xC2, 5, 6, 0xD3, 40, 0xE0, 0x00, 0x40, 0x7F, 0x7F, 0x00, 0x00], 0);
+        assert_eq!(types(&events), vec![
+            (2, EventType::ProgramChange { program: 5 }),
+            (2, EventType::ProgramChange { program: 6 }),
+            (3, EventType::ChannelAftertouch { pressure: 40 }),
+            (0, EventType::PitchBend { value: 0 }),
+            (0, EventType::PitchBend { value: 8191 }),
+            (0, EventType::PitchBend { value: -8192 }),
+        ]);
+    }
+    
+    #[test]
+    fn pedals_are_down_from_half_way() {
+        let pedal = |controller, value| EventType::ControlChange { controller, value }.pedal();
+        assert_eq!(pedal(CC_SUSTAIN, 63), Some((Pedal::Sustain, false)));
+        assert_eq!(pedal(CC_SUSTAIN, 64), Some((Pedal::Sustain, true)));
+        assert_eq!(pedal(CC_SOSTENUTO, 127), Some((Pedal::Sostenuto, true)));
+        assert_eq!(pedal(CC_SOFT, 0), Some((Pedal::Soft, false)));
+        assert_eq!(pedal(1, 127), None);
+    }
+}
\ No newline at end of file
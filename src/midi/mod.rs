@@ -1,5 +1,10 @@
 pub mod input;
 pub mod device;
+pub mod clock;
+pub mod message;
+pub mod recording;
 
-pub use input::{MidiInput, MidiEvent, EventType};
-pub use device::MidiDevice;
\ No newline at end of file
+pub use input::{MidiInput, current_timestamp};
+pub use message::{MidiEvent, EventType, Pedal};
+pub use device::MidiDevice;
+pub use recording::MidiRecording;
\ No newline at end of file
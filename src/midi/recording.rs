@@ -0,0 +1,95 @@
+use std::collections::HashSet;
+use super::{EventType, MidiEvent, Pedal};
+
+// Everything played on the keyboard during one practice attempt, kept so the
+// take can be saved and opened in other software
+#[derive(Debug, Clone, Default)]
+pub struct MidiRecording {
+    started_at: u64, // Timestamp of the start of the attempt
+    events: Vec<MidiEvent>,
+    held_keys: HashSet<(u8, u8)>, // Channel and note of keys still down
+    held_pedals: HashSet<(u8, Pedal)>,
+}
+
+impl MidiRecording {
+    pub fn new(started_at: u64) -> Self {
+        Self {
+            started_at,
+            events: Vec::new(),
+            held_keys: HashSet::new(),
+            held_pedals: HashSet::new(),
+        }
+    }
+    
+    pub fn record(&mut self, event: &MidiEvent) {
+        match event.event_type {
+            EventType::NoteOn { note, .. } => {
+                self.held_keys.insert((event.channel, note));
+            }
+            EventType::NoteOff { note, .. } => {
+                self.held_keys.remove(&(event.channel, note));
+            }
+            event_type => match event_type.pedal() {
+                Some((pedal, true)) => {
+                    self.held_pedals.insert((event.channel, pedal));
+                }
+                Some((pedal, false)) => {
+                    self.held_pedals.remove(&(event.channel, pedal));
+                }
+                None => {}
+            },
+        }
+        self.events.push(event.clone());
+    }
+    
+    // After the attempt ends, keys and pedals still down are let go in the
+    // take, so it does not end with notes hanging. Anything else is ignored.
+    pub fn record_release(&mut self, event: &MidiEvent) {
+        let releases = match event.event_type {
+            EventType::NoteOff { note, .. } => self.held_keys.contains(&(event.channel, note)),
+            event_type => matches!(event_type.pedal(), Some((pedal, false)) if self.held_pedals.contains(&(event.channel, pedal))),
+        };
+        if releases {
+            self.record(event);
+        }
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+    
+    pub fn get_events(&self) -> &[MidiEvent] {
+        &self.events
+    }
+    
+    // Microseconds from the start of the attempt to the event
+    pub fn offset_of(&self, event: &MidiEvent) -> u64 {
+        event.timestamp.saturating_sub(self.started_at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn event(timestamp: u64, event_type: EventType) -> MidiEvent {
+        MidiEvent { channel: 0, timestamp, event_type }
+    }
+    
+    #[test]
+    fn releases_after_the_attempt_close_the_take() {
+        let mut take = MidiRecording::new(0);
+        take.record(&event(10, EventType::ControlChange { controller: 64, value: 127 }));
+        take.record(&event(20, EventType::NoteOn { note: 60, velocity: 80 }));
+        
+        // Only the releases of what is still held are kept
+        take.record_release(&event(30, EventType::NoteOn { note: 62, velocity: 80 }));
+        take.record_release(&event(40, EventType::NoteOff { note: 62, velocity: 0 }));
+        take.record_release(&event(50, EventType::NoteOff { note: 60, velocity: 0 }));
+        take.record_release(&event(60, EventType::ControlChange { controller: 64, value: 0 }));
+        take.record_release(&event(70, EventType::NoteOff { note: 60, velocity: 0 }));
+        
+        let timestamps: Vec<u64> = take.get_events().iter().map(|event| event.timestamp).collect();
+        assert_eq!(timestamps, vec![10, 20, 50, 60]);
+    }
+}
\ No newline at end of file
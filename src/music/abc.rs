@@ -0,0 +1,1195 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+use crate::notation::{Articulation, Hand, KeySignature, Note, SignatureTimeline, SpelledPitch, TimeSignature, Tuplet};
+use crate::notation::duration::{grid_step, quantize};
+use super::parser::{MidiChannelInfo, MidiTrackInfo, ParsedMidi, ParsedNote};
+use super::tempo::{TempoChange, TempoMap};
+
+// Reads a tune written in ABC notation into the same form as a parsed MIDI
+// file. Each voice (V:) becomes a track with a single channel, and a voice in
+// treble or bass clef is played by the right or left hand. The title (T:),
+// composer (C:), meter (M:), unit note length (L:), tempo (Q:) and key (K:)
+// are read from the header and may change in the body or in inline fields.
+// Notes keep their spelling and tuplets, ties are joined, and decorations become
+// articulations and fingerings. Grace notes, chord symbols and lyrics are
+// skipped, repeats are played once as written, and only the first tune of a
+// file is read.
+
+const STEP_LETTERS: &str = "CDEFGAB";
+// Fifths from C of each letter as a major key
+const TONIC_FIFTHS: [i32; 7] = [0, 2, 4, -1, 1, 3, 5];
+
+const POSITION_EPSILON: f32 = 0.001;
+
+// A key and the alteration it gives each letter
+type KeyAlters = (KeySignature, [i8; 7]);
+
+// State that fields can change, for the header and for each voice
+#[derive(Debug, Clone)]
+struct Settings {
+    unit: f32, // Unit note length in quarter-note beats
+    meter: TimeSignature,
+    key: KeySignature,
+    key_alters: [i8; 7], // Alteration of each letter, including explicit key accidentals
+    hand: Option<Hand>, // From the clef
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            unit: 0.5,
+            meter: TimeSignature::default(),
+            key: KeySignature::default(),
+            key_alters: [0; 7],
+            hand: None,
+        }
+    }
+}
+
+// Properties given in a V: field
+#[derive(Debug, Default)]
+struct VoiceParams {
+    name: Option<String>,
+    hand: Option<Option<Hand>>, // Set when a clef is named
+}
+
+// A notehead about to be played, with the marks written before it
+struct Head {
+    spelling: SpelledPitch,
+    length: f32, // In beats
+    articulations: Vec<Articulation>,
+    fingering: Option<u8>,
+    tie: bool,
+}
+
+// Walks the music of one voice, keeping track of the musical time
+struct VoiceReader {
+    id: String,
+    name: Option<String>,
+    settings: Settings,
+    time: f32,
+    measure_start: f32,
+    measures: usize, // Barlines passed
+    first_measure_length: f32,
+    bar_alters: HashMap<(u8, i8), i8>, // Accidentals by letter and octave, until the next barline
+    open_ties: HashMap<u8, usize>, // Index into `notes`, by pitch
+    last_notes: Vec<usize>, // Notes of the previous note or chord
+    last_element: Option<(f32, f32)>, // Start and length of the previous note, chord or rest
+    next_factor: f32, // Length change from a broken rhythm
+    tuplet: Option<(Tuplet, u32)>, // The tuplet being read and its notes left
+    articulations: Vec<Articulation>, // Decorations waiting for a note
+    fingering: Option<u8>,
+    notes: Vec<Note>,
+    time_changes: Vec<(f32, TimeSignature)>,
+    key_changes: Vec<(f32, KeySignature)>,
+}
+
+impl VoiceReader {
+    fn new(id: String, settings: Settings) -> Self {
+        Self {
+            id,
+            name: None,
+            settings,
+            time: 0.0,
+            measure_start: 0.0,
+            measures: 0,
+            first_measure_length: 0.0,
+            bar_alters: HashMap::new(),
+            open_ties: HashMap::new(),
+            last_notes: Vec::new(),
+            last_element: None,
+            next_factor: 1.0,
+            tuplet: None,
+            articulations: Vec::new(),
+            fingering: None,
+            notes: Vec::new(),
+            time_changes: Vec::new(),
+            key_changes: Vec::new(),
+        }
+    }
+    
+    fn apply(&mut self, params: VoiceParams) {
+        if params.name.is_some() {
+            self.name = params.name;
+        }
+        if let Some(hand) = params.hand {
+            self.settings.hand = hand;
+        }
+    }
+    
+    fn set_meter(&mut self, meter: TimeSignature) {
+        self.settings.meter = meter;
+        self.time_changes.push((self.time, meter));
+    }
+    
+    fn set_key(&mut self, key: KeySignature, alters: [i8; 7]) {
+        self.settings.key = key;
+        self.settings.key_alters = alters;
+        self.key_changes.push((self.time, key));
+    }
+    
+    fn decorate(&mut self, name: &str) {
+        let articulation = match name {
+            "staccato" | "." => Articulation::Staccato,
+            "staccatissimo" | "wedge" => Articulation::Staccatissimo,
+            "tenuto" => Articulation::Tenuto,
+            "accent" | "emphasis" | ">" | "L" => Articulation::Accent,
+            "marcato" | "^" => Articulation::Marcato,
+            "fermata" | "invertedfermata" | "H" => Articulation::Fermata,
+            _ => {
+                if let Some(finger) = name.parse::<u8>().ok().filter(|finger| (1..=5).contains(finger)) {
+                    self.fingering = Some(finger);
+                }
+                return;
+            }
+        };
+        if !self.articulations.contains(&articulation) {
+            self.articulations.push(articulation);
+        }
+    }
+    
+    // The pitch of a written letter: its own accidental, or one earlier in the
+    // bar on the same letter and octave, or the key's
+    fn spell(&mut self, step: u8, octave: i8, accidental: Option<i8>) -> SpelledPitch {
+        let alter = match accidental {
+            Some(alter) => {
+                self.bar_alters.insert((step, octave), alter);
+                alter
+            }
+            None => self.bar_alters.get(&(step, octave))
+                .copied()
+                .unwrap_or(self.settings.key_alters[step as usize]),
+        };
+        SpelledPitch { step, alter, octave }
+    }
+    
+    // The length factor for the next note, chord or rest, and the tuplet it
+    // belongs to
+    fn take_factor(&mut self) -> (f32, Option<Tuplet>) {
+        let mut factor = std::mem::replace(&mut self.next_factor, 1.0);
+        let tuplet = self.tuplet.map(|(tuplet, _)| tuplet);
+        if let Some((tuplet, remaining)) = &mut self.tuplet {
+            factor /= tuplet.ratio();
+            *remaining -= 1;
+            if *remaining == 0 {
+                self.tuplet = None;
+            }
+        }
+        (factor, tuplet)
+    }
+    
+    // Plays a note or chord lasting `length` beats. A note tied from one of the
+    // same pitch ending here lengthens that one instead.
+    fn play(&mut self, heads: Vec<Head>, length: f32) {
+        let (factor, tuplet) = self.take_factor();
+        let start = self.time;
+        
+        self.last_notes.clear();
+        for head in heads {
+            let Some(pitch) = head.spelling.midi_pitch() else {
+                continue;
+            };
+            let end = start + head.length * factor;
+            let index = match self.open_ties.remove(&pitch) {
+                Some(index) if (self.notes[index].end() - start).abs() < POSITION_EPSILON => {
+                    self.notes[index].duration = end - self.notes[index].position;
+                    index
+                }
+                _ => {
+                    let mut note = Note::with_duration(pitch, start, end - start);
+                    note.spelling = Some(head.spelling);
+                    note.articulations = head.articulations;
+                    note.fingering = head.fingering;
+                    note.hand = self.settings.hand;
+                    note.tuplet = tuplet;
+                    self.notes.push(note);
+                    self.notes.len() - 1
+                }
+            };
+            if head.tie {
+                self.open_ties.insert(pitch, index);
+            }
+            self.last_notes.push(index);
+        }
+        
+        self.last_element = Some((start, length * factor));
+        self.time = start + length * factor;
+    }
+    
+    fn rest(&mut self, length: f32) {
+        let (factor, _) = self.take_factor();
+        self.articulations.clear();
+        self.fingering = None;
+        self.last_notes.clear();
+        self.last_element = Some((self.time, length * factor));
+        self.time += length * factor;
+    }
+    
+    // Whole measures of rest
+    fn measure_rest(&mut self, measures: u32) {
+        self.last_notes.clear();
+        self.last_element = None;
+        for _ in 0..measures {
+            self.time += self.settings.meter.beats_per_measure();
+            self.bar();
+        }
+    }
+    
+    // Ties the notes just played to the next ones of the same pitch
+    fn tie(&mut self) {
+        for &index in &self.last_notes {
+            self.open_ties.insert(self.notes[index].pitch, index);
+        }
+    }
+    
+    // `>` lengthens the previous note by a dot and shortens the next one to
+    // match; `<` does the opposite. Each further sign doubles the dot.
+    fn broken_rhythm(&mut self, longer_first: bool, signs: u32) {
+        let short = 0.5f32.powi(signs as i32);
+        let long = 2.0 - short;
+        let (previous, next) = if longer_first { (long, short) } else { (short, long) };
+        
+        if let Some((start, length)) = self.last_element {
+            let end = start + length * previous;
+            for &index in &self.last_notes {
+                let note = &mut self.notes[index];
+                note.duration = end - note.position;
+            }
+            self.last_element = Some((start, length * previous));
+            self.time = end;
+        }
+        self.next_factor = next;
+    }
+    
+    // (p:q:r puts p notes in the time of q for the next r notes
+    fn start_tuplet(&mut self, notes: u32, time: Option<u32>, count: Option<u32>) {
+        let meter = self.settings.meter;
+        let compound = meter.numerator > 3 && meter.numerator.is_multiple_of(3);
+        let time = time.unwrap_or(match notes {
+            3 | 6 => 2,
+            2 | 4 | 8 => 3,
+            _ if compound => 3,
+            _ => 2,
+        });
+        if notes > 0 && time > 0 {
+            let tuplet = Tuplet {
+                actual: notes.min(u8::MAX as u32) as u8,
+                normal: time.min(u8::MAX as u32) as u8,
+                start: self.time,
+            };
+            self.tuplet = Some((tuplet, count.unwrap_or(notes).max(1)));
+        }
+    }
+    
+    fn bar(&mut self) {
+        let length = self.time - self.measure_start;
+        if length < POSITION_EPSILON {
+            return;
+        }
+        
+        // As with MusicXML, a short first measure is a pickup and later measures
+        // keep the meter's length unless they are overfull
+        let length = if self.measures == 0 {
+            self.first_measure_length = length;
+            length
+        } else {
+            length.max(self.settings.meter.beats_per_measure())
+        };
+        self.measures += 1;
+        self.measure_start += length;
+        self.time = self.measure_start;
+        self.bar_alters.clear();
+    }
+}
+
+// Reads characters of a music line one at a time
+struct Cursor {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Cursor {
+    fn new(text: &str) -> Self {
+        Self {
+            chars: text.chars().collect(),
+            pos: 0,
+        }
+    }
+    
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+    
+    fn peek_at(&self, ahead: usize) -> Option<char> {
+        self.chars.get(self.pos + ahead).copied()
+    }
+    
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+    
+    fn eat(&mut self, c: char) -> bool {
+        let matched = self.peek() == Some(c);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+    
+    fn number(&mut self) -> Option<u32> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect::<String>().parse().ok()
+    }
+    
+    // Text up to `end`, moving past it. Leaves the cursor alone if there is
+    // no `end` on the line.
+    fn take_until(&mut self, end: char) -> Option<String> {
+        let length = self.chars[self.pos..].iter().position(|&c| c == end)?;
+        let text = self.chars[self.pos..self.pos + length].iter().collect();
+        self.pos += length + 1;
+        Some(text)
+    }
+    
+    // A length multiplier such as 2, 3/2, / or //
+    fn length(&mut self) -> f32 {
+        let numerator = self.number().unwrap_or(1) as f32;
+        let mut denominator = 1.0;
+        while self.eat('/') {
+            denominator *= self.number().filter(|&number| number > 0).unwrap_or(2) as f32;
+        }
+        numerator / denominator
+    }
+}
+
+struct TuneReader {
+    title: Option<String>,
+    composer: Option<String>,
+    in_body: bool,
+    defaults: Settings, // From the header
+    unit_given: bool,
+    header_meter: Option<TimeSignature>,
+    header_key: Option<KeySignature>,
+    header_tempo: Option<String>, // Read once the unit note length is known
+    definitions: Vec<(String, VoiceParams)>, // Voices described in the header
+    declared: bool, // The tune names its voices
+    voices: Vec<VoiceReader>,
+    current: usize,
+    tempo_changes: Vec<TempoChange>,
+    warnings: Vec<String>,
+    overlay_warned: bool,
+}
+
+impl TuneReader {
+    fn new() -> Self {
+        Self {
+            title: None,
+            composer: None,
+            in_body: false,
+            defaults: Settings::default(),
+            unit_given: false,
+            header_meter: None,
+            header_key: None,
+            header_tempo: None,
+            definitions: Vec::new(),
+            declared: false,
+            voices: Vec::new(),
+            current: 0,
+            tempo_changes: Vec::new(),
+            warnings: Vec::new(),
+            overlay_warned: false,
+        }
+    }
+    
+    // Returns false once the tune has ended
+    fn read_line(&mut self, line: &str) -> bool {
+        let line = line.trim();
+        
+        // Tunes are separated by blank lines
+        if line.is_empty() {
+            return !self.in_body;
+        }
+        if line.starts_with('%') {
+            return true;
+        }
+        
+        let mut chars = line.chars();
+        let is_field = chars.next().is_some_and(|c| c.is_ascii_alphabetic()) && chars.next() == Some(':');
+        if is_field {
+            if line.starts_with("X:") && self.in_body {
+                return false;
+            }
+            self.read_field(line);
+        } else {
+            if !self.in_body {
+                self.start_body();
+            }
+            self.read_music(line);
+        }
+        true
+    }
+    
+    fn start_body(&mut self) {
+        self.in_body = true;
+        
+        // Without L:, the unit is an eighth, or a sixteenth in meters under 3/4
+        if !self.unit_given {
+            let meter = self.defaults.meter;
+            let short_meter = (meter.numerator as f32) / (meter.denominator.max(1) as f32) < 0.75;
+            self.defaults.unit = if short_meter { 0.25 } else { 0.5 };
+        }
+        if let Some(bpm) = self.header_tempo.take().and_then(|tempo| parse_tempo(&tempo, self.defaults.unit)) {
+            self.tempo_changes.push(TempoChange { beat: 0.0, bpm });
+        }
+        
+        for (id, params) in std::mem::take(&mut self.definitions) {
+            let mut voice = VoiceReader::new(id, self.defaults.clone());
+            voice.apply(params);
+            self.voices.push(voice);
+        }
+    }
+    
+    // Music before any V: field belongs to the first voice
+    fn voice(&mut self) -> &mut VoiceReader {
+        if self.voices.is_empty() {
+            self.voices.push(VoiceReader::new(String::new(), self.defaults.clone()));
+        }
+        &mut self.voices[self.current]
+    }
+    
+    fn read_field(&mut self, field: &str) {
+        let Some((name, value)) = field.split_once(':') else {
+            return;
+        };
+        let value = value.split('%').next().unwrap_or("").trim();
+        
+        match name.trim() {
+            "T" if self.title.is_none() && !value.is_empty() => self.title = Some(value.to_string()),
+            "C" if self.composer.is_none() && !value.is_empty() => self.composer = Some(value.to_string()),
+            "M" => {
+                let Some(meter) = parse_meter(value) else {
+                    return;
+                };
+                if self.in_body {
+                    self.voice().set_meter(meter);
+                } else {
+                    self.defaults.meter = meter;
+                    self.header_meter = Some(meter);
+                }
+            }
+            "L" => {
+                let Some(unit) = parse_fraction(value).filter(|&unit| unit > 0.0) else {
+                    return;
+                };
+                if self.in_body {
+                    self.voice().settings.unit = unit * 4.0;
+                } else {
+                    self.defaults.unit = unit * 4.0;
+                    self.unit_given = true;
+                }
+            }
+            "Q" => {
+                if self.in_body {
+                    let voice = self.voice();
+                    let (beat, unit) = (voice.time, voice.settings.unit);
+                    if let Some(bpm) = parse_tempo(value, unit) {
+                        self.tempo_changes.push(TempoChange { beat, bpm });
+                    }
+                } else {
+                    self.header_tempo = Some(value.to_string());
+                }
+            }
+            "K" => {
+                let (key, hand) = parse_key(value);
+                if self.in_body {
+                    let voice = self.voice();
+                    if let Some((key, alters)) = key {
+                        voice.set_key(key, alters);
+                    }
+                    if let Some(hand) = hand {
+                        voice.settings.hand = hand;
+                    }
+                } else {
+                    // The key ends the header
+                    if let Some((key, alters)) = key {
+                        self.defaults.key = key;
+                        self.defaults.key_alters = alters;
+                        self.header_key = Some(key);
+                    }
+                    if let Some(hand) = hand {
+                        self.defaults.hand = hand;
+                    }
+                    self.start_body();
+                }
+            }
+            "V" => {
+                let (id, params) = parse_voice(value);
+                self.declared = true;
+                if !self.in_body {
+                    match self.definitions.iter_mut().find(|(defined, _)| *defined == id) {
+                        Some((_, defined)) => {
+                            defined.name = params.name.or(defined.name.take());
+                            defined.hand = params.hand.or(defined.hand);
+                        }
+                        None => self.definitions.push((id, params)),
+                    }
+                    return;
+                }
+                
+                self.current = match self.voices.iter().position(|voice| voice.id == id) {
+                    Some(index) => index,
+                    None if self.voices.len() == 1 && self.voices[0].id.is_empty() => {
+                        self.voices[0].id = id;
+                        0
+                    }
+                    None => {
+                        self.voices.push(VoiceReader::new(id, self.defaults.clone()));
+                        self.voices.len() - 1
+                    }
+                };
+                self.voices[self.current].apply(params);
+            }
+            _ => {}
+        }
+    }
+    
+    fn read_music(&mut self, line: &str) {
+        let mut cursor = Cursor::new(line);
+        while let Some(c) = cursor.peek() {
+            match c {
+                '%' => break,
+                // Chord symbols, annotations and grace notes
+                '"' | '{' => {
+                    cursor.bump();
+                    if cursor.take_until(if c == '"' { '"' } else { '}' }).is_none() {
+                        break;
+                    }
+                }
+                '!' | '+' => {
+                    cursor.bump();
+                    if let Some(name) = cursor.take_until(c) {
+                        self.voice().decorate(&name);
+                    }
+                }
+                '.' | 'H' | 'L' => {
+                    cursor.bump();
+                    self.voice().decorate(&c.to_string());
+                }
+                '[' => match (cursor.peek_at(1), cursor.peek_at(2)) {
+                    // A numbered ending
+                    (Some(digit), _) if digit.is_ascii_digit() => {
+                        cursor.bump();
+                        cursor.number();
+                    }
+                    (Some(letter), Some(':')) if letter.is_ascii_alphabetic() => {
+                        cursor.bump();
+                        match cursor.take_until(']') {
+                            Some(field) => self.read_field(&field),
+                            None => break,
+                        }
+                    }
+                    (Some('|'), _) => {
+                        cursor.bump();
+                        self.read_barline(&mut cursor);
+                    }
+                    _ => {
+                        cursor.bump();
+                        self.read_chord(&mut cursor);
+                    }
+                },
+                '|' | ':' => self.read_barline(&mut cursor),
+                '(' => {
+                    cursor.bump();
+                    if let Some(notes) = cursor.number() {
+                        let time = if cursor.eat(':') { cursor.number() } else { None };
+                        let count = if cursor.eat(':') { cursor.number() } else { None };
+                        self.voice().start_tuplet(notes, time, count);
+                    }
+                }
+                '-' => {
+                    cursor.bump();
+                    self.voice().tie();
+                }
+                '>' | '<' => {
+                    let mut signs = 0;
+                    while cursor.eat(c) {
+                        signs += 1;
+                    }
+                    self.voice().broken_rhythm(c == '>', signs);
+                }
+                'z' | 'x' => {
+                    cursor.bump();
+                    let voice = self.voice();
+                    let length = cursor.length() * voice.settings.unit;
+                    voice.rest(length);
+                }
+                'Z' | 'X' => {
+                    cursor.bump();
+                    let measures = cursor.number().unwrap_or(1);
+                    self.voice().measure_rest(measures);
+                }
+                '&' => {
+                    if !self.overlay_warned {
+                        self.warnings.push("Voice overlays (&) are not supported and were skipped".to_string());
+                        self.overlay_warned = true;
+                    }
+                    while cursor.peek().is_some_and(|c| c != '|') {
+                        cursor.bump();
+                    }
+                }
+                _ if is_note_start(c) => {
+                    let voice = self.voice();
+                    let articulations = std::mem::take(&mut voice.articulations);
+                    let fingering = voice.fingering.take();
+                    if let Some(head) = read_head(&mut cursor, voice, articulations, fingering) {
+                        let length = head.length;
+                        voice.play(vec![head], length);
+                    }
+                }
+                _ => {
+                    cursor.bump();
+                }
+            }
+        }
+    }
+    
+    fn read_barline(&mut self, cursor: &mut Cursor) {
+        let mut previous = None;
+        while let Some(c) = cursor.peek() {
+            let part_of_bar = c == '|' || c == ':' || (c == ']' && previous == Some('|'));
+            if !part_of_bar {
+                break;
+            }
+            previous = cursor.bump();
+        }
+        // The number of an ending written on the barline
+        cursor.number();
+        self.voice().bar();
+    }
+    
+    // Reads a chord after its opening bracket. Marks before the bracket apply to
+    // every note and marks inside to the note they precede.
+    fn read_chord(&mut self, cursor: &mut Cursor) {
+        let voice = self.voice();
+        let chord_articulations = std::mem::take(&mut voice.articulations);
+        let chord_fingering = voice.fingering.take();
+        
+        let mut heads: Vec<Head> = Vec::new();
+        while let Some(c) = cursor.peek() {
+            match c {
+                ']' => {
+                    cursor.bump();
+                    break;
+                }
+                '!' | '+' => {
+                    cursor.bump();
+                    if let Some(name) = cursor.take_until(c) {
+                        voice.decorate(&name);
+                    }
+                }
+                '-' => {
+                    cursor.bump();
+                    if let Some(head) = heads.last_mut() {
+                        head.tie = true;
+                    }
+                }
+                _ if is_note_start(c) => {
+                    let mut articulations = chord_articulations.clone();
+                    for articulation in std::mem::take(&mut voice.articulations) {
+                        if !articulations.contains(&articulation) {
+                            articulations.push(articulation);
+                        }
+                    }
+                    let fingering = voice.fingering.take().or(if heads.is_empty() { chord_fingering } else { None });
+                    if let Some(head) = read_head(cursor, voice, articulations, fingering) {
+                        heads.push(head);
+                    }
+                }
+                _ => {
+                    cursor.bump();
+                }
+            }
+        }
+        
+        // A length after the chord multiplies the lengths inside; the chord lasts
+        // as long as its first note
+        let multiplier = cursor.length();
+        for head in &mut heads {
+            head.length *= multiplier;
+        }
+        if let Some(length) = heads.first().map(|head| head.length) {
+            voice.play(heads, length);
+        }
+    }
+    
+    fn finish(self) -> Result<ParsedMidi> {
+        if self.voices.iter().all(|voice| voice.notes.is_empty()) {
+            bail!("the tune has no notes");
+        }
+        let first = &self.voices[0];
+        
+        // Signatures come from the header and the first voice. A pickup is moved
+        // to the end of a full first measure, as for MusicXML.
+        let opening_meter = first.time_changes.first()
+            .filter(|(beat, _)| *beat < POSITION_EPSILON)
+            .map_or(self.defaults.meter, |(_, meter)| *meter);
+        let pickup_length = first.first_measure_length;
+        let shift = if pickup_length > POSITION_EPSILON && pickup_length < opening_meter.beats_per_measure() - POSITION_EPSILON {
+            opening_meter.beats_per_measure() - pickup_length
+        } else {
+            0.0
+        };
+        let shifted = |beat: f32| if beat < POSITION_EPSILON { beat } else { beat + shift };
+        
+        let mut signatures = SignatureTimeline::default();
+        if let Some(meter) = self.header_meter {
+            signatures.add_time_signature(0.0, meter);
+        }
+        if let Some(key) = self.header_key {
+            signatures.add_key_signature(0.0, key);
+        }
+        for &(beat, meter) in &first.time_changes {
+            signatures.add_time_signature(shifted(beat), meter);
+        }
+        for &(beat, key) in &first.key_changes {
+            signatures.add_key_signature(shifted(beat), key);
+        }
+        let tempo_map = TempoMap::from_changes(
+            self.tempo_changes.iter()
+                .map(|change| TempoChange { beat: shifted(change.beat), bpm: change.bpm })
+                .collect(),
+        );
+        
+        let mut tracks = Vec::new();
+        let mut notes = Vec::new();
+        for (index, voice) in self.voices.into_iter().enumerate() {
+            let name = voice.name.or_else(|| Some(voice.id).filter(|id| !id.is_empty()));
+            tracks.push(MidiTrackInfo {
+                index,
+                name,
+                channels: if voice.notes.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![MidiChannelInfo::notated(0, voice.notes.len())]
+                },
+                note_count: voice.notes.len(),
+            });
+            notes.extend(voice.notes.into_iter().map(|mut note| {
+                // Snapped to the thirty-second grid, as MIDI notes are, or to
+                // the tuplet's own grid
+                let tuplet = note.tuplet.map(|tuplet| Tuplet {
+                    start: quantize(tuplet.start + shift, None),
+                    ..tuplet
+                });
+                let position = quantize(note.position + shift, tuplet);
+                let end = quantize(note.end() + shift, tuplet).max(position + grid_step(tuplet));
+                note.position = position;
+                note.duration = end - position;
+                note.tuplet = tuplet;
+                if self.declared {
+                    note.voice = Some(index.min(u8::MAX as usize - 1) as u8 + 1);
+                }
+                ParsedNote {
+                    track: index,
+                    channel: 0,
+                    note,
+                }
+            }));
+        }
+        
+        Ok(ParsedMidi {
+            tracks,
+            notes,
+            rests: Vec::new(),
+            tempo_map,
+            signatures,
+            title: self.title,
+            composer: self.composer,
+            warnings: self.warnings,
+        })
+    }
+}
+
+fn is_note_start(c: char) -> bool {
+    matches!(c, '^' | '_' | '=' | 'A'..='G' | 'a'..='g')
+}
+
+// Reads an accidental, letter, octave marks and length. Uppercase letters are
+// the octave from middle C and lowercase the one above.
+fn read_head(cursor: &mut Cursor, voice: &mut VoiceReader, articulations: Vec<Articulation>, fingering: Option<u8>) -> Option<Head> {
+    let mut accidental = None;
+    loop {
+        match cursor.peek()? {
+            '^' => accidental = Some(accidental.unwrap_or(0) + 1),
+            '_' => accidental = Some(accidental.unwrap_or(0) - 1),
+            '=' => accidental = Some(0),
+            _ => break,
+        }
+        cursor.bump();
+    }
+    
+    let letter = cursor.peek().filter(char::is_ascii_alphabetic)?;
+    let step = STEP_LETTERS.find(letter.to_ascii_uppercase())? as u8;
+    cursor.bump();
+    let mut octave: i8 = if letter.is_ascii_lowercase() { 5 } else { 4 };
+    loop {
+        if cursor.eat('\'') {
+            octave += 1;
+        } else if cursor.eat(',') {
+            octave -= 1;
+        } else {
+            break;
+        }
+    }
+    
+    let length = cursor.length() * voice.settings.unit;
+    Some(Head {
+        spelling: voice.spell(step, octave, accidental),
+        length,
+        articulations,
+        fingering,
+        tie: false,
+    })
+}
+
+fn parse_fraction(text: &str) -> Option<f32> {
+    let (numerator, denominator) = text.trim().split_once('/').unwrap_or((text.trim(), "1"));
+    let numerator: f32 = numerator.trim().parse().ok()?;
+    let denominator: f32 = denominator.trim().parse().ok()?;
+    (denominator > 0.0).then(|| numerator / denominator)
+}
+
+// C is common time and C| cut time. Additive meters like 2+3/8 are kept as
+// their total.
+fn parse_meter(text: &str) -> Option<TimeSignature> {
+    match text {
+        "C" => return Some(TimeSignature::new(4, 4)),
+        "C|" => return Some(TimeSignature::new(2, 2)),
+        _ => {}
+    }
+    let (beats, beat_type) = text.split_once('/')?;
+    let beats: u32 = beats.trim_matches(|c| c == '(' || c == ')')
+        .split('+')
+        .map(|part| part.trim().parse::<u32>().ok())
+        .sum::<Option<u32>>()?;
+    let beat_type: u8 = beat_type.trim().parse().ok()?;
+    (beats > 0 && beat_type > 0).then(|| TimeSignature::new(beats.min(u8::MAX as u32) as u8, beat_type))
+}
+
+// Quarter notes per minute from a Q: field such as 1/4=120, 3/8=60, C=100 or
+// the older plain 120 (unit notes per minute). Text in quotes is ignored.
+fn parse_tempo(text: &str, unit: f32) -> Option<f32> {
+    let text: String = text.split('"').step_by(2).collect();
+    let (beat, bpm) = match text.split_once('=') {
+        Some((beat, bpm)) => {
+            let beat = beat.trim();
+            let beat = if beat.is_empty() || beat.starts_with('C') {
+                unit
+            } else {
+                beat.split_whitespace().map(parse_fraction).sum::<Option<f32>>()? * 4.0
+            };
+            (beat, bpm)
+        }
+        None => (unit, text.as_str()),
+    };
+    let bpm: f32 = bpm.trim().parse().ok()?;
+    Some(bpm * beat).filter(|bpm| *bpm > 0.0)
+}
+
+// The hand for a clef name: treble or G clefs for the right hand, bass or F
+// clefs for the left, and none for the rest
+fn hand_for_clef(name: &str) -> Option<Hand> {
+    let name = name.to_lowercase();
+    if name.starts_with("treble") || name.starts_with('g') {
+        Some(Hand::Right)
+    } else if name.starts_with("bass") || name.starts_with('f') {
+        Some(Hand::Left)
+    } else {
+        None
+    }
+}
+
+fn is_clef_name(word: &str) -> bool {
+    let word = word.to_lowercase();
+    ["treble", "bass", "alto", "tenor", "perc"].iter().any(|clef| word.starts_with(clef))
+}
+
+// Fifths added to a major key by a mode name, and whether it is minor
+fn mode_offset(mode: &str) -> Option<(i32, bool)> {
+    let mode = mode.to_lowercase();
+    if mode.is_empty() {
+        return Some((0, false));
+    }
+    if mode == "m" {
+        return Some((-3, true));
+    }
+    match mode.get(..3)? {
+        "maj" | "ion" => Some((0, false)),
+        "min" | "aeo" => Some((-3, true)),
+        "mix" => Some((-1, false)),
+        "dor" => Some((-2, false)),
+        "phr" => Some((-4, false)),
+        "lyd" => Some((1, false)),
+        "loc" => Some((-5, false)),
+        _ => None,
+    }
+}
+
+// The key of a K: field such as G, F#m, Bb dorian or none, with the alteration
+// of each letter, and the hand of any clef it names. Accidentals listed after
+// the key (^f _b) change single letters, and exp starts from none.
+fn parse_key(text: &str) -> (Option<KeyAlters>, Option<Option<Hand>>) {
+    let mut words = text.split_whitespace().peekable();
+    let mut key = None;
+    
+    if let Some(first) = words.peek().copied() {
+        let tonic = first.chars().next().and_then(|letter| STEP_LETTERS.find(letter));
+        if let Some(step) = tonic {
+            words.next();
+            let rest = &first[1..];
+            let (sharpen, mode) = match rest.chars().next() {
+                Some('#') => (7, &rest[1..]),
+                Some('b') => (-7, &rest[1..]),
+                _ => (0, rest),
+            };
+            let mode = if mode.is_empty() {
+                words.next_if(|word| !word.is_empty() && mode_offset(word).is_some()).unwrap_or("")
+            } else {
+                mode
+            };
+            let (offset, minor) = mode_offset(mode).unwrap_or((0, false));
+            let fifths = (TONIC_FIFTHS[step] + sharpen + offset).clamp(-7, 7) as i8;
+            key = Some(KeySignature::new(fifths, minor));
+        } else if first.eq_ignore_ascii_case("none") || first.starts_with('H') {
+            // Also the highland pipe keys HP and Hp
+            words.next();
+            key = Some(KeySignature::default());
+        }
+    }
+    
+    let mut alters = [0; 7];
+    if let Some(key) = key {
+        for (step, alter) in alters.iter_mut().enumerate() {
+            *alter = key.alter_for_step(step as u8);
+        }
+    }
+    
+    let mut hand = None;
+    for word in words {
+        let lower = word.to_lowercase();
+        if let Some(clef) = lower.strip_prefix("clef=") {
+            hand = Some(hand_for_clef(clef));
+        } else if is_clef_name(&lower) {
+            hand = Some(hand_for_clef(&lower));
+        } else if lower == "exp" {
+            alters = [0; 7];
+        } else {
+            let alter = match lower.chars().next() {
+                Some('^') => 1,
+                Some('_') => -1,
+                Some('=') => 0,
+                _ => continue,
+            };
+            let doubled = lower.chars().nth(1) == lower.chars().next() && alter != 0;
+            let letter = lower.trim_start_matches(['^', '_', '=']).chars().next();
+            if let Some(step) = letter.and_then(|letter| STEP_LETTERS.find(letter.to_ascii_uppercase())) {
+                alters[step] = if doubled { alter * 2 } else { alter };
+            }
+        }
+    }
+    
+    (key.map(|key| (key, alters)), hand)
+}
+
+// Splits a field into words, keeping quoted text together without its quotes
+fn split_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            _ if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            _ => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+// A V: field: the voice id, then properties such as clef=bass and name="RH"
+fn parse_voice(text: &str) -> (String, VoiceParams) {
+    let mut words = split_words(text).into_iter();
+    let id = words.next().unwrap_or_default();
+    let mut params = VoiceParams::default();
+    
+    for word in words {
+        match word.split_once('=') {
+            Some((property, value)) => match property.to_lowercase().as_str() {
+                "name" | "nm" => params.name = Some(value.to_string()).filter(|name| !name.is_empty()),
+                "clef" => params.hand = Some(hand_for_clef(value)),
+                _ => {}
+            },
+            None if is_clef_name(&word) => params.hand = Some(hand_for_clef(&word)),
+            None => {}
+        }
+    }
+    (id, params)
+}
+
+pub struct AbcParser;
+
+impl AbcParser {
+    pub fn parse(data: &[u8]) -> Result<ParsedMidi> {
+        let text = String::from_utf8_lossy(data);
+        let mut reader = TuneReader::new();
+        for line in text.trim_start_matches('\u{FEFF}').lines() {
+            if !reader.read_line(line) {
+                break;
+            }
+        }
+        reader.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn parse(text: &str) -> ParsedMidi {
+        AbcParser::parse(text.as_bytes()).unwrap()
+    }
+    
+    // Pitch, position and duration of each note of the first voice
+    fn timing(parsed: &ParsedMidi) -> Vec<(u8, f32, f32)> {
+        parsed.notes.iter()
+            .filter(|parsed| parsed.track == 0)
+            .map(|parsed| (parsed.note.pitch, parsed.note.position, parsed.note.duration))
+            .collect()
+    }
+    
+    fn pitches(parsed: &ParsedMidi, track: usize) -> Vec<u8> {
+        parsed.notes.iter()
+            .filter(|parsed| parsed.track == track)
+            .map(|parsed| parsed.note.pitch)
+            .collect()
+    }
+    
+    #[test]
+    fn reads_the_built_in_songs() {
+        let c_scale = parse(include_str!("../../assets/songs/c_scale.abc"));
+        assert_eq!(c_scale.title.as_deref(), Some("C Major Scale"));
+        assert_eq!(c_scale.composer.as_deref(), Some("Practice"));
+        assert_eq!(pitches(&c_scale, 0), vec![60, 62, 64, 65, 67, 69, 71, 72]);
+        assert!(timing(&c_scale).iter().enumerate().all(|(i, &(_, position, duration))| position == i as f32 && duration == 1.0));
+        
+        let twinkle = parse(include_str!("../../assets/songs/twinkle.abc"));
+        assert_eq!(twinkle.title.as_deref(), Some("Twinkle Twinkle Little Star"));
+        assert_eq!(pitches(&twinkle, 0), vec![60, 60, 67, 67, 69, 69, 67, 65, 65, 64, 64, 62, 62, 60]);
+        assert_eq!(timing(&twinkle)[6], (67, 6.0, 2.0));
+        assert_eq!(timing(&twinkle).last(), Some(&(60, 14.0, 2.0)));
+        
+        let mary_lamb = parse(include_str!("../../assets/songs/mary_lamb.abc"));
+        assert_eq!(mary_lamb.title.as_deref(), Some("Mary Had a Little Lamb"));
+        assert_eq!(pitches(&mary_lamb, 0), vec![64, 62, 60, 62, 64, 64, 64, 62, 62, 62, 64, 67, 67]);
+        assert_eq!(timing(&mary_lamb).last(), Some(&(67, 14.0, 2.0)));
+        
+        // Each voice is a track played by the hand of its clef, and z4 rests
+        // keep the left hand in step
+        let ledger_lines = parse(include_str!("../../assets/songs/ledger_lines.abc"));
+        let names: Vec<Option<&str>> = ledger_lines.tracks.iter().map(|track| track.name.as_deref()).collect();
+        assert_eq!(names, vec![Some("Right hand"), Some("Left hand")]);
+        assert_eq!(pitches(&ledger_lines, 0), vec![72, 74, 76, 77, 79, 81, 83, 84, 64, 65, 67, 69, 71, 60, 62]);
+        assert_eq!(pitches(&ledger_lines, 1), vec![59, 57, 55, 53, 52, 50, 48, 47, 45]);
+        let left_start = ledger_lines.notes.iter().find(|parsed| parsed.track == 1).unwrap();
+        assert_eq!((left_start.note.position, left_start.note.hand), (15.0, Some(Hand::Left)));
+        assert!(ledger_lines.notes.iter().filter(|parsed| parsed.track == 0).all(|parsed| parsed.note.hand == Some(Hand::Right)));
+    }
+    
+    #[test]
+    fn unit_length_defaults_from_the_meter() {
+        // An eighth without L:, or a sixteenth in meters under 3/4
+        let common = parse("X:1\nK:C\nC D z6|\n");
+        assert_eq!(timing(&common), vec![(60, 0.0, 0.5), (62, 0.5, 0.5)]);
+        let short = parse("X:1\nM:2/4\nK:C\nC D z6|\n");
+        assert_eq!(timing(&short), vec![(60, 0.0, 0.25), (62, 0.25, 0.25)]);
+        let given = parse("X:1\nM:2/4\nL:1/4\nK:C\nC D|\n");
+        assert_eq!(timing(&given), vec![(60, 0.0, 1.0), (62, 1.0, 1.0)]);
+    }
+    
+    #[test]
+    fn meter_defaults_to_common_time() {
+        let measures = |parsed: &ParsedMidi| parsed.signatures.measures(8.0);
+        assert_eq!(measures(&parse("X:1\nL:1/4\nK:C\nC4|C4|\n"))[0].time_signature, TimeSignature::new(4, 4));
+        assert_eq!(measures(&parse("X:1\nM:C|\nL:1/4\nK:C\nC2|C2|\n"))[0].time_signature, TimeSignature::new(2, 2));
+        assert_eq!(measures(&parse("X:1\nM:6/8\nL:1/8\nK:C\nC6|C6|\n"))[0].time_signature, TimeSignature::new(6, 8));
+    }
+    
+    #[test]
+    fn keys_and_modes_set_the_signature_and_spelling() {
+        let key = |field: &str| {
+            let parsed = parse(&format!("X:1\nL:1/4\nK:{}\nF B|\n", field));
+            (parsed.signatures.measures(1.0)[0].key_signature, pitches(&parsed, 0))
+        };
+        assert_eq!(key("D"), (KeySignature::new(2, false), vec![66, 71]));
+        assert_eq!(key("Am"), (KeySignature::new(0, true), vec![65, 71]));
+        assert_eq!(key("E minor"), (KeySignature::new(1, true), vec![66, 71]));
+        assert_eq!(key("D dorian"), (KeySignature::new(0, false), vec![65, 71]));
+        assert_eq!(key("G mix"), (KeySignature::new(0, false), vec![65, 71]));
+        assert_eq!(key("Bb"), (KeySignature::new(-2, false), vec![65, 70]));
+        assert_eq!(key("F#m"), (KeySignature::new(3, true), vec![66, 71]));
+        assert_eq!(key("C exp _b"), (KeySignature::new(0, false), vec![65, 70]));
+        assert_eq!(key("none"), (KeySignature::new(0, false), vec![65, 71]));
+    }
+    
+    #[test]
+    fn triplets_keep_their_exact_positions() {
+        let parsed = parse("X:1\nL:1/8\nK:C\n(3CDE F2 z4|\n");
+        let third = 1.0 / 3.0;
+        let expected = [(60, 0.0, third), (62, third, third), (64, 2.0 * third, third), (65, 1.0, 1.0)];
+        for (parsed, (pitch, position, duration)) in parsed.notes.iter().zip(expected) {
+            assert_eq!(parsed.note.pitch, pitch);
+            assert!((parsed.note.position - position).abs() < 1e-4, "{} at {}", pitch, parsed.note.position);
+            assert!((parsed.note.duration - duration).abs() < 1e-4);
+        }
+        
+        let tuplets: Vec<Option<(u8, u8, f32)>> = parsed.notes.iter()
+            .map(|parsed| parsed.note.tuplet.map(|tuplet| (tuplet.actual, tuplet.normal, tuplet.start)))
+            .collect();
+        assert_eq!(tuplets, vec![Some((3, 2, 0.0)), Some((3, 2, 0.0)), Some((3, 2, 0.0)), None]);
+    }
+    
+    #[test]
+    fn broken_rhythm_dots_one_note_and_halves_the_next() {
+        let parsed = parse("X:1\nL:1/8\nK:C\nC>D E<F G>>A z2|\n");
+        assert_eq!(timing(&parsed), vec![
+            (60, 0.0, 0.75),
+            (62, 0.75, 0.25),
+            (64, 1.0, 0.25),
+            (65, 1.25, 0.75),
+            (67, 2.0, 0.875),
+            (69, 2.875, 0.125),
+        ]);
+    }
+    
+    #[test]
+    fn inline_key_changes_apply_from_where_they_are_written() {
+        let parsed = parse("X:1\nL:1/4\nK:C\nF4|[K:G] F4|\n");
+        assert_eq!(pitches(&parsed, 0), vec![65, 66]);
+        let keys: Vec<KeySignature> = parsed.signatures.measures(8.0).iter().map(|measure| measure.key_signature).collect();
+        assert_eq!(keys, vec![KeySignature::new(0, false), KeySignature::new(1, false)]);
+    }
+}
\ No newline at end of file
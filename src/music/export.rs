@@ -0,0 +1,847 @@
+use anyhow::{bail, Context, Result};
+use midly::num::{u15, u24, u28, u4, u7};
+use midly::{Format, Header, MetaMessage, MidiMessage, PitchBend, Smf, Timing, TrackEvent, TrackEventKind};
+use std::fs;
+use std::path::Path;
+
+use crate::midi::{EventType, MidiRecording};
+use crate::notation::{Articulation, Hand, KeySignature, Note, NoteType, NoteValue, Tuplet, WrittenRest};
+use crate::notation::duration::{note_segments, rest_segments};
+use crate::notation::signature::Measure;
+use super::library::Song;
+use super::tempo::DEFAULT_TEMPO_BPM;
+
+// Writes songs out as MusicXML or Standard MIDI Files, and recorded takes as
+// Standard MIDI Files. A song is written as one piano part with a staff per
+// hand (MusicXML) or a track per hand (MIDI), plus a MIDI track for any
+// accompaniment.
+
+const TICKS_PER_BEAT: u16 = 480;
+// Imported notes carry no dynamics; accented notes are played a little louder
+const DEFAULT_VELOCITY: u8 = 80;
+const ACCENT_VELOCITY: u8 = 100;
+const CC_SUSTAIN: u8 = 64;
+const ACCOMPANIMENT_CHANNEL: u8 = 1; // The hands share channel 0
+
+// Takes are written at a fixed tempo so the ticks keep the real timing
+const TAKE_TEMPO_BPM: f32 = DEFAULT_TEMPO_BPM;
+
+const EPSILON: f32 = 0.001;
+
+// MusicXML divisions per quarter note: a whole number for a thirty-second
+// note, and for each note of a triplet, quintuplet or septuplet of those
+const DIVISIONS_PER_BEAT: f32 = 8.0 * 3.0 * 5.0 * 7.0;
+
+// Writes `song` to `path` in the format its extension names
+pub fn export_song(song: &Song, path: &Path) -> Result<()> {
+    let extension = path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_lowercase);
+    let data = match extension.as_deref() {
+        Some("musicxml" | "xml") => song_to_musicxml(song).into_bytes(),
+        Some("mid" | "midi") => song_to_midi(song)?,
+        _ => bail!("Unsupported song format {} (expected .musicxml, .xml, .mid or .midi)", path.display()),
+    };
+    fs::write(path, data).with_context(|| format!("Failed to write {}", path.display()))?;
+    
+    log::info!("Exported '{}' to {}", song.title, path.display());
+    Ok(())
+}
+
+// Writes what was played in an attempt as a MIDI file, with the original
+// timing, velocities and pedalling
+pub fn export_take(take: &MidiRecording, title: &str, path: &Path) -> Result<()> {
+    let ticks_per_micro = TICKS_PER_BEAT as f64 * TAKE_TEMPO_BPM as f64 / 60_000_000.0;
+    
+    let mut events = vec![
+        (0, TrackEventKind::Meta(MetaMessage::TrackName(title.as_bytes()))),
+        (0, TrackEventKind::Meta(MetaMessage::Tempo(micros_per_beat(TAKE_TEMPO_BPM)))),
+    ];
+    for event in take.get_events() {
+        let message = match event.event_type {
+            EventType::NoteOn { note, velocity } => MidiMessage::NoteOn { key: note.into(), vel: velocity.into() },
+            EventType::NoteOff { note, velocity } => MidiMessage::NoteOff { key: note.into(), vel: velocity.into() },
+            EventType::PolyAftertouch { note, pressure } => MidiMessage::Aftertouch { key: note.into(), vel: pressure.into() },
+            EventType::ControlChange { controller, value } => MidiMessage::Controller { controller: controller.into(), value: value.into() },
+            EventType::ProgramChange { program } => MidiMessage::ProgramChange { program: program.into() },
+            EventType::ChannelAftertouch { pressure } => MidiMessage::ChannelAftertouch { vel: pressure.into() },
+            EventType::PitchBend { value } => MidiMessage::PitchBend { bend: PitchBend::from_int(value) },
+        };
+        let tick = (take.offset_of(event) as f64 * ticks_per_micro).round() as u32;
+        events.push((tick, TrackEventKind::Midi { channel: u4::from(event.channel), message }));
+    }
+    
+    let data = write_smf(Format::SingleTrack, vec![build_track(events)])?;
+    fs::write(path, data).with_context(|| format!("Failed to write {}", path.display()))?;
+    
+    log::info!("Saved take of '{}' to {}", title, path.display());
+    Ok(())
+}
+
+// Tempos too slow for the 24-bit field get the slowest it can hold
+fn micros_per_beat(bpm: f32) -> u24 {
+    let slowest = u24::max_value().as_int();
+    let micros = (60_000_000.0 / bpm.max(f32::MIN_POSITIVE) as f64).round();
+    u24::from(micros.clamp(1.0, slowest as f64) as u32)
+}
+
+fn beat_ticks(beats: f32) -> u32 {
+    (beats.max(0.0) * TICKS_PER_BEAT as f32).round() as u32
+}
+
+// Orders events by time and turns their absolute ticks into deltas. Events at
+// the same tick keep their order.
+fn build_track(mut events: Vec<(u32, TrackEventKind)>) -> Vec<TrackEvent> {
+    events.sort_by_key(|&(tick, _)| tick);
+    
+    let mut previous = 0;
+    let mut track: Vec<TrackEvent> = events.into_iter()
+        .map(|(tick, kind)| {
+            let delta = tick - previous;
+            previous = tick;
+            TrackEvent { delta: u28::from(delta), kind }
+        })
+        .collect();
+    track.push(TrackEvent { delta: u28::from(0), kind: TrackEventKind::Meta(MetaMessage::EndOfTrack) });
+    track
+}
+
+fn write_smf(format: Format, tracks: Vec<Vec<TrackEvent>>) -> Result<Vec<u8>> {
+    let mut smf = Smf::new(Header::new(format, Timing::Metrical(u15::from(TICKS_PER_BEAT))));
+    smf.tracks = tracks;
+    let mut data = Vec::new();
+    smf.write_std(&mut data).context("Failed to encode MIDI file")?;
+    Ok(data)
+}
+
+// A conductor track with the title, tempo and signatures, then a track per hand
+pub fn song_to_midi(song: &Song) -> Result<Vec<u8>> {
+    let measures = song.signatures.measures(song.end_beat());
+    
+    let mut conductor = vec![(0, TrackEventKind::Meta(MetaMessage::TrackName(song.title.as_bytes())))];
+    for change in song.tempo_map.get_changes() {
+        conductor.push((beat_ticks(change.beat), TrackEventKind::Meta(MetaMessage::Tempo(micros_per_beat(change.bpm)))));
+    }
+    for (i, measure) in measures.iter().enumerate() {
+        let previous = i.checked_sub(1).map(|previous| &measures[previous]);
+        let tick = beat_ticks(measure.start);
+        if previous.is_none_or(|previous| previous.time_signature != measure.time_signature) {
+            let signature = measure.time_signature;
+            // The denominator is stored as a power of two
+            let power = signature.denominator.max(1).ilog2() as u8;
+            conductor.push((tick, TrackEventKind::Meta(MetaMessage::TimeSignature(signature.numerator, power, 24, 8))));
+        }
+        if previous.is_none_or(|previous| previous.key_signature != measure.key_signature) {
+            let key = measure.key_signature;
+            conductor.push((tick, TrackEventKind::Meta(MetaMessage::KeySignature(key.fifths, key.minor))));
+        }
+    }
+    
+    let mut tracks = vec![build_track(conductor)];
+    for hand in [Hand::Right, Hand::Left] {
+        let notes = song.notes.iter().filter(|note| note.written_hand() == hand);
+        let mut events = part_events(hand.as_str(), notes, 0);
+        // The pedal goes with the left hand, which usually sets the harmony
+        if hand == Hand::Left {
+            for marking in &song.pedal_markings {
+                events.push((beat_ticks(marking.start), sustain(true)));
+                events.push((beat_ticks(marking.end), sustain(false)));
+            }
+        }
+        tracks.push(build_part_track(events));
+    }
+    if !song.accompaniment.is_empty() {
+        let events = part_events("Accompaniment", song.accompaniment.iter(), ACCOMPANIMENT_CHANNEL);
+        tracks.push(build_part_track(events));
+    }
+    
+    write_smf(Format::Parallel, tracks)
+}
+
+// A named track holding the notes on one channel
+fn part_events<'a>(name: &'a str, notes: impl Iterator<Item = &'a Note>, channel: u8) -> Vec<(u32, TrackEventKind<'a>)> {
+    let mut events = vec![(0, TrackEventKind::Meta(MetaMessage::TrackName(name.as_bytes())))];
+    for note in notes {
+        let accented = note.articulations.iter().any(|articulation| matches!(articulation, Articulation::Accent | Articulation::Marcato));
+        let velocity = if accented { ACCENT_VELOCITY } else { DEFAULT_VELOCITY };
+        let key = u7::from(note.pitch);
+        events.push((beat_ticks(note.position), channel_message(channel, MidiMessage::NoteOn { key, vel: velocity.into() })));
+        events.push((beat_ticks(note.end()), channel_message(channel, MidiMessage::NoteOff { key, vel: 0.into() })));
+    }
+    events
+}
+
+fn build_part_track(mut events: Vec<(u32, TrackEventKind)>) -> Vec<TrackEvent> {
+    // A note ending where the next one of the same pitch starts must be
+    // released first
+    events.sort_by_key(|(tick, kind)| (*tick, !is_release(kind)));
+    build_track(events)
+}
+
+fn channel_message(channel: u8, message: MidiMessage) -> TrackEventKind<'static> {
+    TrackEventKind::Midi { channel: u4::from(channel), message }
+}
+
+fn sustain(down: bool) -> TrackEventKind<'static> {
+    let value = if down { 127 } else { 0 };
+    channel_message(0, MidiMessage::Controller { controller: CC_SUSTAIN.into(), value: value.into() })
+}
+
+fn is_release(kind: &TrackEventKind) -> bool {
+    match kind {
+        TrackEventKind::Midi { message: MidiMessage::NoteOff { .. }, .. } => true,
+        TrackEventKind::Midi { message: MidiMessage::Controller { controller, value }, .. } => {
+            u8::from(*controller) == CC_SUSTAIN && u8::from(*value) < 64
+        }
+        _ => false,
+    }
+}
+
+// Indented XML built up element by element
+struct XmlWriter {
+    out: String,
+    depth: usize,
+}
+
+impl XmlWriter {
+    fn new() -> Self {
+        Self {
+            out: String::new(),
+            depth: 0,
+        }
+    }
+    
+    fn line(&mut self, text: &str) {
+        self.out.push_str(&"  ".repeat(self.depth));
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+    
+    fn open(&mut self, tag: &str) {
+        self.line(&format!("<{}>", tag));
+        self.depth += 1;
+    }
+    
+    fn close(&mut self, tag: &str) {
+        self.depth -= 1;
+        let name = tag.split_whitespace().next().unwrap_or(tag);
+        self.line(&format!("</{}>", name));
+    }
+    
+    fn element(&mut self, tag: &str, text: impl std::fmt::Display) {
+        let name = tag.split_whitespace().next().unwrap_or(tag);
+        self.line(&format!("<{}>{}</{}>", tag, escape(&text.to_string()), name));
+    }
+    
+    fn empty(&mut self, tag: &str) {
+        self.line(&format!("<{}/>", tag));
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// One written value of a note in a measure
+struct Head<'a> {
+    note: &'a Note,
+    value: NoteValue,
+    tie_stop: bool,
+    tie_start: bool,
+}
+
+// Heads sounding together in one voice, or a written rest when there are none
+struct Chord<'a> {
+    position: f32,
+    value: NoteValue,
+    voice: Option<u8>, // As imported
+    tuplet: Option<Tuplet>,
+    bracket: Option<&'static str>, // Where the chord starts or stops its tuplet bracket
+    heads: Vec<Head<'a>>,
+}
+
+impl Chord<'_> {
+    // Beats taken, which a tuplet shortens
+    fn length(&self) -> f32 {
+        self.value.beats() / self.tuplet.map_or(1.0, |tuplet| tuplet.ratio())
+    }
+}
+
+// Something written above or below the staff at a beat
+enum Direction {
+    Tempo(f32),
+    Pedal(bool), // Down or up
+}
+
+const STEP_NAMES: [&str; 7] = ["C", "D", "E", "F", "G", "A", "B"];
+
+fn divisions(beats: f32) -> i64 {
+    (beats * DIVISIONS_PER_BEAT).round() as i64
+}
+
+fn type_name(note_type: NoteType) -> &'static str {
+    match note_type {
+        NoteType::Whole => "whole",
+        NoteType::Half => "half",
+        NoteType::Quarter => "quarter",
+        NoteType::Eighth => "eighth",
+        NoteType::Sixteenth => "16th",
+        NoteType::ThirtySecond => "32nd",
+    }
+}
+
+// Partwise MusicXML with one piano part on two staves. Notes are split at
+// barlines and into tied written values the way they are engraved on screen,
+// and overlapping notes on a staff go into separate voices. Rests the source
+// wrote are kept; other gaps are filled with rests on the beat.
+pub fn song_to_musicxml(song: &Song) -> String {
+    let measures = song.signatures.measures(song.end_beat());
+    
+    let mut directions: Vec<(f32, Direction)> = song.tempo_map.get_changes().iter()
+        .map(|change| (change.beat, Direction::Tempo(change.bpm)))
+        .collect();
+    for marking in &song.pedal_markings {
+        directions.push((marking.start, Direction::Pedal(true)));
+        directions.push((marking.end, Direction::Pedal(false)));
+    }
+    
+    let mut xml = XmlWriter::new();
+    xml.line("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>");
+    xml.line("<!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD MusicXML 4.0 Partwise//EN\" \"http://www.musicxml.org/dtds/partwise.dtd\">");
+    xml.open("score-partwise version=\"4.0\"");
+    
+    xml.open("work");
+    xml.element("work-title", &song.title);
+    xml.close("work");
+    xml.open("identification");
+    if !song.artist.is_empty() {
+        xml.element("creator type=\"composer\"", &song.artist);
+    }
+    xml.open("encoding");
+    xml.element("software", concat!("piano ", env!("CARGO_PKG_VERSION")));
+    xml.close("encoding");
+    xml.close("identification");
+    
+    xml.open("part-list");
+    xml.open("score-part id=\"P1\"");
+    xml.element("part-name", "Piano");
+    xml.close("score-part");
+    xml.close("part-list");
+    
+    // Each staff's voices in each measure. The right hand numbers its voices
+    // from 1 and the left hand carries on after the most the right hand needs,
+    // from 5 at the earliest, so the staves never share a voice number.
+    let staves: Vec<(usize, Vec<&Note>, Vec<&WrittenRest>)> = [(1, Hand::Right), (2, Hand::Left)].into_iter()
+        .map(|(staff, hand)| (
+            staff,
+            song.notes.iter().filter(|note| note.written_hand() == hand).collect(),
+            song.rests.iter().filter(|rest| rest.hand == Some(hand)).collect(),
+        ))
+        .collect();
+    let lanes: Vec<Vec<Vec<Vec<Chord>>>> = measures.iter()
+        .map(|measure| staves.iter().map(|(_, notes, rests)| voices(notes, rests, &measures, measure)).collect())
+        .collect();
+    let right_voices = lanes.iter().map(|staves| staves[0].len()).max().unwrap_or(0).max(4);
+    
+    xml.open("part id=\"P1\"");
+    for (i, measure) in measures.iter().enumerate() {
+        xml.open(&format!("measure number=\"{}\"", measure.index + 1));
+        write_attributes(&mut xml, measure, i.checked_sub(1).map(|previous| &measures[previous]));
+        
+        let end = measure.start + measure.length;
+        for (beat, direction) in directions.iter().filter(|(beat, _)| *beat >= measure.start - EPSILON && *beat < end - EPSILON) {
+            write_direction(&mut xml, direction, divisions(beat - measure.start));
+        }
+        
+        let mut first_voice = true;
+        for ((staff, _, _), staff_lanes) in staves.iter().zip(&lanes[i]) {
+            let first_number = if *staff == 1 { 1 } else { right_voices + 1 };
+            for (lane, chords) in staff_lanes.iter().enumerate() {
+                // Each voice starts from the beginning of the measure
+                if !first_voice {
+                    xml.open("backup");
+                    xml.element("duration", divisions(measure.length));
+                    xml.close("backup");
+                }
+                first_voice = false;
+                write_voice(&mut xml, chords, measure, *staff, first_number + lane);
+            }
+        }
+        
+        xml.close("measure");
+    }
+    xml.close("part");
+    xml.close("score-partwise");
+    xml.out
+}
+
+// Divisions, and the key and meter wherever they change
+fn write_attributes(xml: &mut XmlWriter, measure: &Measure, previous: Option<&Measure>) {
+    let key_changes = previous.is_none_or(|previous| previous.key_signature != measure.key_signature);
+    let meter_changes = previous.is_none_or(|previous| previous.time_signature != measure.time_signature);
+    if !key_changes && !meter_changes {
+        return;
+    }
+    
+    xml.open("attributes");
+    if previous.is_none() {
+        xml.element("divisions", divisions(1.0));
+    }
+    if key_changes {
+        xml.open("key");
+        xml.element("fifths", measure.key_signature.fifths);
+        xml.element("mode", if measure.key_signature.minor { "minor" } else { "major" });
+        xml.close("key");
+    }
+    if meter_changes {
+        xml.open("time");
+        xml.element("beats", measure.time_signature.numerator);
+        xml.element("beat-type", measure.time_signature.denominator);
+        xml.close("time");
+    }
+    if previous.is_none() {
+        xml.element("staves", 2);
+        for (number, sign, line) in [(1, "G", 2), (2, "F", 4)] {
+            xml.open(&format!("clef number=\"{}\"", number));
+            xml.element("sign", sign);
+            xml.element("line", line);
+            xml.close("clef");
+        }
+    }
+    xml.close("attributes");
+}
+
+fn write_direction(xml: &mut XmlWriter, direction: &Direction, offset: i64) {
+    match direction {
+        Direction::Tempo(bpm) => {
+            xml.open("direction placement=\"above\"");
+            xml.open("direction-type");
+            xml.open("metronome");
+            xml.element("beat-unit", "quarter");
+            xml.element("per-minute", bpm.round());
+            xml.close("metronome");
+            xml.close("direction-type");
+            if offset > 0 {
+                xml.element("offset", offset);
+            }
+            xml.empty(&format!("sound tempo=\"{}\"", bpm));
+            xml.close("direction");
+        }
+        Direction::Pedal(down) => {
+            xml.open("direction placement=\"below\"");
+            xml.open("direction-type");
+            xml.empty(&format!("pedal type=\"{}\" line=\"yes\"", if *down { "start" } else { "stop" }));
+            xml.close("direction-type");
+            if offset > 0 {
+                xml.element("offset", offset);
+            }
+            xml.element("staff", 2);
+            xml.close("direction");
+        }
+    }
+}
+
+// The chords and written rests of one staff in one measure, split into voices
+// that never overlap. Imported voices are kept in order where they fit; each
+// chord joins the first voice with room for it.
+fn voices<'a>(notes: &[&'a Note], rests: &[&WrittenRest], measures: &[Measure], measure: &Measure) -> Vec<Vec<Chord<'a>>> {
+    let end = measure.start + measure.length;
+    let in_measure = |position: f32| position >= measure.start - EPSILON && position < end - EPSILON;
+    let mut chords: Vec<Chord> = Vec::new();
+    for &note in notes {
+        let segments = note_segments(note, measures);
+        let last = segments.len().saturating_sub(1);
+        for (segment, (position, value)) in segments.into_iter().enumerate() {
+            if !in_measure(position) {
+                continue;
+            }
+            let head = Head {
+                note,
+                value,
+                tie_stop: segment > 0,
+                tie_start: segment < last,
+            };
+            let same_chord = |chord: &&mut Chord| {
+                (chord.position - position).abs() < EPSILON
+                    && chord.value == value
+                    && chord.voice == note.voice
+                    && chord.tuplet == note.tuplet
+                    && !chord.heads.is_empty()
+            };
+            match chords.iter_mut().find(same_chord) {
+                Some(chord) => chord.heads.push(head),
+                None => chords.push(Chord { position, value, voice: note.voice, tuplet: note.tuplet, bracket: None, heads: vec![head] }),
+            }
+        }
+    }
+    // Measure rests are left for the empty voice to write
+    for rest in rests.iter().filter(|rest| !rest.full_measure) {
+        for (position, value) in rest_segments(rest).into_iter().filter(|(position, _)| in_measure(*position)) {
+            chords.push(Chord { position, value, voice: rest.voice, tuplet: rest.tuplet, bracket: None, heads: Vec::new() });
+        }
+    }
+    chords.sort_by(|a, b| {
+        a.voice.cmp(&b.voice)
+            .then(a.position.total_cmp(&b.position))
+            .then(b.length().total_cmp(&a.length()))
+    });
+    
+    let overlaps = |a: &Chord, b: &Chord| {
+        a.position < b.position + b.length() - EPSILON && b.position < a.position + a.length() - EPSILON
+    };
+    let mut lanes: Vec<Vec<Chord>> = Vec::new();
+    for mut chord in chords {
+        chord.heads.sort_by_key(|head| head.note.pitch);
+        match lanes.iter_mut().find(|lane| !lane.iter().any(|other| overlaps(other, &chord))) {
+            Some(lane) => lane.push(chord),
+            None => lanes.push(vec![chord]),
+        }
+    }
+    for lane in &mut lanes {
+        lane.sort_by(|a, b| a.position.total_cmp(&b.position));
+        
+        // A tuplet bracket opens on its first chord and closes on its last
+        let bracket_of = |chord: Option<&Chord>| chord.and_then(|chord| chord.tuplet).map(|tuplet| tuplet.start.to_bits());
+        for i in 0..lane.len() {
+            let Some(bracket) = bracket_of(lane.get(i)) else {
+                continue;
+            };
+            let starts = i.checked_sub(1).and_then(|previous| bracket_of(lane.get(previous))) != Some(bracket);
+            let stops = bracket_of(lane.get(i + 1)) != Some(bracket);
+            lane[i].bracket = match (starts, stops) {
+                (true, _) => Some("start"),
+                (false, true) => Some("stop"),
+                (false, false) => None,
+            };
+        }
+    }
+    
+    // An empty staff still gets a voice for its measure rest
+    if lanes.is_empty() {
+        lanes.push(Vec::new());
+    }
+    lanes
+}
+
+fn write_voice(xml: &mut XmlWriter, chords: &[Chord], measure: &Measure, staff: usize, voice: usize) {
+    if chords.is_empty() {
+        xml.open("note");
+        xml.empty("rest measure=\"yes\"");
+        xml.element("duration", divisions(measure.length));
+        xml.element("voice", voice);
+        xml.element("staff", staff);
+        xml.close("note");
+        return;
+    }
+    
+    let end = measure.start + measure.length;
+    let mut cursor = measure.start;
+    for chord in chords {
+        write_rests(xml, measure, cursor, chord.position, staff, voice);
+        if chord.heads.is_empty() {
+            write_written_rest(xml, chord, staff, voice);
+        }
+        for (i, head) in chord.heads.iter().enumerate() {
+            write_note(xml, chord, head, i > 0, measure.key_signature, staff, voice);
+        }
+        cursor = chord.position + chord.length();
+    }
+    write_rests(xml, measure, cursor, end, staff, voice);
+}
+
+fn write_rests(xml: &mut XmlWriter, measure: &Measure, from: f32, to: f32, staff: usize, voice: usize) {
+    if to - from < EPSILON {
+        return;
+    }
+    for value in NoteValue::split_rest(from - measure.start, to - from) {
+        xml.open("note");
+        xml.empty("rest");
+        xml.element("duration", divisions(value.beats()));
+        xml.element("voice", voice);
+        write_value(xml, value);
+        xml.element("staff", staff);
+        xml.close("note");
+    }
+}
+
+fn write_written_rest(xml: &mut XmlWriter, chord: &Chord, staff: usize, voice: usize) {
+    xml.open("note");
+    xml.empty("rest");
+    xml.element("duration", divisions(chord.length()));
+    xml.element("voice", voice);
+    write_value(xml, chord.value);
+    write_time_modification(xml, chord.tuplet);
+    xml.element("staff", staff);
+    if let Some(bracket) = chord.bracket {
+        xml.open("notations");
+        xml.empty(&format!("tuplet type=\"{}\"", bracket));
+        xml.close("notations");
+    }
+    xml.close("note");
+}
+
+fn write_value(xml: &mut XmlWriter, value: NoteValue) {
+    xml.element("type", type_name(value.note_type));
+    for _ in 0..value.dots {
+        xml.empty("dot");
+    }
+}
+
+fn write_time_modification(xml: &mut XmlWriter, tuplet: Option<Tuplet>) {
+    if let Some(tuplet) = tuplet {
+        xml.open("time-modification");
+        xml.element("actual-notes", tuplet.actual);
+        xml.element("normal-notes", tuplet.normal);
+        xml.close("time-modification");
+    }
+}
+
+fn write_note(xml: &mut XmlWriter, chord: &Chord, head: &Head, in_chord: bool, key: KeySignature, staff: usize, voice: usize) {
+    let note = head.note;
+    let spelled = note.spelled_in(key);
+    
+    xml.open("note");
+    if in_chord {
+        xml.empty("chord");
+    }
+    xml.open("pitch");
+    xml.element("step", STEP_NAMES[spelled.step as usize]);
+    if spelled.alter != 0 {
+        xml.element("alter", spelled.alter);
+    }
+    xml.element("octave", spelled.octave);
+    xml.close("pitch");
+    xml.element("duration", divisions(chord.length()));
+    if head.tie_stop {
+        xml.empty("tie type=\"stop\"");
+    }
+    if head.tie_start {
+        xml.empty("tie type=\"start\"");
+    }
+    xml.element("voice", voice);
+    write_value(xml, head.value);
+    write_time_modification(xml, chord.tuplet);
+    xml.element("staff", staff);
+    
+    // Marks belong to the first written value of a tied note
+    let first = !head.tie_stop;
+    let articulations: Vec<&str> = note.articulations.iter()
+        .filter_map(|articulation| match articulation {
+            Articulation::Staccato => Some("staccato"),
+            Articulation::Staccatissimo => Some("staccatissimo"),
+            Articulation::Tenuto => Some("tenuto"),
+            Articulation::Accent => Some("accent"),
+            Articulation::Marcato => Some("strong-accent"),
+            Articulation::Fermata => None,
+        })
+        .collect();
+    let fermata = note.articulations.contains(&Articulation::Fermata);
+    let marked = first && (!articulations.is_empty() || fermata || note.fingering.is_some());
+    // The first note of a chord carries its tuplet bracket
+    let bracket = chord.bracket.filter(|_| !in_chord);
+    
+    if head.tie_stop || head.tie_start || marked || bracket.is_some() {
+        xml.open("notations");
+        if let Some(bracket) = bracket {
+            xml.empty(&format!("tuplet type=\"{}\"", bracket));
+        }
+        if head.tie_stop {
+            xml.empty("tied type=\"stop\"");
+        }
+        if head.tie_start {
+            xml.empty("tied type=\"start\"");
+        }
+        if marked {
+            if !articulations.is_empty() {
+                xml.open("articulations");
+                for articulation in &articulations {
+                    xml.empty(articulation);
+                }
+                xml.close("articulations");
+            }
+            if fermata {
+                xml.empty("fermata type=\"upright\"");
+            }
+            if let Some(finger) = note.fingering {
+                xml.open("technical");
+                xml.element("fingering", finger);
+                xml.close("technical");
+            }
+        }
+        xml.close("notations");
+    }
+    xml.close("note");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::MidiEvent;
+    use crate::music::musicxml::MusicXmlParser;
+    use crate::music::parser::MidiParser;
+    use crate::music::tempo::{TempoChange, TempoMap};
+    use crate::notation::PedalMarking;
+    use crate::notation::signature::SignatureTimeline;
+    use std::collections::{HashMap, HashSet};
+    
+    fn song(notes: Vec<Note>) -> Song {
+        let mut song = Song::from_notes("test".to_string(), "Test".to_string(), String::new(), None, notes);
+        song.pedal_markings = vec![PedalMarking { start: 0.0, end: 2.0 }];
+        song.with_timing(TempoMap::from_changes(vec![
+            TempoChange { beat: 0.0, bpm: 90.0 },
+            TempoChange { beat: 4.0, bpm: 120.0 },
+        ]), SignatureTimeline::default())
+    }
+    
+    fn sample_song() -> Song {
+        let triplet = Some(Tuplet { actual: 3, normal: 2, start: 5.0 });
+        let mut notes = vec![
+            Note::with_duration(60, 0.0, 1.0),
+            Note::with_duration(64, 1.0, 1.0),
+            Note::with_duration(67, 3.0, 2.0), // Tied across the barline
+            Note::with_duration(48, 0.0, 4.0),
+        ];
+        for (i, pitch) in [72, 74, 76].into_iter().enumerate() {
+            let mut note = Note::with_duration(pitch, 5.0 + i as f32 / 3.0, 1.0 / 3.0);
+            note.tuplet = triplet;
+            notes.push(note);
+        }
+        song(notes)
+    }
+    
+    fn summary(notes: impl Iterator<Item = Note>) -> Vec<(u8, f32, f32)> {
+        let round = |beats: f32| (beats * 1000.0).round() / 1000.0;
+        let mut notes: Vec<(u8, f32, f32)> = notes.map(|note| (note.pitch, round(note.position), round(note.duration))).collect();
+        notes.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        notes
+    }
+    
+    #[test]
+    fn musicxml_round_trips_notes_ties_and_tuplets() {
+        let song = sample_song();
+        let xml = song_to_musicxml(&song);
+        let parsed = MusicXmlParser::parse(xml.as_bytes()).unwrap();
+        
+        let expected = summary(song.notes.iter().cloned());
+        assert_eq!(summary(parsed.notes.iter().map(|parsed| parsed.note.clone())), expected);
+        
+        // The note over the barline is written as two tied values
+        assert_eq!(xml.matches("<tie type=\"start\"/>").count(), 1);
+        assert_eq!(xml.matches("<tie type=\"stop\"/>").count(), 1);
+        
+        assert_eq!(xml.matches("<tuplet type=\"start\"/>").count(), 1);
+        assert_eq!(xml.matches("<tuplet type=\"stop\"/>").count(), 1);
+        let tuplets: Vec<Option<Tuplet>> = parsed.notes.iter()
+            .filter(|parsed| parsed.note.pitch >= 72)
+            .map(|parsed| parsed.note.tuplet)
+            .collect();
+        assert_eq!(tuplets, vec![Some(Tuplet { actual: 3, normal: 2, start: 5.0 }); 3]);
+        
+        assert!(xml.contains("<pedal type=\"start\" line=\"yes\"/>"));
+        assert!(xml.contains("<pedal type=\"stop\" line=\"yes\"/>"));
+        let bpms: Vec<f32> = parsed.tempo_map.get_changes().iter().map(|change| change.bpm).collect();
+        assert_eq!(bpms, vec![90.0, 120.0]);
+    }
+    
+    #[test]
+    fn midi_round_trips_notes_tempo_and_pedal() {
+        let song = sample_song();
+        let data = song_to_midi(&song).unwrap();
+        let parsed = MidiParser::parse_midi_file(&data).unwrap();
+        
+        // Importing snaps notes to the thirty-second grid, which the triplet
+        // is off, so it is checked by its ticks below
+        let expected = summary(song.notes.iter().filter(|note| note.tuplet.is_none()).cloned());
+        let notes = parsed.notes.iter().map(|parsed| parsed.note.clone()).filter(|note| note.pitch < 72);
+        assert_eq!(summary(notes), expected);
+        
+        // Tempos are stored in whole microseconds per beat
+        let changes: Vec<(f32, f32)> = parsed.tempo_map.get_changes().iter()
+            .map(|change| (change.beat, (change.bpm * 100.0).round() / 100.0))
+            .collect();
+        assert_eq!(changes, vec![(0.0, 90.0), (4.0, 120.0)]);
+        
+        let smf = Smf::parse(&data).unwrap();
+        let mut pedal = Vec::new();
+        let mut triplet = Vec::new();
+        for track in &smf.tracks {
+            let mut tick = 0;
+            for event in track {
+                tick += event.delta.as_int();
+                match event.kind {
+                    TrackEventKind::Midi { message: MidiMessage::Controller { controller, value }, .. } if controller.as_int() == CC_SUSTAIN => {
+                        pedal.push((tick, value.as_int()));
+                    }
+                    TrackEventKind::Midi { message: MidiMessage::NoteOn { key, .. }, .. } if key.as_int() >= 72 => {
+                        triplet.push(tick);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        assert_eq!(pedal, vec![(0, 127), (960, 0)]);
+        assert_eq!(triplet, vec![2400, 2560, 2720]);
+    }
+    
+    #[test]
+    fn takes_keep_their_timing_and_velocities() {
+        let event = |timestamp: u64, event_type: EventType| MidiEvent { channel: 0, timestamp, event_type };
+        let mut take = MidiRecording::new(1_000_000);
+        take.record(&event(1_000_000, EventType::NoteOn { note: 60, velocity: 45 }));
+        take.record(&event(1_250_000, EventType::ControlChange { controller: 64, value: 127 }));
+        take.record(&event(1_500_000, EventType::NoteOn { note: 64, velocity: 110 }));
+        take.record(&event(2_000_000, EventType::NoteOff { note: 60, velocity: 30 }));
+        
+        let path = std::env::temp_dir().join(format!("piano-take-{}.mid", std::process::id()));
+        export_take(&take, "Take", &path).unwrap();
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).ok();
+        
+        // At 120 bpm a quarter note lasts half a second
+        let smf = Smf::parse(&data).unwrap();
+        let mut tick = 0;
+        let mut messages = Vec::new();
+        for event in &smf.tracks[0] {
+            tick += event.delta.as_int();
+            if let TrackEventKind::Midi { message, .. } = event.kind {
+                messages.push((tick, message));
+            }
+        }
+        assert_eq!(messages, vec![
+            (0, MidiMessage::NoteOn { key: 60.into(), vel: 45.into() }),
+            (240, MidiMessage::Controller { controller: 64.into(), value: 127.into() }),
+            (480, MidiMessage::NoteOn { key: 64.into(), vel: 110.into() }),
+            (960, MidiMessage::NoteOff { key: 60.into(), vel: 30.into() }),
+        ]);
+    }
+    
+    #[test]
+    fn slow_tempos_clamp_to_the_tempo_field() {
+        assert_eq!(micros_per_beat(120.0).as_int(), 500_000);
+        assert_eq!(micros_per_beat(1.0).as_int(), 16_777_215);
+        assert_eq!(micros_per_beat(0.0).as_int(), 16_777_215);
+    }
+    
+    #[test]
+    fn staves_never_share_a_voice() {
+        // Five overlapping notes need five voices in the right hand
+        let mut notes: Vec<Note> = (0..5)
+            .map(|i| Note::with_duration(72 + i as u8, i as f32 * 0.25, 3.0))
+            .collect();
+        notes.push(Note::with_duration(48, 0.0, 4.0));
+        let xml = song_to_musicxml(&song(notes));
+        
+        let options = roxmltree::ParsingOptions { allow_dtd: true, ..roxmltree::ParsingOptions::default() };
+        let document = roxmltree::Document::parse_with_options(&xml, options).unwrap();
+        let mut staves_of_voice: HashMap<String, HashSet<String>> = HashMap::new();
+        for note in document.descendants().filter(|node| node.has_tag_name("note")) {
+            let text = |name: &str| note.children().find(|child| child.has_tag_name(name)).and_then(|child| child.text()).unwrap().to_string();
+            staves_of_voice.entry(text("voice")).or_default().insert(text("staff"));
+        }
+        assert!(staves_of_voice.values().all(|staves| staves.len() == 1));
+        assert_eq!(staves_of_voice["6"], HashSet::from(["2".to_string()]));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,211 @@
+use crate::notation::{Hand, Note};
+
+// Splits a piece between the hands. Notes that already know their hand (from
+// their track or an explicit tag) keep it and guide the rest, which follow
+// each hand's recent range. A chord only moves to the other hand when it is
+// clearly closer to it, so a line wandering around middle C stays on one staff.
+
+// Where the hands start out: around G4 and C3
+const RIGHT_START: f32 = 67.0;
+const LEFT_START: f32 = 48.0;
+
+// A hand's position never crosses middle C, so a single line heading into the
+// other register does hand over eventually
+const MIDDLE_C: f32 = 60.0;
+
+// Widest chord one hand is given, in semitones
+const HAND_SPAN: u8 = 14;
+
+// How much closer (in semitones) a chord has to be to the other hand before it
+// switches
+const SWITCH_MARGIN: f32 = 5.0;
+
+// How far a hand's position moves towards each chord it plays
+const FOLLOW: f32 = 0.5;
+
+const POSITION_EPSILON: f32 = 0.001;
+
+struct HandTracker {
+    right: f32,
+    left: f32,
+    last: Option<Hand>,
+    held: Vec<(Hand, u8, f32)>, // Hand, pitch and end of the notes still sounding
+}
+
+impl HandTracker {
+    fn new() -> Self {
+        Self {
+            right: RIGHT_START,
+            left: LEFT_START,
+            last: None,
+            held: Vec::new(),
+        }
+    }
+    
+    fn release_until(&mut self, position: f32) {
+        self.held.retain(|&(_, _, end)| end > position + POSITION_EPSILON);
+    }
+    
+    fn play(&mut self, hand: Hand, pitches: &[u8], end: f32) {
+        let center = pitches.iter().map(|&pitch| pitch as f32).sum::<f32>() / pitches.len() as f32;
+        match hand {
+            Hand::Right => self.right = (self.right + FOLLOW * (center - self.right)).max(MIDDLE_C),
+            Hand::Left => self.left = (self.left + FOLLOW * (center - self.left)).min(MIDDLE_C),
+        }
+        self.held.extend(pitches.iter().map(|&pitch| (hand, pitch, end)));
+    }
+    
+    // Hand for a chord (ascending pitches) that fits under one hand
+    fn choose(&self, pitches: &[u8]) -> Hand {
+        let lowest = pitches[0];
+        let highest = pitches[pitches.len() - 1];
+        
+        // A hand holding notes keeps the other hand on its own side
+        let below_right = self.held.iter().any(|&(hand, pitch, _)| hand == Hand::Right && highest < pitch);
+        let above_left = self.held.iter().any(|&(hand, pitch, _)| hand == Hand::Left && lowest > pitch);
+        match (below_right, above_left) {
+            (true, false) => return Hand::Left,
+            (false, true) => return Hand::Right,
+            _ => {}
+        }
+        
+        let center = pitches.iter().map(|&pitch| pitch as f32).sum::<f32>() / pitches.len() as f32;
+        let to_right = (center - self.right).abs();
+        let to_left = (center - self.left).abs();
+        let closer = if to_right <= to_left { Hand::Right } else { Hand::Left };
+        
+        match self.last {
+            Some(last) if last != closer && (to_right - to_left).abs() < SWITCH_MARGIN => last,
+            _ => closer,
+        }
+    }
+}
+
+// Gives every note without a hand one
+pub fn assign_hands(notes: &mut [Note]) {
+    if notes.iter().all(|note| note.hand.is_some()) {
+        return;
+    }
+    
+    let mut order: Vec<usize> = (0..notes.len()).collect();
+    order.sort_by(|&a, &b| {
+        notes[a].position.total_cmp(&notes[b].position)
+            .then(notes[a].pitch.cmp(&notes[b].pitch))
+    });
+    
+    let mut tracker = HandTracker::new();
+    let mut start = 0;
+    while start < order.len() {
+        let position = notes[order[start]].position;
+        let mut end = start + 1;
+        while end < order.len() && (notes[order[end]].position - position).abs() < POSITION_EPSILON {
+            end += 1;
+        }
+        let onset = &order[start..end];
+        start = end;
+        
+        tracker.release_until(position);
+        let chord_end = onset.iter().map(|&i| notes[i].end()).fold(position, f32::max);
+        
+        for hand in [Hand::Right, Hand::Left] {
+            let tagged: Vec<u8> = onset.iter()
+                .filter(|&&i| notes[i].hand == Some(hand))
+                .map(|&i| notes[i].pitch)
+                .collect();
+            if !tagged.is_empty() {
+                tracker.play(hand, &tagged, chord_end);
+            }
+        }
+        
+        let untagged: Vec<usize> = onset.iter().copied().filter(|&i| notes[i].hand.is_none()).collect();
+        if untagged.is_empty() {
+            continue;
+        }
+        let pitches: Vec<u8> = untagged.iter().map(|&i| notes[i].pitch).collect();
+        
+        // Too wide for one hand: split at the widest gap
+        let split = if pitches[pitches.len() - 1] - pitches[0] > HAND_SPAN {
+            (1..pitches.len()).max_by_key(|&k| pitches[k] - pitches[k - 1])
+        } else {
+            None
+        };
+        
+        match split {
+            Some(split) => {
+                for (k, &i) in untagged.iter().enumerate() {
+                    notes[i].hand = Some(if k < split { Hand::Left } else { Hand::Right });
+                }
+                tracker.play(Hand::Left, &pitches[..split], chord_end);
+                tracker.play(Hand::Right, &pitches[split..], chord_end);
+            }
+            None => {
+                let hand = tracker.choose(&pitches);
+                for &i in &untagged {
+                    notes[i].hand = Some(hand);
+                }
+                tracker.play(hand, &pitches, chord_end);
+                tracker.last = Some(hand);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Hand::{Left as L, Right as R};
+    
+    // One note after another, a beat each
+    fn line(pitches: &[u8]) -> Vec<Note> {
+        pitches.iter().enumerate().map(|(i, &pitch)| Note::with_duration(pitch, i as f32, 1.0)).collect()
+    }
+    
+    fn hands(notes: &[Note]) -> Vec<Hand> {
+        notes.iter().map(|note| note.hand.unwrap()).collect()
+    }
+    
+    #[test]
+    fn registers_decide_the_hand() {
+        let mut notes = line(&[72, 36, 76, 40]);
+        assign_hands(&mut notes);
+        assert_eq!(hands(&notes), vec![R, L, R, L]);
+    }
+    
+    #[test]
+    fn a_line_around_middle_c_stays_in_one_hand() {
+        // Both sides of middle C without leaving the right hand, which only lets
+        // go once the line is clearly in the bass
+        let mut notes = line(&[64, 62, 60, 59, 57, 59, 55, 53, 52, 50, 48]);
+        assign_hands(&mut notes);
+        assert_eq!(hands(&notes), vec![R, R, R, R, R, R, R, R, R, L, L]);
+        
+        // Coming back up, the left hand holds on just as long
+        let mut notes = line(&[48, 52, 55, 57, 59, 60, 62, 64, 67]);
+        assign_hands(&mut notes);
+        assert_eq!(hands(&notes), vec![L, L, L, L, L, L, L, L, R]);
+    }
+    
+    #[test]
+    fn wide_chords_split_at_their_widest_gap() {
+        let mut notes: Vec<Note> = [36, 48, 64, 67, 72].iter().map(|&pitch| Note::with_duration(pitch, 0.0, 1.0)).collect();
+        assign_hands(&mut notes);
+        assert_eq!(hands(&notes), vec![L, L, R, R, R]);
+    }
+    
+    #[test]
+    fn tagged_notes_keep_their_hand_and_guide_the_rest() {
+        // A right-hand note held at A3 keeps the note struck below it in the left hand
+        let mut held = Note::with_duration(57, 0.0, 4.0);
+        held.hand = Some(R);
+        let mut notes = vec![held, Note::with_duration(55, 1.0, 1.0), Note::with_duration(64, 2.0, 1.0)];
+        assign_hands(&mut notes);
+        assert_eq!(hands(&notes), vec![R, L, R]);
+        
+        // Notes that all have a hand are left alone, wherever they are
+        let mut notes = line(&[36, 84]);
+        notes[0].hand = Some(R);
+        notes[1].hand = Some(L);
+        assign_hands(&mut notes);
+        assert_eq!(hands(&notes), vec![R, L]);
+    }
+}
\ No newline at end of file
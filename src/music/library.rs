@@ -1,5 +1,19 @@
-use crate::notation::{Note, NoteType};
-use super::{DifficultyLevel, DifficultyClassifier};
+use anyhow::{anyhow, Context, Result};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::notation::{Note, PedalMarking, SignatureTimeline, WrittenRest};
+use crate::storage;
+use super::manifest::{LibraryManifest, SongManifestEntry, MANIFEST_FILE_NAME};
+use super::hands;
+use super::scanner::{LibraryScanner, SONG_EXTENSIONS};
+use super::{AbcParser, ChannelSelection, DifficultyLevel, DifficultyClassifier, ParsedMidi, TempoMap};
+
+// Copying a folder of songs produces a burst of events; rescan once it settles
+const RESCAN_DELAY: Duration = Duration::from_millis(500);
 
 #[derive(Debug, Clone)]
 pub struct Song {
@@ -8,12 +22,127 @@ pub struct Song {
     pub artist: String,
     pub difficulty: DifficultyLevel,
     pub notes: Vec<Note>,
-    pub duration: f32, // in seconds
+    pub pedal_markings: Vec<PedalMarking>,
+    pub duration: f32, // in seconds, from the tempo map
+    pub tags: Vec<String>,
+    pub tempo_map: TempoMap,
+    pub signatures: SignatureTimeline,
+    pub source: Option<PathBuf>, // File in the user library; None for built-in songs
+    pub accompaniment: Vec<Note>, // Backing parts that are not practised
+    pub rests: Vec<WrittenRest>, // As the source wrote them; empty when they are inferred
+}
+
+impl Song {
+    // Builds a song from imported notes, classifying difficulty when not given
+    pub fn from_notes(id: String, title: String, artist: String, difficulty: Option<DifficultyLevel>, mut notes: Vec<Note>) -> Self {
+        hands::assign_hands(&mut notes);
+        let difficulty = difficulty.unwrap_or_else(|| DifficultyClassifier::classify_song(&notes));
+        
+        let mut song = Self {
+            id,
+            title,
+            artist,
+            difficulty,
+            notes,
+            pedal_markings: Vec::new(),
+            duration: 0.0,
+            tags: Vec::new(),
+            tempo_map: TempoMap::default(),
+            signatures: SignatureTimeline::default(),
+            source: None,
+            accompaniment: Vec::new(),
+            rests: Vec::new(),
+        };
+        song.update_duration();
+        song
+    }
+    
+    // Takes the tempo and signatures of the file the notes came from
+    pub fn with_timing(mut self, tempo_map: TempoMap, signatures: SignatureTimeline) -> Self {
+        self.tempo_map = tempo_map;
+        self.signatures = signatures;
+        self.update_duration();
+        self
+    }
+    
+    // Takes the rests the source wrote, which may run past the last note
+    pub fn with_rests(mut self, rests: Vec<WrittenRest>) -> Self {
+        self.rests = rests;
+        self.update_duration();
+        self
+    }
+    
+    // Beat at which the last note (or written rest) ends
+    pub fn end_beat(&self) -> f32 {
+        self.notes.iter()
+            .map(|note| note.end())
+            .chain(self.rests.iter().map(|rest| rest.position + rest.duration))
+            .fold(0.0, f32::max)
+    }
+    
+    // Replaces the tempo map with a single tempo
+    pub fn set_tempo(&mut self, bpm: f32) {
+        self.tempo_map = TempoMap::constant(bpm);
+        self.update_duration();
+    }
+    
+    fn update_duration(&mut self) {
+        self.duration = self.tempo_map.beats_to_seconds(self.end_beat());
+    }
+}
+
+// Lowercase identifier made of the text's letters and digits, e.g.
+// "Für Elise (easy)" -> "f_r_elise_easy"
+pub fn slugify(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>()
+        .split('_')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+// Some basic practice songs, written in ABC notation
+pub fn built_in_songs() -> Vec<Song> {
+    let songs: [(&str, &str, DifficultyLevel, &[&str]); 4] = [
+        ("c_scale", include_str!("../../assets/songs/c_scale.abc"), DifficultyLevel::Beginner, &["scales"]),
+        ("twinkle", include_str!("../../assets/songs/twinkle.abc"), DifficultyLevel::Beginner, &["nursery rhyme"]),
+        ("mary_lamb", include_str!("../../assets/songs/mary_lamb.abc"), DifficultyLevel::Beginner, &["nursery rhyme"]),
+        ("ledger_lines", include_str!("../../assets/songs/ledger_lines.abc"), DifficultyLevel::Intermediate, &["exercise", "ledger lines"]),
+    ];
+    songs.into_iter()
+        .filter_map(|(id, abc, difficulty, tags)| built_in_song(id, abc, difficulty, tags))
+        .collect()
+}
+
+// The title and artist come from the tune's T: and C: fields
+fn built_in_song(id: &str, abc: &str, difficulty: DifficultyLevel, tags: &[&str]) -> Option<Song> {
+    let parsed = match AbcParser::parse(abc.as_bytes()) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            log::error!("Failed to read built-in song {}: {:#}", id, e);
+            return None;
+        }
+    };
+    
+    let notes = parsed.notes_for(&parsed.default_channels(), None);
+    let title = parsed.title.clone().unwrap_or_else(|| id.to_string());
+    let artist = parsed.composer.clone().unwrap_or_default();
+    let mut song = Song::from_notes(id.to_string(), title, artist, Some(difficulty), notes)
+        .with_timing(parsed.tempo_map, parsed.signatures);
+    song.tags = tags.iter().map(|tag| tag.to_string()).collect();
+    Some(song)
 }
 
 pub struct MusicLibrary {
     songs: Vec<Song>,
     current_song_index: Option<usize>,
+    user_library_dir: Option<PathBuf>, // Songs on disk, including imported ones
+    scanner: LibraryScanner,
+    watcher: Option<RecommendedWatcher>,
+    last_disk_change: Arc<Mutex<Option<Instant>>>, // Set by the watcher thread
 }
 
 impl MusicLibrary {
@@ -21,90 +150,172 @@ impl MusicLibrary {
         let mut library = Self {
             songs: Vec::new(),
             current_song_index: None,
+            user_library_dir: storage::library_dir(),
+            scanner: LibraryScanner::new(),
+            watcher: None,
+            last_disk_change: Arc::new(Mutex::new(None)),
         };
         
         library.load_default_songs();
+        library.load_user_library();
+        library.watch_user_library();
         library
     }
     
-    fn load_default_songs(&mut self) {
-        // Create some basic practice songs
-        self.songs.push(Song {
-            id: "c_scale".to_string(),
-            title: "C Major Scale".to_string(),
-            artist: "Practice".to_string(),
-            difficulty: DifficultyLevel::Beginner,
-            notes: self.create_c_major_scale(),
-            duration: 8.0,
-        });
+    // Replaces every song from disk with a fresh scan, keeping the current
+    // selection if the song is still there
+    fn load_user_library(&mut self) {
+        let Some(dir) = self.user_library_dir.clone() else {
+            return;
+        };
         
-        self.songs.push(Song {
-            id: "twinkle".to_string(),
-            title: "Twinkle Twinkle Little Star".to_string(),
-            artist: "Traditional".to_string(),
-            difficulty: DifficultyLevel::Beginner,
-            notes: self.create_twinkle_twinkle(),
-            duration: 12.0,
+        let current_id = self.get_current_song().map(|song| song.id.clone());
+        self.songs.retain(|song| song.source.is_none());
+        
+        for mut song in self.scanner.scan(&dir) {
+            if self.get_song_by_id(&song.id).is_some() {
+                let id = self.unique_song_id(&song.id, None);
+                log::warn!("Song id '{}' is used more than once; loading {} as '{}'", song.id, song.title, id);
+                song.id = id;
+            }
+            self.songs.push(song);
+        }
+        
+        self.current_song_index = current_id.and_then(|id| self.songs.iter().position(|song| song.id == id));
+    }
+    
+    fn watch_user_library(&mut self) {
+        let Some(dir) = self.user_library_dir.clone() else {
+            return;
+        };
+        if let Err(e) = fs::create_dir_all(&dir) {
+            log::warn!("Failed to create song library {}: {}", dir.display(), e);
+            return;
+        }
+        
+        let last_disk_change = Arc::clone(&self.last_disk_change);
+        let watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+            match result {
+                Ok(event) if !event.kind.is_access() => {
+                    *last_disk_change.lock().unwrap() = Some(Instant::now());
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("Song library watcher error: {}", e),
+            }
+        }).and_then(|mut watcher| {
+            watcher.watch(&dir, RecursiveMode::Recursive)?;
+            Ok(watcher)
         });
         
-        self.songs.push(Song {
-            id: "mary_lamb".to_string(),
-            title: "Mary Had a Little Lamb".to_string(),
-            artist: "Traditional".to_string(),
-            difficulty: DifficultyLevel::Beginner,
-            notes: self.create_mary_had_a_little_lamb(),
-            duration: 10.0,
+        match watcher {
+            Ok(watcher) => self.watcher = Some(watcher),
+            Err(e) => log::warn!("Not watching {} for new songs: {}", dir.display(), e),
+        }
+    }
+    
+    // Rescans the library once changes on disk have settled. Returns true when a
+    // rescan happened.
+    pub fn poll_changes(&mut self) -> bool {
+        let settled = {
+            let mut last_disk_change = self.last_disk_change.lock().unwrap();
+            match *last_disk_change {
+                Some(changed_at) if changed_at.elapsed() >= RESCAN_DELAY => {
+                    *last_disk_change = None;
+                    true
+                }
+                _ => false,
+            }
+        };
+        
+        if settled {
+            log::info!("Song library changed on disk; rescanning");
+            self.load_user_library();
+        }
+        settled
+    }
+    
+    pub fn get_user_library_dir(&self) -> Option<&Path> {
+        self.user_library_dir.as_deref()
+    }
+    
+    // Copies a song file into the user library and records it in the manifest.
+    // Returns the new song's id.
+    pub fn import_song(&mut self, source: &Path, title: &str, artist: &str, parsed: &ParsedMidi, selection: &ChannelSelection) -> Result<String> {
+        let dir = self.user_library_dir.clone()
+            .ok_or_else(|| anyhow!("No data directory available for the song library"))?;
+        let notes = parsed.notes_for(&selection.play, Some(&selection.hands));
+        if notes.is_empty() {
+            return Err(anyhow!("The selected tracks contain no notes"));
+        }
+        
+        let id = self.unique_song_id(&slugify(title), Some(&dir));
+        let extension = source.extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_else(|| "mid".to_string());
+        let file = format!("{}.{}", id, extension);
+        let path = dir.join(&file);
+        
+        let mut song = Song::from_notes(id.clone(), title.to_string(), artist.to_string(), None, notes)
+            .with_timing(parsed.tempo_map.clone(), parsed.signatures.clone())
+            .with_rests(parsed.rests_for(&selection.play, Some(&selection.hands)));
+        song.source = Some(path.clone());
+        song.accompaniment = parsed.notes_in(&selection.accompaniment);
+        let entry = SongManifestEntry {
+            id: Some(id.clone()),
+            title: Some(song.title.clone()),
+            artist: song.artist.clone(),
+            difficulty: Some(song.difficulty),
+            channels: selection.play.clone(),
+            hands: Some(selection.hands.clone()).filter(|hands| !hands.is_empty()),
+            accompaniment: selection.accompaniment.clone(),
+            ..SongManifestEntry::for_file(file)
+        };
+        
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        fs::copy(source, &path)
+            .with_context(|| format!("Failed to copy {}", source.display()))?;
+        
+        // Without its manifest entry the copy would be scanned as a different
+        // song, so it goes again if the entry cannot be written
+        let manifest_path = dir.join(MANIFEST_FILE_NAME);
+        let recorded = LibraryManifest::load(&manifest_path).and_then(|mut manifest| {
+            manifest.songs.push(entry);
+            manifest.save(&manifest_path)
         });
+        if let Err(e) = recorded {
+            if let Err(remove_error) = fs::remove_file(&path) {
+                log::warn!("Failed to remove {}: {}", path.display(), remove_error);
+            }
+            return Err(e);
+        }
+        
+        log::info!("Imported '{}' as {} ({})", song.title, id, song.difficulty.as_str());
+        self.songs.push(song);
+        Ok(id)
     }
     
-    fn create_c_major_scale(&self) -> Vec<Note> {
-        vec![
-            Note::new(60, NoteType::Quarter, 0.0), // C
-            Note::new(62, NoteType::Quarter, 1.0), // D
-            Note::new(64, NoteType::Quarter, 2.0), // E
-            Note::new(65, NoteType::Quarter, 3.0), // F
-            Note::new(67, NoteType::Quarter, 4.0), // G
-            Note::new(69, NoteType::Quarter, 5.0), // A
-            Note::new(71, NoteType::Quarter, 6.0), // B
-            Note::new(72, NoteType::Quarter, 7.0), // C
-        ]
-    }
-    
-    fn create_twinkle_twinkle(&self) -> Vec<Note> {
-        vec![
-            Note::new(60, NoteType::Quarter, 0.0), // C
-            Note::new(60, NoteType::Quarter, 1.0), // C
-            Note::new(67, NoteType::Quarter, 2.0), // G
-            Note::new(67, NoteType::Quarter, 3.0), // G
-            Note::new(69, NoteType::Quarter, 4.0), // A
-            Note::new(69, NoteType::Quarter, 5.0), // A
-            Note::new(67, NoteType::Half, 6.0),    // G
-            Note::new(65, NoteType::Quarter, 8.0), // F
-            Note::new(65, NoteType::Quarter, 9.0), // F
-            Note::new(64, NoteType::Quarter, 10.0), // E
-            Note::new(64, NoteType::Quarter, 11.0), // E
-            Note::new(62, NoteType::Quarter, 12.0), // D
-            Note::new(62, NoteType::Quarter, 13.0), // D
-            Note::new(60, NoteType::Half, 14.0),    // C
-        ]
-    }
-    
-    fn create_mary_had_a_little_lamb(&self) -> Vec<Note> {
-        vec![
-            Note::new(64, NoteType::Quarter, 0.0), // E
-            Note::new(62, NoteType::Quarter, 1.0), // D
-            Note::new(60, NoteType::Quarter, 2.0), // C
-            Note::new(62, NoteType::Quarter, 3.0), // D
-            Note::new(64, NoteType::Quarter, 4.0), // E
-            Note::new(64, NoteType::Quarter, 5.0), // E
-            Note::new(64, NoteType::Half, 6.0),    // E
-            Note::new(62, NoteType::Quarter, 8.0), // D
-            Note::new(62, NoteType::Quarter, 9.0), // D
-            Note::new(62, NoteType::Half, 10.0),   // D
-            Note::new(64, NoteType::Quarter, 12.0), // E
-            Note::new(67, NoteType::Quarter, 13.0), // G
-            Note::new(67, NoteType::Half, 14.0),    // G
-        ]
+    // Suffixes `base` to avoid clashes with loaded songs and, when given, files
+    // already in the library directory
+    fn unique_song_id(&self, base: &str, dir: Option<&Path>) -> String {
+        let base = if base.is_empty() { "song" } else { base };
+        
+        let taken = |id: &str| {
+            self.get_song_by_id(id).is_some()
+                || dir.is_some_and(|dir| SONG_EXTENSIONS.iter().any(|extension| dir.join(format!("{}.{}", id, extension)).exists()))
+        };
+        
+        let mut id = base.to_string();
+        let mut suffix = 2;
+        while taken(&id) {
+            id = format!("{}_{}", base, suffix);
+            suffix += 1;
+        }
+        id
+    }
+    
+    fn load_default_songs(&mut self) {
+        self.songs.extend(built_in_songs());
     }
     
     pub fn get_songs(&self) -> &[Song] {
@@ -130,6 +341,11 @@ impl MusicLibrary {
         }
     }
     
+    pub fn select_song_by_id(&mut self, id: &str) -> Option<&Song> {
+        let index = self.songs.iter().position(|song| song.id == id)?;
+        self.select_song(index)
+    }
+    
     pub fn get_current_song(&self) -> Option<&Song> {
         self.current_song_index.map(|index| &self.songs[index])
     }
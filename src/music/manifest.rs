@@ -0,0 +1,126 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::notation::Hand;
+use crate::storage;
+use super::DifficultyLevel;
+
+const MANIFEST_SCHEMA_VERSION: u32 = 1;
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+// Which (track, channel) pairs of a MIDI file each hand plays
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HandAssignment {
+    #[serde(default)]
+    pub right: Vec<(usize, u8)>,
+    #[serde(default)]
+    pub left: Vec<(usize, u8)>,
+}
+
+impl HandAssignment {
+    pub fn is_empty(&self) -> bool {
+        self.right.is_empty() && self.left.is_empty()
+    }
+    
+    pub fn hand_for(&self, track: usize, channel: u8) -> Option<Hand> {
+        if self.right.contains(&(track, channel)) {
+            Some(Hand::Right)
+        } else if self.left.contains(&(track, channel)) {
+            Some(Hand::Left)
+        } else {
+            None
+        }
+    }
+}
+
+// One song in a library directory; `file` is relative to the directory. Only
+// `file` is required, everything else has a sensible fallback.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SongManifestEntry {
+    #[serde(default)]
+    pub id: Option<String>, // Derived from the file path when missing
+    #[serde(default)]
+    pub title: Option<String>, // File name when missing
+    #[serde(default)]
+    pub artist: String,
+    #[serde(default)]
+    pub difficulty: Option<DifficultyLevel>, // None = classify from the notes
+    pub file: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub hands: Option<HandAssignment>,
+    #[serde(default)]
+    pub tempo: Option<f32>, // Quarter-note BPM
+    #[serde(default)]
+    pub channels: Vec<(usize, u8)>, // (track, channel) pairs to include; empty = the hands, or all but drums
+    #[serde(default)]
+    pub accompaniment: Vec<(usize, u8)>, // Pairs kept as backing rather than practised
+}
+
+impl SongManifestEntry {
+    pub fn for_file(file: String) -> Self {
+        Self {
+            id: None,
+            title: None,
+            artist: String::new(),
+            difficulty: None,
+            file,
+            tags: Vec::new(),
+            hands: None,
+            tempo: None,
+            channels: Vec::new(),
+            accompaniment: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryManifest {
+    pub version: u32,
+    #[serde(default)]
+    pub songs: Vec<SongManifestEntry>,
+}
+
+impl Default for LibraryManifest {
+    fn default() -> Self {
+        Self {
+            version: MANIFEST_SCHEMA_VERSION,
+            songs: Vec::new(),
+        }
+    }
+}
+
+impl LibraryManifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let manifest: Self = serde_json::from_str(&contents)
+            .with_context(|| format!("Malformed library manifest {}", path.display()))?;
+        
+        if manifest.version > MANIFEST_SCHEMA_VERSION {
+            bail!(
+                "Library manifest version {} is newer than supported version {}",
+                manifest.version,
+                MANIFEST_SCHEMA_VERSION
+            );
+        }
+        
+        Ok(manifest)
+    }
+    
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let manifest = Self {
+            version: MANIFEST_SCHEMA_VERSION,
+            songs: self.songs.clone(),
+        };
+        let json = serde_json::to_string_pretty(&manifest)?;
+        storage::write_atomic(path, json.as_bytes())
+    }
+}
\ No newline at end of file
@@ -1,7 +1,18 @@
 pub mod library;
 pub mod parser;
 pub mod difficulty;
+pub mod manifest;
+pub mod scanner;
+pub mod tempo;
+pub mod hands;
+pub mod musicxml;
+pub mod abc;
+pub mod export;
+pub mod instruments;
 
 pub use library::MusicLibrary;
-pub use parser::MidiParser;
-pub use difficulty::{DifficultyLevel, DifficultyClassifier};
\ No newline at end of file
+pub use parser::{ChannelSelection, MidiParser, ParsedMidi};
+pub use musicxml::MusicXmlParser;
+pub use abc::AbcParser;
+pub use difficulty::{DifficultyLevel, DifficultyClassifier};
+pub use tempo::TempoMap;
\ No newline at end of file
@@ -0,0 +1,672 @@
+use anyhow::{anyhow, bail, Context, Result};
+use roxmltree::{Document, Node, ParsingOptions};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Cursor, Read};
+use zip::ZipArchive;
+
+use crate::notation::{Articulation, Hand, KeySignature, Note, SignatureTimeline, SpelledPitch, TimeSignature, Tuplet, WrittenRest};
+use crate::notation::duration::{grid_step, quantize};
+use super::parser::{MidiChannelInfo, MidiTrackInfo, ParsedMidi, ParsedNote, ParsedRest};
+use super::tempo::{TempoChange, TempoMap};
+
+// Reads partwise MusicXML, plain or compressed (.mxl), into the same form as a
+// parsed MIDI file so the library can treat both alike. Each part becomes a
+// track and each of its staves a channel. Unlike MIDI, the notes keep what the
+// engraver wrote: spelling, staff (and so hand), voice, articulations,
+// fingering, rests and tuplets. Ties are joined into single notes. Repeats are
+// not expanded.
+
+// Where a compressed file says which of its entries is the score
+const CONTAINER_PATH: &str = "META-INF/container.xml";
+
+const STEP_NAMES: [&str; 7] = ["C", "D", "E", "F", "G", "A", "B"];
+
+const POSITION_EPSILON: f32 = 0.001;
+
+// Everything read from one part, with positions in quarter-note beats from the
+// start of its first measure
+#[derive(Debug, Default)]
+struct PartContents {
+    notes: Vec<(u8, Note)>, // Staff (from 0) and note
+    rests: Vec<(u8, WrittenRest)>,
+    first_measure_length: f32,
+    time_changes: Vec<(f32, TimeSignature)>,
+    key_changes: Vec<(f32, KeySignature)>,
+    tempo_changes: Vec<TempoChange>,
+}
+
+// A note whose tie has not been closed yet, by staff, voice and pitch
+type TieKey = (u8, Option<u8>, u8);
+
+// Where the open tuplet bracket of a staff and voice starts, and the beat its
+// notes are expected to fill up to when the source does not mark its end
+type OpenTuplet = (f32, f32);
+
+// Walks the measures of one part, keeping track of the musical time
+struct PartReader {
+    divisions: f32, // Per quarter note
+    time_signature: TimeSignature,
+    measure_start: f32,
+    time: f32,
+    chord_start: f32, // Start of the previous note, shared by <chord/> notes
+    staves: u8,
+    clefs: HashMap<u8, String>, // First clef sign of each staff
+    open_ties: HashMap<TieKey, usize>, // Index into `contents.notes`
+    open_tuplets: HashMap<(u8, Option<u8>), OpenTuplet>,
+    contents: PartContents,
+}
+
+impl PartReader {
+    fn new() -> Self {
+        Self {
+            divisions: 1.0,
+            time_signature: TimeSignature::default(),
+            measure_start: 0.0,
+            time: 0.0,
+            chord_start: 0.0,
+            staves: 1,
+            clefs: HashMap::new(),
+            open_ties: HashMap::new(),
+            open_tuplets: HashMap::new(),
+            contents: PartContents::default(),
+        }
+    }
+    
+    fn read(mut self, part: Node) -> PartContents {
+        for (index, measure) in part.children().filter(|node| node.has_tag_name("measure")).enumerate() {
+            let reached = self.read_measure(measure);
+            let length = reached - self.measure_start;
+            
+            // A pickup is shorter than the meter. Later measures keep the meter's
+            // length so the barlines drawn from the time signature stay in line
+            // with the music, unless they are overfull.
+            let length = if index == 0 {
+                self.contents.first_measure_length = length;
+                if length > POSITION_EPSILON { length } else { self.time_signature.beats_per_measure() }
+            } else {
+                length.max(self.time_signature.beats_per_measure())
+            };
+            self.measure_start += length;
+            self.time = self.measure_start;
+        }
+        
+        self.assign_hands();
+        self.contents
+    }
+    
+    // Returns the furthest beat any voice reached
+    fn read_measure(&mut self, measure: Node) -> f32 {
+        let mut reached = self.time;
+        for child in measure.children().filter(Node::is_element) {
+            match child.tag_name().name() {
+                "attributes" => self.read_attributes(child),
+                "note" => self.read_note(child),
+                "backup" => self.time -= self.duration_of(child),
+                "forward" => self.time += self.duration_of(child),
+                // A direction may take effect a little after where it is written
+                "direction" => {
+                    if let Some(sound) = child_element(child, "sound") {
+                        let offset = child_number::<f32>(child, "offset").unwrap_or(0.0) / self.divisions;
+                        self.read_sound(sound, self.time + offset);
+                    }
+                }
+                "sound" => self.read_sound(child, self.time),
+                _ => {}
+            }
+            reached = reached.max(self.time);
+        }
+        reached
+    }
+    
+    fn read_attributes(&mut self, attributes: Node) {
+        if let Some(divisions) = child_number::<f32>(attributes, "divisions").filter(|&divisions| divisions > 0.0) {
+            self.divisions = divisions;
+        }
+        if let Some(staves) = child_number::<u8>(attributes, "staves") {
+            self.staves = staves.max(1);
+        }
+        
+        if let Some(key) = child_element(attributes, "key") {
+            if let Some(fifths) = child_number::<i8>(key, "fifths") {
+                let minor = child_text(key, "mode") == Some("minor");
+                self.contents.key_changes.push((self.time, KeySignature::new(fifths, minor)));
+            }
+        }
+        
+        // Additive meters like 3+2/8 are kept as their total
+        if let Some(time) = child_element(attributes, "time") {
+            let beats: Option<u32> = child_text(time, "beats")
+                .and_then(|beats| beats.split('+').map(|part| part.trim().parse::<u32>().ok()).sum());
+            let beat_type = child_number::<u8>(time, "beat-type");
+            if let (Some(beats), Some(beat_type)) = (beats, beat_type) {
+                self.time_signature = TimeSignature::new(beats.min(u8::MAX as u32) as u8, beat_type);
+                self.contents.time_changes.push((self.time, self.time_signature));
+            }
+        }
+        
+        for clef in attributes.children().filter(|node| node.has_tag_name("clef")) {
+            let staff = clef.attribute("number").and_then(|number| number.parse().ok()).unwrap_or(1);
+            if let Some(sign) = child_text(clef, "sign") {
+                self.clefs.entry(staff).or_insert_with(|| sign.to_string());
+            }
+        }
+    }
+    
+    fn read_sound(&mut self, sound: Node, beat: f32) {
+        if let Some(bpm) = sound.attribute("tempo").and_then(|tempo| tempo.parse::<f32>().ok()).filter(|&bpm| bpm > 0.0) {
+            self.contents.tempo_changes.push(TempoChange { beat, bpm });
+        }
+    }
+    
+    fn read_note(&mut self, element: Node) {
+        // Grace notes take no time and are not scored
+        if child_element(element, "grace").is_some() {
+            return;
+        }
+        
+        let duration = self.duration_of(element);
+        let start = if child_element(element, "chord").is_some() {
+            self.chord_start
+        } else {
+            self.chord_start = self.time;
+            self.time += duration;
+            self.chord_start
+        };
+        
+        // Cue notes are printed small for reference and not played
+        if child_element(element, "cue").is_some() {
+            return;
+        }
+        let staff = child_number::<u8>(element, "staff").unwrap_or(1).max(1) - 1;
+        let voice = child_number::<u8>(element, "voice");
+        let tuplet = self.read_tuplet(element, start, duration, staff, voice);
+        
+        if let Some(rest) = child_element(element, "rest") {
+            // Hidden rests only hold a voice's place
+            if element.attribute("print-object") != Some("no") {
+                // A measure rest may be written without a value
+                let full_measure = rest.attribute("measure") == Some("yes")
+                    || (child_element(element, "type").is_none() && duration >= self.time_signature.beats_per_measure() - POSITION_EPSILON);
+                self.contents.rests.push((staff, WrittenRest {
+                    position: start,
+                    duration,
+                    hand: None,
+                    voice,
+                    tuplet,
+                    full_measure,
+                }));
+            }
+            return;
+        }
+        let Some(spelling) = child_element(element, "pitch").and_then(read_pitch) else {
+            return;
+        };
+        let Some(pitch) = spelling.midi_pitch() else {
+            return;
+        };
+        
+        let ties: Vec<&str> = element.children()
+            .filter(|node| node.has_tag_name("tie"))
+            .filter_map(|tie| tie.attribute("type"))
+            .collect();
+        let key = (staff, voice, pitch);
+        
+        // The end of a tie lengthens the note it continues
+        if ties.contains(&"stop") {
+            if let Some(&index) = self.open_ties.get(&key) {
+                let note = &mut self.contents.notes[index].1;
+                if (note.end() - start).abs() < POSITION_EPSILON {
+                    note.duration = start + duration - note.position;
+                    if !ties.contains(&"start") {
+                        self.open_ties.remove(&key);
+                    }
+                    return;
+                }
+            }
+        }
+        
+        let mut note = Note::with_duration(pitch, start, duration);
+        note.spelling = Some(spelling);
+        note.voice = voice;
+        note.tuplet = tuplet;
+        if let Some(notations) = child_element(element, "notations") {
+            read_notations(notations, &mut note);
+        }
+        
+        if ties.contains(&"start") {
+            self.open_ties.insert(key, self.contents.notes.len());
+        } else {
+            self.open_ties.remove(&key);
+        }
+        self.contents.notes.push((staff, note));
+    }
+    
+    // The tuplet a note or rest belongs to. Its bracket runs from a tuplet
+    // start to a tuplet stop; sources that leave those out get brackets of
+    // `actual` notes like the first.
+    fn read_tuplet(&mut self, element: Node, start: f32, duration: f32, staff: u8, voice: Option<u8>) -> Option<Tuplet> {
+        let modification = child_element(element, "time-modification")?;
+        let actual = child_number::<u8>(modification, "actual-notes").filter(|&actual| actual > 0)?;
+        let normal = child_number::<u8>(modification, "normal-notes").filter(|&normal| normal > 0)?;
+        let marks: Vec<&str> = element.children()
+            .filter(|node| node.has_tag_name("notations"))
+            .flat_map(|notations| notations.children().filter(|node| node.has_tag_name("tuplet")))
+            .filter_map(|tuplet| tuplet.attribute("type"))
+            .collect();
+        
+        let key = (staff, voice);
+        let bracket_start = match self.open_tuplets.get(&key) {
+            Some(&(bracket_start, end)) if !marks.contains(&"start") && start < end - POSITION_EPSILON => bracket_start,
+            _ => {
+                self.open_tuplets.insert(key, (start, start + duration * actual as f32));
+                start
+            }
+        };
+        if marks.contains(&"stop") {
+            self.open_tuplets.remove(&key);
+        }
+        Some(Tuplet { actual, normal, start: bracket_start })
+    }
+    
+    fn duration_of(&self, element: Node) -> f32 {
+        child_number::<f32>(element, "duration").unwrap_or(0.0) / self.divisions
+    }
+    
+    // A piano part has a staff per hand. A part on a single staff is played by
+    // the hand its clef suggests, and anything else is left to the voice
+    // heuristic.
+    fn assign_hands(&mut self) {
+        let staves = self.contents.notes.iter()
+            .map(|(staff, _)| staff + 1)
+            .max()
+            .unwrap_or(1)
+            .max(self.staves);
+        
+        let hand_for = |staff: u8| {
+            if staves > 1 {
+                return match staff {
+                    0 => Some(Hand::Right),
+                    1 => Some(Hand::Left),
+                    _ => None,
+                };
+            }
+            match self.clefs.get(&(staff + 1)).map(String::as_str) {
+                Some("G") => Some(Hand::Right),
+                Some("F") => Some(Hand::Left),
+                _ => None,
+            }
+        };
+        
+        for (staff, note) in &mut self.contents.notes {
+            note.hand = hand_for(*staff);
+        }
+        for (staff, rest) in &mut self.contents.rests {
+            rest.hand = hand_for(*staff);
+        }
+    }
+}
+
+fn read_pitch(pitch: Node) -> Option<SpelledPitch> {
+    let step = child_text(pitch, "step")?;
+    let step = STEP_NAMES.iter().position(|&name| name == step)? as u8;
+    // Microtones are rounded to the nearest semitone
+    let alter = child_number::<f32>(pitch, "alter").unwrap_or(0.0).round() as i8;
+    let octave = child_number::<i8>(pitch, "octave")?;
+    Some(SpelledPitch { step, alter, octave })
+}
+
+fn read_notations(notations: Node, note: &mut Note) {
+    for child in notations.children().filter(Node::is_element) {
+        match child.tag_name().name() {
+            "fermata" => note.articulations.push(Articulation::Fermata),
+            "articulations" => {
+                for articulation in child.children().filter(Node::is_element) {
+                    match articulation.tag_name().name() {
+                        "staccato" => note.articulations.push(Articulation::Staccato),
+                        "staccatissimo" | "spiccato" => note.articulations.push(Articulation::Staccatissimo),
+                        "tenuto" => note.articulations.push(Articulation::Tenuto),
+                        "accent" => note.articulations.push(Articulation::Accent),
+                        "strong-accent" => note.articulations.push(Articulation::Marcato),
+                        "detached-legato" => note.articulations.extend([Articulation::Tenuto, Articulation::Staccato]),
+                        _ => {}
+                    }
+                }
+            }
+            "technical" => {
+                if let Some(finger) = child_number::<u8>(child, "fingering").filter(|finger| (1..=5).contains(finger)) {
+                    note.fingering = Some(finger);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn child_element<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    child_element(node, name)?.text().map(str::trim)
+}
+
+fn child_number<T: std::str::FromStr>(node: Node, name: &str) -> Option<T> {
+    child_text(node, name)?.parse().ok()
+}
+
+pub struct MusicXmlParser;
+
+impl MusicXmlParser {
+    // Accepts both plain MusicXML and the zipped .mxl form
+    pub fn parse(data: &[u8]) -> Result<ParsedMidi> {
+        let text = if data.starts_with(b"PK\x03\x04") {
+            Self::read_compressed(data)?
+        } else {
+            String::from_utf8_lossy(data).into_owned()
+        };
+        Self::parse_document(text.trim_start_matches('\u{FEFF}'))
+    }
+    
+    fn read_compressed(data: &[u8]) -> Result<String> {
+        let mut archive = ZipArchive::new(Cursor::new(data))
+            .context("Not a valid compressed MusicXML file")?;
+        
+        // The container lists the score first; without one, take the first XML
+        // file outside META-INF
+        let score_path = match Self::read_entry(&mut archive, CONTAINER_PATH) {
+            Ok(container) => {
+                let document = Document::parse(&container).context("Invalid MusicXML container")?;
+                document.descendants()
+                    .find(|node| node.has_tag_name("rootfile"))
+                    .and_then(|rootfile| rootfile.attribute("full-path"))
+                    .map(str::to_owned)
+                    .ok_or_else(|| anyhow!("The MusicXML container names no score"))?
+            }
+            Err(_) => archive.file_names()
+                .find(|name| !name.starts_with("META-INF/") && (name.ends_with(".xml") || name.ends_with(".musicxml")))
+                .map(str::to_owned)
+                .ok_or_else(|| anyhow!("No score in the compressed MusicXML file"))?,
+        };
+        Self::read_entry(&mut archive, &score_path)
+    }
+    
+    fn read_entry(archive: &mut ZipArchive<Cursor<&[u8]>>, name: &str) -> Result<String> {
+        let mut entry = archive.by_name(name)
+            .with_context(|| format!("Missing {} in the compressed MusicXML file", name))?;
+        let mut text = String::new();
+        entry.read_to_string(&mut text)
+            .with_context(|| format!("Failed to read {} from the compressed MusicXML file", name))?;
+        Ok(text)
+    }
+    
+    fn parse_document(text: &str) -> Result<ParsedMidi> {
+        // Notation programs write the MusicXML doctype
+        let options = ParsingOptions { allow_dtd: true, ..ParsingOptions::default() };
+        let document = Document::parse_with_options(text, options).context("Invalid XML")?;
+        let score = document.root_element();
+        if score.has_tag_name("score-timewise") {
+            bail!("timewise MusicXML is not supported; export the score as partwise MusicXML");
+        }
+        if !score.has_tag_name("score-partwise") {
+            bail!("not a MusicXML score");
+        }
+        
+        let part_names: HashMap<&str, &str> = child_element(score, "part-list")
+            .into_iter()
+            .flat_map(|list| list.children().filter(|node| node.has_tag_name("score-part")))
+            .filter_map(|part| Some((part.attribute("id")?, child_text(part, "part-name")?)))
+            .collect();
+        
+        let parts: Vec<(Option<String>, PartContents)> = score.children()
+            .filter(|node| node.has_tag_name("part"))
+            .map(|part| {
+                let name = part.attribute("id")
+                    .and_then(|id| part_names.get(id))
+                    .map(|name| name.to_string())
+                    .filter(|name| !name.is_empty());
+                (name, PartReader::new().read(part))
+            })
+            .collect();
+        let Some((_, first)) = parts.first() else {
+            bail!("the score has no parts");
+        };
+        
+        // Signatures and tempo come from the first part. A pickup is moved to the
+        // end of a full first measure, since measures are counted from beat 0.
+        let opening_meter = first.time_changes.first()
+            .filter(|(beat, _)| *beat < POSITION_EPSILON)
+            .map_or_else(TimeSignature::default, |(_, signature)| *signature);
+        let pickup_length = first.first_measure_length;
+        let shift = if pickup_length > POSITION_EPSILON && pickup_length < opening_meter.beats_per_measure() - POSITION_EPSILON {
+            opening_meter.beats_per_measure() - pickup_length
+        } else {
+            0.0
+        };
+        let shifted = |beat: f32| if beat < POSITION_EPSILON { beat } else { beat + shift };
+        
+        let mut signatures = SignatureTimeline::default();
+        for &(beat, signature) in &first.time_changes {
+            signatures.add_time_signature(shifted(beat), signature);
+        }
+        for &(beat, signature) in &first.key_changes {
+            signatures.add_key_signature(shifted(beat), signature);
+        }
+        let tempo_map = TempoMap::from_changes(
+            first.tempo_changes.iter()
+                .map(|change| TempoChange { beat: shifted(change.beat), bpm: change.bpm })
+                .collect(),
+        );
+        
+        // Positions are snapped to the thirty-second grid, as MIDI notes are,
+        // or to the tuplet's own grid
+        let shift_tuplet = |tuplet: Option<Tuplet>| tuplet.map(|tuplet| Tuplet {
+            start: quantize(tuplet.start + shift, None),
+            ..tuplet
+        });
+        
+        let mut tracks = Vec::new();
+        let mut notes = Vec::new();
+        let mut rests = Vec::new();
+        for (index, (name, contents)) in parts.into_iter().enumerate() {
+            let channels: BTreeSet<u8> = contents.notes.iter().map(|(staff, _)| *staff).collect();
+            tracks.push(MidiTrackInfo {
+                index,
+                name,
+                channels: channels.into_iter()
+                    .map(|staff| {
+                        let count = contents.notes.iter().filter(|(note_staff, _)| *note_staff == staff).count();
+                        MidiChannelInfo::notated(staff, count)
+                    })
+                    .collect(),
+                note_count: contents.notes.len(),
+            });
+            notes.extend(contents.notes.into_iter().map(|(staff, mut note)| {
+                let tuplet = shift_tuplet(note.tuplet);
+                let position = quantize(note.position + shift, tuplet);
+                let end = quantize(note.end() + shift, tuplet).max(position + grid_step(tuplet));
+                note.position = position;
+                note.duration = end - position;
+                note.tuplet = tuplet;
+                ParsedNote {
+                    track: index,
+                    channel: staff,
+                    note,
+                }
+            }));
+            rests.extend(contents.rests.into_iter().map(|(staff, mut rest)| {
+                let tuplet = shift_tuplet(rest.tuplet);
+                let position = quantize(rest.position + shift, tuplet);
+                let end = quantize(rest.position + rest.duration + shift, tuplet).max(position + grid_step(tuplet));
+                rest.position = position;
+                rest.duration = end - position;
+                rest.tuplet = tuplet;
+                ParsedRest {
+                    track: index,
+                    channel: staff,
+                    rest,
+                }
+            }));
+        }
+        
+        let title = child_element(score, "work")
+            .and_then(|work| child_text(work, "work-title"))
+            .or_else(|| child_text(score, "movement-title"))
+            .filter(|title| !title.is_empty())
+            .map(str::to_owned);
+        let composer = child_element(score, "identification")
+            .into_iter()
+            .flat_map(|identification| identification.children().filter(|node| node.has_tag_name("creator")))
+            .find(|creator| creator.attribute("type") == Some("composer"))
+            .and_then(|creator| creator.text())
+            .map(str::trim)
+            .filter(|composer| !composer.is_empty())
+            .map(str::to_owned);
+        
+        Ok(ParsedMidi {
+            tracks,
+            notes,
+            rests,
+            tempo_map,
+            signatures,
+            title,
+            composer,
+            warnings: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // A one-part score with a measure of 4/4 on two staves, a division being a
+    // sixth of a beat. The measure is always full, so it is never taken for a
+    // pickup.
+    fn parse(measure: &str) -> ParsedMidi {
+        let score = format!(
+            r#"<score-partwise version="4.0">
+                <part-list><score-part id="P1"><part-name>Piano</part-name></score-part></part-list>
+                <part id="P1">
+                    <measure number="1">
+                        <attributes>
+                            <divisions>6</divisions>
+                            <time><beats>4</beats><beat-type>4</beat-type></time>
+                            <staves>2</staves>
+                        </attributes>
+                        <forward><duration>24</duration></forward>
+                        <backup><duration>24</duration></backup>
+                        {}
+                    </measure>
+                </part>
+            </score-partwise>"#,
+            measure,
+        );
+        MusicXmlParser::parse(score.as_bytes()).unwrap()
+    }
+    
+    fn note(step: &str, octave: u8, duration: u32, staff: u8, extra: &str) -> String {
+        format!(
+            "<note>{}<pitch><step>{}</step><octave>{}</octave></pitch><duration>{}</duration><staff>{}</staff></note>",
+            extra, step, octave, duration, staff,
+        )
+    }
+    
+    // Pitch, position and duration of each note, in time order
+    fn timing(parsed: &ParsedMidi) -> Vec<(u8, f32, f32)> {
+        let mut notes: Vec<(u8, f32, f32)> = parsed.notes.iter()
+            .map(|parsed| (parsed.note.pitch, parsed.note.position, parsed.note.duration))
+            .collect();
+        notes.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        notes
+    }
+    
+    #[test]
+    fn backup_and_forward_move_the_time() {
+        let parsed = parse(&[
+            note("C", 5, 12, 1, ""),
+            note("D", 5, 12, 1, ""),
+            "<backup><duration>24</duration></backup>".to_string(),
+            "<forward><duration>6</duration></forward>".to_string(),
+            note("C", 3, 18, 2, ""),
+        ].concat());
+        
+        assert_eq!(timing(&parsed), vec![(72, 0.0, 2.0), (48, 1.0, 3.0), (74, 2.0, 2.0)]);
+    }
+    
+    #[test]
+    fn chord_notes_share_the_start_of_the_first() {
+        let parsed = parse(&[
+            note("C", 4, 6, 1, ""),
+            note("E", 4, 6, 1, "<chord/>"),
+            note("G", 4, 6, 1, "<chord/>"),
+            note("A", 4, 6, 1, ""),
+        ].concat());
+        
+        assert_eq!(timing(&parsed), vec![(60, 0.0, 1.0), (64, 0.0, 1.0), (67, 0.0, 1.0), (69, 1.0, 1.0)]);
+    }
+    
+    #[test]
+    fn ties_join_into_one_note() {
+        let parsed = parse(&[
+            note("E", 4, 6, 1, r#"<tie type="start"/>"#),
+            note("E", 4, 12, 1, r#"<tie type="stop"/><tie type="start"/>"#),
+            note("E", 4, 3, 1, r#"<tie type="stop"/>"#),
+            note("E", 4, 3, 1, ""),
+        ].concat());
+        
+        assert_eq!(timing(&parsed), vec![(64, 0.0, 3.5), (64, 3.5, 0.5)]);
+    }
+    
+    #[test]
+    fn staves_become_channels_played_by_each_hand() {
+        let parsed = parse(&[
+            note("C", 5, 24, 1, ""),
+            "<backup><duration>24</duration></backup>".to_string(),
+            note("C", 3, 24, 2, ""),
+        ].concat());
+        
+        let channels: Vec<u8> = parsed.tracks[0].channels.iter().map(|channel| channel.channel).collect();
+        assert_eq!(channels, vec![0, 1]);
+        let hands: Vec<(u8, u8, Option<Hand>)> = parsed.notes.iter()
+            .map(|parsed| (parsed.channel, parsed.note.pitch, parsed.note.hand))
+            .collect();
+        assert_eq!(hands, vec![(0, 72, Some(Hand::Right)), (1, 48, Some(Hand::Left))]);
+    }
+    
+    #[test]
+    fn rests_are_kept_as_written() {
+        let parsed = parse(&[
+            "<note><rest/><duration>9</duration><voice>1</voice><type>quarter</type><dot/><staff>1</staff></note>",
+            "<note print-object=\"no\"><rest/><duration>3</duration><voice>1</voice><staff>1</staff></note>",
+            &note("C", 5, 12, 1, ""),
+            "<backup><duration>24</duration></backup>",
+            "<note><rest measure=\"yes\"/><duration>24</duration><voice>5</voice><staff>2</staff></note>",
+        ].concat());
+        
+        let rests: Vec<(u8, f32, f32, Option<Hand>, bool)> = parsed.rests.iter()
+            .map(|parsed| (parsed.channel, parsed.rest.position, parsed.rest.duration, parsed.rest.hand, parsed.rest.full_measure))
+            .collect();
+        assert_eq!(rests, vec![(0, 0.0, 1.5, Some(Hand::Right), false), (1, 0.0, 4.0, Some(Hand::Left), true)]);
+    }
+    
+    #[test]
+    fn tuplets_keep_their_exact_positions() {
+        let triplet = "<time-modification><actual-notes>3</actual-notes><normal-notes>2</normal-notes></time-modification>";
+        let parsed = parse(&[
+            note("C", 5, 6, 1, ""),
+            note("D", 5, 2, 1, &format!("{}<notations><tuplet type=\"start\"/></notations>", triplet)),
+            note("E", 5, 2, 1, triplet),
+            note("F", 5, 2, 1, &format!("{}<notations><tuplet type=\"stop\"/></notations>", triplet)),
+            note("G", 5, 2, 1, triplet),
+        ].concat());
+        
+        let third = 1.0 / 3.0;
+        for (parsed, (position, bracket)) in parsed.notes[1..].iter().zip([(1.0, 1.0), (1.0 + third, 1.0), (1.0 + 2.0 * third, 1.0), (2.0, 2.0)]) {
+            assert!((parsed.note.position - position).abs() < 1e-4, "{} at {}", parsed.note.pitch, parsed.note.position);
+            assert!((parsed.note.duration - third).abs() < 1e-4);
+            let tuplet = parsed.note.tuplet.unwrap();
+            assert_eq!((tuplet.actual, tuplet.normal, tuplet.start), (3, 2, bracket));
+        }
+        assert_eq!(parsed.notes[0].note.tuplet, None);
+    }
+}
\ No newline at end of file
@@ -1,83 +1,557 @@
-use midly::{Smf, Track, TrackEventKind, MidiMessage};
-use crate::notation::{Note, NoteType};
-use std::collections::HashMap;
+use anyhow::{Context, Result};
+use midly::{Smf, Timing, Track, TrackEventKind, MidiMessage, MetaMessage};
+use crate::notation::{Hand, KeySignature, Note, SignatureTimeline, TimeSignature, WrittenRest, DURATION_GRID};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use super::instruments::PERCUSSION_CHANNEL;
+use super::manifest::HandAssignment;
+use super::tempo::{TempoChange, TempoMap, DEFAULT_TEMPO_BPM};
+
+// One channel of a track that carries notes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MidiChannelInfo {
+    pub channel: u8, // 0-15; the staff (from 0) for MusicXML
+    pub program: Option<u8>, // General MIDI instrument, from the channel's first program change
+    pub note_count: usize,
+    pub percussion: bool, // Drums, which are left out unless chosen
+}
+
+impl MidiChannelInfo {
+    // A channel of a notated score, which names no instrument
+    pub fn notated(channel: u8, note_count: usize) -> Self {
+        Self {
+            channel,
+            program: None,
+            note_count,
+            percussion: false,
+        }
+    }
+}
+
+// Summary of one SMF track (or MusicXML part), for choosing what to import
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MidiTrackInfo {
+    pub index: usize,
+    pub name: Option<String>,
+    pub channels: Vec<MidiChannelInfo>,
+    pub note_count: usize,
+}
+
+// How the parts of a parsed file are used: the (track, channel) pairs to
+// practise, optionally with the hand that plays each, and the pairs kept as
+// backing accompaniment
+#[derive(Debug, Clone, Default)]
+pub struct ChannelSelection {
+    pub play: Vec<(usize, u8)>,
+    pub hands: HandAssignment,
+    pub accompaniment: Vec<(usize, u8)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParsedNote {
+    pub track: usize,
+    pub channel: u8,
+    pub note: Note,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParsedRest {
+    pub track: usize,
+    pub channel: u8,
+    pub rest: WrittenRest,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParsedMidi {
+    pub tracks: Vec<MidiTrackInfo>,
+    pub notes: Vec<ParsedNote>,
+    #[serde(default)]
+    pub rests: Vec<ParsedRest>, // Only engraved sources write rests; MIDI leaves them to be inferred
+    pub tempo_map: TempoMap,
+    pub signatures: SignatureTimeline,
+    #[serde(default)]
+    pub title: Option<String>, // From the file's own metadata, where the format has it
+    #[serde(default)]
+    pub composer: Option<String>,
+    #[serde(default)]
+    pub warnings: Vec<String>, // What had to be repaired or left out while reading
+}
+
+impl ParsedMidi {
+    // Notes from the given (track, channel) pairs, in time order. Notes take
+    // their hand from `hands`, or from the hands guessed from the tracks.
+    pub fn notes_for(&self, selection: &[(usize, u8)], hands: Option<&HandAssignment>) -> Vec<Note> {
+        let hands = self.hands_or_guessed(selection, hands);
+        let mut notes: Vec<Note> = self.notes.iter()
+            .filter(|parsed| selection.contains(&(parsed.track, parsed.channel)))
+            .map(|parsed| {
+                let mut note = parsed.note.clone();
+                note.hand = note.hand.or(hands.hand_for(parsed.track, parsed.channel));
+                note
+            })
+            .collect();
+        notes.sort_by(|a, b| a.position.total_cmp(&b.position));
+        notes
+    }
+    
+    // Piano files usually keep each hand in its own track: use the track names
+    // when they say which is which, and otherwise give the higher of exactly
+    // two parts to the right hand. Anything else is left to the voice heuristic.
+    pub fn guess_hands(&self, selection: &[(usize, u8)]) -> HandAssignment {
+        let mut hands = HandAssignment::default();
+        let parts: Vec<(usize, u8)> = self.all_channels().into_iter()
+            .filter(|part| selection.contains(part))
+            .collect();
+        
+        for &(track, channel) in &parts {
+            let name = self.tracks.iter()
+                .find(|info| info.index == track)
+                .and_then(|info| info.name.as_deref());
+            match name.and_then(hand_from_name) {
+                Some(Hand::Right) => hands.right.push((track, channel)),
+                Some(Hand::Left) => hands.left.push((track, channel)),
+                None => {}
+            }
+        }
+        if !hands.is_empty() || parts.len() != 2 {
+            return hands;
+        }
+        
+        let mean_pitch = |part: (usize, u8)| {
+            let pitches: Vec<f32> = self.notes.iter()
+                .filter(|parsed| (parsed.track, parsed.channel) == part)
+                .map(|parsed| parsed.note.pitch as f32)
+                .collect();
+            pitches.iter().sum::<f32>() / pitches.len().max(1) as f32
+        };
+        let (high, low) = if mean_pitch(parts[0]) >= mean_pitch(parts[1]) { (parts[0], parts[1]) } else { (parts[1], parts[0]) };
+        hands.right.push(high);
+        hands.left.push(low);
+        hands
+    }
+    
+    // Rests the source wrote in the given (track, channel) pairs, with their
+    // hands found as for `notes_for`. Rests no hand can be found for are left
+    // out, so their staff falls back on inferred rests.
+    pub fn rests_for(&self, selection: &[(usize, u8)], hands: Option<&HandAssignment>) -> Vec<WrittenRest> {
+        let hands = self.hands_or_guessed(selection, hands);
+        self.rests.iter()
+            .filter(|parsed| selection.contains(&(parsed.track, parsed.channel)))
+            .filter_map(|parsed| {
+                let mut rest = parsed.rest.clone();
+                rest.hand = Some(rest.hand.or(hands.hand_for(parsed.track, parsed.channel))?);
+                Some(rest)
+            })
+            .collect()
+    }
+    
+    fn hands_or_guessed(&self, selection: &[(usize, u8)], hands: Option<&HandAssignment>) -> HandAssignment {
+        match hands {
+            Some(hands) if !hands.is_empty() => hands.clone(),
+            _ => self.guess_hands(selection),
+        }
+    }
+    
+    // Notes from the given (track, channel) pairs as they are, in time order
+    pub fn notes_in(&self, selection: &[(usize, u8)]) -> Vec<Note> {
+        let mut notes: Vec<Note> = self.notes.iter()
+            .filter(|parsed| selection.contains(&(parsed.track, parsed.channel)))
+            .map(|parsed| parsed.note.clone())
+            .collect();
+        notes.sort_by(|a, b| a.position.total_cmp(&b.position));
+        notes
+    }
+    
+    // Every (track, channel) pair that has notes
+    pub fn all_channels(&self) -> Vec<(usize, u8)> {
+        self.channel_pairs(|_| true)
+    }
+    
+    // The pairs practised when nothing is chosen: everything but the drums
+    pub fn default_channels(&self) -> Vec<(usize, u8)> {
+        self.channel_pairs(|channel| !channel.percussion)
+    }
+    
+    fn channel_pairs(&self, include: impl Fn(&MidiChannelInfo) -> bool) -> Vec<(usize, u8)> {
+        self.tracks.iter()
+            .flat_map(|track| {
+                track.channels.iter()
+                    .filter(|channel| include(channel))
+                    .map(move |channel| (track.index, channel.channel))
+            })
+            .collect()
+    }
+}
+
+// Recognizes names like "Piano RH", "Right Hand" or "Bass"
+fn hand_from_name(name: &str) -> Option<Hand> {
+    let name = name.to_lowercase();
+    let words: Vec<&str> = name.split(|c: char| !c.is_alphanumeric()).collect();
+    if words.iter().any(|word| matches!(*word, "right" | "rh" | "treble" | "upper")) {
+        Some(Hand::Right)
+    } else if words.iter().any(|word| matches!(*word, "left" | "lh" | "bass" | "lower")) {
+        Some(Hand::Left)
+    } else {
+        None
+    }
+}
+
+// Everything read from one track
+#[derive(Debug, Default)]
+struct TrackContents {
+    name: Option<String>,
+    notes: Vec<(u8, Note)>, // Channel and note
+    programs: HashMap<u8, u8>, // First program change on each channel
+    warnings: Vec<String>,
+}
+
+// Converts delta-time ticks to quarter-note beats
+#[derive(Debug, Clone, Copy)]
+enum TickScale {
+    Metrical { ticks_per_beat: f32 },
+    // SMPTE files count real time, so they are laid out at the default tempo and
+    // their beats map back to the exact seconds
+    Timecode { ticks_per_second: f32 },
+}
+
+impl TickScale {
+    fn beats(&self, ticks: u32) -> f32 {
+        match *self {
+            TickScale::Metrical { ticks_per_beat } => ticks as f32 / ticks_per_beat,
+            TickScale::Timecode { ticks_per_second } => ticks as f32 / ticks_per_second * DEFAULT_TEMPO_BPM / 60.0,
+        }
+    }
+}
+
+// Song-wide meta events, which may appear in any track
+#[derive(Debug, Default)]
+struct Conductor {
+    tempo_changes: Vec<TempoChange>,
+    signatures: SignatureTimeline,
+}
 
 pub struct MidiParser;
 
 impl MidiParser {
-    pub fn parse_midi_file(data: &[u8]) -> Result<Vec<Note>, Box<dyn std::error::Error>> {
-        let smf = Smf::parse(data)?;
-        let mut notes = Vec::new();
-        let ticks_per_beat = match smf.header.timing {
-            midly::Timing::Metrical(tpb) => tpb.as_int(),
-            midly::Timing::Timecode(_, _) => 96, // Default fallback
+    pub fn parse_midi_file(data: &[u8]) -> Result<ParsedMidi> {
+        let smf = Smf::parse(data).context("Not a valid Standard MIDI File")?;
+        let scale = match smf.header.timing {
+            Timing::Metrical(tpb) => TickScale::Metrical {
+                ticks_per_beat: tpb.as_int().max(1) as f32,
+            },
+            Timing::Timecode(fps, subframes) => TickScale::Timecode {
+                ticks_per_second: (fps.as_f32() * subframes.max(1) as f32).max(1.0),
+            },
         };
         
-        for track in &smf.tracks {
-            let track_notes = Self::parse_track(track, ticks_per_beat)?;
-            notes.extend(track_notes);
+        let mut tracks = Vec::new();
+        let mut notes = Vec::new();
+        let mut warnings = Vec::new();
+        let mut conductor = Conductor::default();
+        
+        for (index, track) in smf.tracks.iter().enumerate() {
+            let contents = Self::parse_track(track, scale, &mut conductor);
+            let label = match &contents.name {
+                Some(name) => format!("Track {} ({})", index + 1, name),
+                None => format!("Track {}", index + 1),
+            };
+            warnings.extend(contents.warnings.iter().map(|warning| format!("{}: {}", label, warning)));
+            let channels: BTreeSet<u8> = contents.notes.iter().map(|(channel, _)| *channel).collect();
+            
+            tracks.push(MidiTrackInfo {
+                index,
+                name: contents.name,
+                channels: channels.into_iter()
+                    .map(|channel| MidiChannelInfo {
+                        channel,
+                        program: contents.programs.get(&channel).copied(),
+                        note_count: contents.notes.iter().filter(|(note_channel, _)| *note_channel == channel).count(),
+                        percussion: channel == PERCUSSION_CHANNEL,
+                    })
+                    .collect(),
+                note_count: contents.notes.len(),
+            });
+            notes.extend(contents.notes.into_iter().map(|(channel, note)| ParsedNote {
+                track: index,
+                channel,
+                note,
+            }));
         }
         
-        // Sort notes by time position
-        notes.sort_by(|a, b| a.position.partial_cmp(&b.position).unwrap());
+        let tempo_map = match scale {
+            TickScale::Metrical { .. } => TempoMap::from_changes(conductor.tempo_changes),
+            TickScale::Timecode { .. } => TempoMap::default(),
+        };
         
-        Ok(notes)
+        Ok(ParsedMidi {
+            tracks,
+            notes,
+            rests: Vec::new(),
+            tempo_map,
+            signatures: conductor.signatures,
+            title: None,
+            composer: None,
+            warnings,
+        })
     }
     
-    fn parse_track(track: &Track, ticks_per_beat: u16) -> Result<Vec<Note>, Box<dyn std::error::Error>> {
-        let mut notes = Vec::new();
+    fn parse_track(track: &Track, scale: TickScale, conductor: &mut Conductor) -> TrackContents {
+        let mut contents = TrackContents::default();
         let mut current_time = 0u32;
-        let mut note_on_events: HashMap<u8, u32> = HashMap::new();
+        // Start times of the notes sounding on each (channel, pitch). A note
+        // struck again before it is released stacks up, and each release ends
+        // the latest strike.
+        let mut open_notes: HashMap<(u8, u8), Vec<u32>> = HashMap::new();
+        let mut restruck = 0;
+        let mut unmatched_releases = 0;
         
         for event in track {
             current_time += event.delta.as_int();
             
-            if let TrackEventKind::Midi { channel: _, message } = &event.kind {
-                match message {
-                    MidiMessage::NoteOn { key, vel } => {
-                        if *vel > 0 {
-                            note_on_events.insert((*key).into(), current_time);
-                        } else {
-                            // Note off (velocity 0)
-                            let key_u8: u8 = (*key).into();
-                            if let Some(start_time) = note_on_events.remove(&key_u8) {
-                                let duration_ticks = current_time - start_time;
-                                let duration_beats = duration_ticks as f32 / ticks_per_beat as f32;
-                                let note_type = Self::duration_to_note_type(duration_beats);
-                                let position = start_time as f32 / ticks_per_beat as f32;
-                                
-                                notes.push(Note::new(key_u8, note_type, position));
+            match &event.kind {
+                TrackEventKind::Meta(MetaMessage::TrackName(bytes)) => {
+                    contents.name = Some(String::from_utf8_lossy(bytes).trim().to_string())
+                        .filter(|name| !name.is_empty());
+                }
+                TrackEventKind::Meta(MetaMessage::Tempo(micros_per_beat)) => {
+                    let micros_per_beat = micros_per_beat.as_int();
+                    if micros_per_beat > 0 {
+                        conductor.tempo_changes.push(TempoChange {
+                            beat: scale.beats(current_time),
+                            bpm: 60_000_000.0 / micros_per_beat as f32,
+                        });
+                    }
+                }
+                // The denominator is stored as a power of two
+                TrackEventKind::Meta(MetaMessage::TimeSignature(numerator, denominator_power, _, _)) if *denominator_power <= 6 => {
+                    conductor.signatures.add_time_signature(
+                        scale.beats(current_time),
+                        TimeSignature::new(*numerator, 1 << denominator_power),
+                    );
+                }
+                TrackEventKind::Meta(MetaMessage::KeySignature(fifths, minor)) => {
+                    conductor.signatures.add_key_signature(
+                        scale.beats(current_time),
+                        KeySignature::new(*fifths, *minor),
+                    );
+                }
+                TrackEventKind::Midi { channel, message } => {
+                    let channel: u8 = (*channel).into();
+                    match message {
+                        MidiMessage::NoteOn { key, vel } if *vel > 0 => {
+                            let starts = open_notes.entry((channel, (*key).into())).or_default();
+                            if !starts.is_empty() {
+                                restruck += 1;
                             }
+                            starts.push(current_time);
                         }
-                    }
-                    MidiMessage::NoteOff { key, vel: _ } => {
-                        let key_u8: u8 = (*key).into();
-                        if let Some(start_time) = note_on_events.remove(&key_u8) {
-                            let duration_ticks = current_time - start_time;
-                            let duration_beats = duration_ticks as f32 / ticks_per_beat as f32;
-                            let note_type = Self::duration_to_note_type(duration_beats);
-                            let position = start_time as f32 / ticks_per_beat as f32;
-                            
-                            notes.push(Note::new(key_u8, note_type, position));
+                        // A note on with velocity 0 is a note off
+                        MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                            let key: u8 = (*key).into();
+                            match open_notes.get_mut(&(channel, key)).and_then(|starts| starts.pop()) {
+                                Some(start_time) => {
+                                    contents.notes.push((channel, Self::make_note(key, start_time, current_time, scale)));
+                                }
+                                None => unmatched_releases += 1,
+                            }
                         }
+                        MidiMessage::ProgramChange { program } => {
+                            contents.programs.entry(channel).or_insert((*program).into());
+                        }
+                        _ => {}
                     }
-                    _ => {}
                 }
+                _ => {}
             }
         }
         
-        Ok(notes)
+        // Notes still sounding when the track ends last until its end
+        let mut dangling: Vec<(u32, u8, u8)> = open_notes.into_iter()
+            .flat_map(|((channel, key), starts)| starts.into_iter().map(move |start_time| (start_time, channel, key)))
+            .collect();
+        dangling.sort_unstable();
+        for &(start_time, channel, key) in &dangling {
+            contents.notes.push((channel, Self::make_note(key, start_time, current_time, scale)));
+        }
+        let dangling = dangling.len();
+        
+        let problems = [
+            (restruck, "struck again while still sounding"),
+            (dangling, "never released; held until the end of the track"),
+            (unmatched_releases, "released without being struck; ignored"),
+        ];
+        for (count, problem) in problems {
+            if count > 0 {
+                let notes = if count == 1 { "note" } else { "notes" };
+                contents.warnings.push(format!("{} {} {}", count, notes, problem));
+            }
+        }
+        contents
+    }
+    
+    // Start and end are snapped to the thirty-second grid so the notation can
+    // write the note; very short notes still last one grid step
+    fn make_note(key: u8, start_time: u32, end_time: u32, scale: TickScale) -> Note {
+        let quantize = |beats: f32| (beats / DURATION_GRID).round() * DURATION_GRID;
+        let position = quantize(scale.beats(start_time));
+        let end = quantize(scale.beats(end_time)).max(position + DURATION_GRID);
+        
+        Note::with_duration(key, position, end - position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const TICKS_PER_BEAT: u16 = 480;
+    
+    // A format 1 file with one track per list of (delta ticks, event bytes)
+    fn smf(tracks: &[Vec<(u32, Vec<u8>)>]) -> Vec<u8> {
+        smf_with_division(TICKS_PER_BEAT.to_be_bytes(), tracks)
+    }
+    
+    fn smf_with_division(division: [u8; 2], tracks: &[Vec<(u32, Vec<u8>)>]) -> Vec<u8> {
+        let mut data = b"MThd".to_vec();
+        data.extend(6u32.to_be_bytes());
+        data.extend(1u16.to_be_bytes());
+        data.extend((tracks.len() as u16).to_be_bytes());
+        data.extend(division);
+        
+        for events in tracks {
+            let mut body = Vec::new();
+            for (delta, bytes) in events.iter().chain([&(0, vec![0xFF, 0x2F, 0x00])]) {
+                body.extend(variable_length(*delta));
+                body.extend(bytes);
+            }
+            data.extend(b"MTrk");
+            data.extend((body.len() as u32).to_be_bytes());
+            data.extend(body);
+        }
+        data
     }
     
-    fn duration_to_note_type(duration_beats: f32) -> NoteType {
-        if duration_beats >= 3.5 {
-            NoteType::Whole
-        } else if duration_beats >= 1.5 {
-            NoteType::Half
-        } else if duration_beats >= 0.75 {
-            NoteType::Quarter
-        } else {
-            NoteType::Eighth
+    fn variable_length(mut value: u32) -> Vec<u8> {
+        let mut bytes = vec![(value & 0x7F) as u8];
+        value >>= 7;
+        while value > 0 {
+            bytes.insert(0, (value & 0x7F) as u8 | 0x80);
+            value >>= 7;
         }
+        bytes
+    }
+    
+    fn note_on(channel: u8, key: u8) -> Vec<u8> {
+        vec![0x90 | channel, key, 64]
+    }
+    
+    fn note_off(channel: u8, key: u8) -> Vec<u8> {
+        vec![0x80 | channel, key, 0]
+    }
+    
+    // Channel, pitch, position and duration of every note
+    fn timing(parsed: &ParsedMidi) -> Vec<(u8, u8, f32, f32)> {
+        let mut timing: Vec<_> = parsed.notes.iter()
+            .map(|parsed| (parsed.channel, parsed.note.pitch, parsed.note.position, parsed.note.duration))
+            .collect();
+        timing.sort_by(|a, b| a.2.total_cmp(&b.2).then(a.0.cmp(&b.0)).then(a.1.cmp(&b.1)));
+        timing
+    }
+    
+    #[test]
+    fn restruck_notes_release_the_latest_strike_first() {
+        let data = smf(&[vec![
+            (0, vec![0xFF, 0x03, 5, b'P', b'i', b'a', b'n', b'o']),
+            (0, note_on(0, 60)),
+            (480, note_on(0, 60)),
+            (480, note_off(0, 60)),
+            (480, note_off(0, 60)),
+        ]]);
+        let parsed = MidiParser::parse_midi_file(&data).unwrap();
+        
+        assert_eq!(timing(&parsed), vec![(0, 60, 0.0, 3.0), (0, 60, 1.0, 1.0)]);
+        assert_eq!(parsed.warnings, vec!["Track 1 (Piano): 1 note struck again while still sounding"]);
+    }
+    
+    #[test]
+    fn one_pitch_on_two_channels_is_paired_per_channel() {
+        let data = smf(&[vec![
+            (0, note_on(0, 60)),
+            (240, note_on(1, 60)),
+            (240, note_off(0, 60)),
+            (480, note_off(1, 60)),
+        ]]);
+        let parsed = MidiParser::parse_midi_file(&data).unwrap();
+        
+        assert_eq!(timing(&parsed), vec![(0, 60, 0.0, 1.0), (1, 60, 0.5, 1.5)]);
+        assert!(parsed.warnings.is_empty(), "{:?}", parsed.warnings);
+        let channels: Vec<(u8, usize)> = parsed.tracks[0].channels.iter()
+            .map(|info| (info.channel, info.note_count))
+            .collect();
+        assert_eq!(channels, vec![(0, 1), (1, 1)]);
+    }
+    
+    #[test]
+    fn unreleased_notes_are_held_until_the_end_of_the_track() {
+        let data = smf(&[
+            vec![
+                (0, note_on(0, 60)),
+                (0, note_on(0, 64)),
+                (480, note_off(0, 64)),
+                (0, note_off(0, 67)),
+                (1440, vec![0xFF, 0x01, 0x00]),
+            ],
+            vec![
+                (0, note_on(1, 48)),
+                (960, vec![0x91, 48, 0]),
+            ],
+        ]);
+        let parsed = MidiParser::parse_midi_file(&data).unwrap();
+        
+        assert_eq!(timing(&parsed), vec![(0, 60, 0.0, 4.0), (0, 64, 0.0, 1.0), (1, 48, 0.0, 2.0)]);
+        assert_eq!(parsed.warnings, vec![
+            "Track 1: 1 note never released; held until the end of the track",
+            "Track 1: 1 note released without being struck; ignored",
+        ]);
+    }
+    
+    fn tempo(micros_per_beat: u32) -> Vec<u8> {
+        let mut bytes = vec![0xFF, 0x51, 0x03];
+        bytes.extend(&micros_per_beat.to_be_bytes()[1..]);
+        bytes
+    }
+    
+    #[test]
+    fn tempo_events_are_placed_in_beats() {
+        let data = smf(&[vec![
+            (0, tempo(500_000)),
+            (960, tempo(1_000_000)),
+            (0, note_on(0, 60)),
+            (480, note_off(0, 60)),
+        ]]);
+        let parsed = MidiParser::parse_midi_file(&data).unwrap();
+        
+        assert_eq!(parsed.tempo_map, TempoMap::from_changes(vec![
+            TempoChange { beat: 0.0, bpm: 120.0 },
+            TempoChange { beat: 2.0, bpm: 60.0 },
+        ]));
+        assert_eq!(timing(&parsed), vec![(0, 60, 2.0, 1.0)]);
+        assert_eq!(parsed.tempo_map.beats_to_seconds(3.0), 2.0);
+    }
+    
+    #[test]
+    fn timecode_files_are_laid_out_at_the_default_tempo() {
+        // 25 frames of 40 ticks: a thousand ticks a second, and two beats a
+        // second at 120 bpm. Tempo events have no say over real time.
+        let data = smf_with_division([-25i8 as u8, 40], &[vec![
+            (0, tempo(1_000_000)),
+            (500, note_on(0, 60)),
+            (1000, note_off(0, 60)),
+        ]]);
+        let parsed = MidiParser::parse_midi_file(&data).unwrap();
+        
+        assert_eq!(timing(&parsed), vec![(0, 60, 1.0, 2.0)]);
+        assert_eq!(parsed.tempo_map, TempoMap::default());
+        assert_eq!(parsed.tempo_map.beats_to_seconds(3.0), 1.5);
     }
 }
\ No newline at end of file
@@ -0,0 +1,295 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::storage;
+use super::library::{slugify, Song};
+use super::manifest::{LibraryManifest, SongManifestEntry, MANIFEST_FILE_NAME};
+use super::{AbcParser, MidiParser, MusicXmlParser, ParsedMidi};
+
+// Bump whenever parser output changes so stale cached notes get re-parsed
+const CACHE_VERSION: u32 = 10;
+const CACHE_FILE_NAME: &str = "library_cache.json";
+
+// Files picked up without a manifest entry
+pub const SONG_EXTENSIONS: [&str; 6] = ["mid", "midi", "musicxml", "xml", "mxl", "abc"];
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedFile {
+    modified_ms: u64, // Since the Unix epoch
+    size: u64,
+    parsed: ParsedMidi,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ScanCache {
+    version: u32,
+    files: HashMap<String, CachedFile>, // Keyed by absolute path
+}
+
+impl Default for ScanCache {
+    fn default() -> Self {
+        Self {
+            version: CACHE_VERSION,
+            files: HashMap::new(),
+        }
+    }
+}
+
+// Walks a library directory tree and turns its song files into songs. Parsed
+// files are cached by path, size and modification time so only new or edited
+// files are parsed again.
+pub struct LibraryScanner {
+    cache: ScanCache,
+    cache_path: Option<PathBuf>,
+    cache_dirty: bool,
+}
+
+impl LibraryScanner {
+    pub fn new() -> Self {
+        let cache_path = storage::data_dir().map(|dir| dir.join(CACHE_FILE_NAME));
+        let cache = cache_path.as_deref()
+            .and_then(Self::load_cache)
+            .unwrap_or_default();
+        
+        Self {
+            cache,
+            cache_path,
+            cache_dirty: false,
+        }
+    }
+    
+    // Parses every file afresh and leaves the cache on disk alone, for one-off
+    // loads such as command-line exports
+    pub fn uncached() -> Self {
+        Self {
+            cache: ScanCache::default(),
+            cache_path: None,
+            cache_dirty: false,
+        }
+    }
+    
+    fn load_cache(path: &Path) -> Option<ScanCache> {
+        let contents = fs::read_to_string(path).ok()?;
+        match serde_json::from_str::<ScanCache>(&contents) {
+            Ok(cache) if cache.version == CACHE_VERSION => Some(cache),
+            Ok(_) => {
+                log::info!("Library cache is from a different version; rebuilding it");
+                None
+            }
+            Err(e) => {
+                log::warn!("Ignoring unreadable library cache {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+    
+    fn save_cache(&mut self) {
+        if !self.cache_dirty {
+            return;
+        }
+        let Some(path) = &self.cache_path else {
+            return;
+        };
+        
+        let result = serde_json::to_vec(&self.cache)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| storage::write_atomic(path, &json));
+        match result {
+            Ok(()) => self.cache_dirty = false,
+            Err(e) => log::warn!("Failed to save library cache: {:#}", e),
+        }
+    }
+    
+    // Loads every song below `root`. Each directory may have a manifest describing
+    // its songs; song files it doesn't mention are added with default metadata.
+    pub fn scan(&mut self, root: &Path) -> Vec<Song> {
+        let mut songs = Vec::new();
+        let mut seen = HashSet::new();
+        
+        if root.is_dir() {
+            self.scan_dir(root, root, &mut songs, &mut seen);
+        }
+        
+        // Forget files that have been removed
+        let cached = self.cache.files.len();
+        self.cache.files.retain(|path, _| seen.contains(path));
+        if self.cache.files.len() != cached {
+            self.cache_dirty = true;
+        }
+        self.save_cache();
+        
+        songs
+    }
+    
+    fn scan_dir(&mut self, root: &Path, dir: &Path, songs: &mut Vec<Song>, seen: &mut HashSet<String>) {
+        let manifest_path = dir.join(MANIFEST_FILE_NAME);
+        let mut entries = match LibraryManifest::load(&manifest_path) {
+            Ok(manifest) => manifest.songs,
+            Err(e) => {
+                log::warn!("Ignoring library manifest: {:#}", e);
+                Vec::new()
+            }
+        };
+        let listed: HashSet<PathBuf> = entries.iter().map(|entry| dir.join(&entry.file)).collect();
+        
+        let mut paths: Vec<PathBuf> = match fs::read_dir(dir) {
+            Ok(read_dir) => read_dir.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect(),
+            Err(e) => {
+                log::warn!("Failed to read library directory {}: {}", dir.display(), e);
+                return;
+            }
+        };
+        paths.sort();
+        
+        let mut subdirs = Vec::new();
+        for path in paths {
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if name.starts_with('.') {
+                continue;
+            }
+            
+            if path.is_dir() {
+                subdirs.push(path);
+            } else if Self::is_song_file(&path) && !listed.contains(&path) {
+                entries.push(SongManifestEntry::for_file(name.to_string()));
+            }
+        }
+        
+        for entry in &entries {
+            let path = dir.join(&entry.file);
+            seen.insert(path.to_string_lossy().into_owned());
+            
+            match self.load_song(root, &path, entry) {
+                Ok(song) => songs.push(song),
+                Err(e) => log::warn!("Skipping library song {}: {:#}", path.display(), e),
+            }
+        }
+        
+        for subdir in subdirs {
+            self.scan_dir(root, &subdir, songs, seen);
+        }
+    }
+    
+    // Loads a single song file from anywhere on disk, with default metadata
+    pub fn load_file(&mut self, path: &Path) -> Result<Song> {
+        let name = path.file_name()
+            .ok_or_else(|| anyhow!("{} is not a file", path.display()))?
+            .to_string_lossy()
+            .into_owned();
+        let root = path.parent().unwrap_or(Path::new(""));
+        self.load_song(root, path, &SongManifestEntry::for_file(name))
+    }
+    
+    fn is_song_file(path: &Path) -> bool {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| SONG_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+    }
+    
+    fn load_song(&mut self, root: &Path, path: &Path, entry: &SongManifestEntry) -> Result<Song> {
+        let parsed = self.parse_cached(path)?;
+        
+        let channels = if !entry.channels.is_empty() {
+            entry.channels.clone()
+        } else if let Some(hands) = entry.hands.as_ref().filter(|hands| !hands.is_empty()) {
+            hands.right.iter().chain(&hands.left).copied().collect()
+        } else {
+            parsed.default_channels()
+        };
+        
+        let notes = parsed.notes_for(&channels, entry.hands.as_ref());
+        if notes.is_empty() {
+            bail!("no notes in the selected channels");
+        }
+        let accompaniment = parsed.notes_in(&entry.accompaniment);
+        let rests = parsed.rests_for(&channels, entry.hands.as_ref());
+        
+        // Files without an explicit id are named after their place in the library,
+        // so the same file keeps its progress history across rescans. The title
+        // and artist fall back on what the file itself says.
+        let relative = path.strip_prefix(root).unwrap_or(path).with_extension("");
+        let id = entry.id.clone()
+            .unwrap_or_else(|| slugify(&relative.to_string_lossy()));
+        let title = entry.title.clone()
+            .or_else(|| parsed.title.clone())
+            .unwrap_or_else(|| {
+                relative.file_name()
+                    .map(|name| name.to_string_lossy().replace('_', " "))
+                    .unwrap_or_else(|| id.clone())
+            });
+        let artist = if entry.artist.is_empty() {
+            parsed.composer.clone().unwrap_or_default()
+        } else {
+            entry.artist.clone()
+        };
+        
+        let mut song = Song::from_notes(id, title, artist, entry.difficulty, notes)
+            .with_timing(parsed.tempo_map, parsed.signatures)
+            .with_rests(rests);
+        song.tags = entry.tags.clone();
+        song.source = Some(path.to_path_buf());
+        song.accompaniment = accompaniment;
+        if let Some(tempo) = entry.tempo {
+            song.set_tempo(tempo);
+        }
+        
+        Ok(song)
+    }
+    
+    fn parse_cached(&mut self, path: &Path) -> Result<ParsedMidi> {
+        let metadata = fs::metadata(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let modified_ms = metadata.modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or(0);
+        let size = metadata.len();
+        let key = path.to_string_lossy().into_owned();
+        
+        if let Some(cached) = self.cache.files.get(&key) {
+            if cached.modified_ms == modified_ms && cached.size == size {
+                return Ok(cached.parsed.clone());
+            }
+        }
+        
+        let parsed = Self::parse_file(path)?;
+        self.cache.files.insert(key, CachedFile {
+            modified_ms,
+            size,
+            parsed: parsed.clone(),
+        });
+        self.cache_dirty = true;
+        Ok(parsed)
+    }
+    
+    // Reads a song file of any supported format, by its extension
+    pub fn parse_file(path: &Path) -> Result<ParsedMidi> {
+        let extension = path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase())
+            .unwrap_or_default();
+        if !SONG_EXTENSIONS.contains(&extension.as_str()) {
+            bail!("unsupported song format '{}'", extension);
+        }
+        
+        let data = fs::read(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let parsed = match extension.as_str() {
+            "mid" | "midi" => MidiParser::parse_midi_file(&data),
+            "abc" => AbcParser::parse(&data),
+            _ => MusicXmlParser::parse(&data),
+        }.with_context(|| format!("Failed to parse {}", path.display()))?;
+        
+        for warning in &parsed.warnings {
+            log::warn!("{}: {}", path.display(), warning);
+        }
+        Ok(parsed)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,178 @@
+use serde::{Deserialize, Serialize};
+
+// Tempo assumed by Standard MIDI Files until the first tempo event
+pub const DEFAULT_TEMPO_BPM: f32 = 120.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TempoChange {
+    pub beat: f32, // Quarter-note beats from the start of the song
+    pub bpm: f32,
+}
+
+// Piecewise-constant tempo over the song, for converting between beats and
+// seconds. Always starts with a change at beat 0, which is why loading goes
+// through `from_changes`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "TempoMapFile")]
+pub struct TempoMap {
+    changes: Vec<TempoChange>,
+}
+
+// Stored form of a tempo map, whose changes may be empty or out of order
+#[derive(Deserialize)]
+struct TempoMapFile {
+    #[serde(default)]
+    changes: Vec<TempoChange>,
+}
+
+impl From<TempoMapFile> for TempoMap {
+    fn from(file: TempoMapFile) -> Self {
+        Self::from_changes(file.changes)
+    }
+}
+
+impl Default for TempoMap {
+    fn default() -> Self {
+        Self::constant(DEFAULT_TEMPO_BPM)
+    }
+}
+
+impl TempoMap {
+    pub fn constant(bpm: f32) -> Self {
+        Self::from_changes(vec![TempoChange { beat: 0.0, bpm }])
+    }
+    
+    // Orders the changes, keeps the last one where several share a beat and fills
+    // in the default tempo before the first one
+    pub fn from_changes(mut changes: Vec<TempoChange>) -> Self {
+        changes.retain(|change| change.bpm.is_finite() && change.bpm > 0.0 && change.beat >= 0.0);
+        changes.sort_by(|a, b| a.beat.total_cmp(&b.beat));
+        
+        let mut merged: Vec<TempoChange> = Vec::with_capacity(changes.len() + 1);
+        for change in changes {
+            match merged.last_mut() {
+                Some(last) if (last.beat - change.beat).abs() < f32::EPSILON => *last = change,
+                Some(last) if (last.bpm - change.bpm).abs() < f32::EPSILON => {}
+                _ => merged.push(change),
+            }
+        }
+        
+        if merged.first().is_none_or(|first| first.beat > 0.0) {
+            merged.insert(0, TempoChange { beat: 0.0, bpm: DEFAULT_TEMPO_BPM });
+        }
+        
+        Self { changes: merged }
+    }
+    
+    pub fn get_changes(&self) -> &[TempoChange] {
+        &self.changes
+    }
+    
+    pub fn initial_bpm(&self) -> f32 {
+        self.changes[0].bpm
+    }
+    
+    // Same tempo changes played `factor` times faster
+    pub fn scaled(&self, factor: f32) -> Self {
+        Self {
+            changes: self.changes.iter()
+                .map(|change| TempoChange { beat: change.beat, bpm: change.bpm * factor })
+                .collect(),
+        }
+    }
+    
+    // Seconds from beat 0 to the given beat; negative beats (a count-in) use the
+    // initial tempo
+    pub fn beats_to_seconds(&self, beat: f32) -> f32 {
+        if beat <= 0.0 {
+            return beat * 60.0 / self.initial_bpm();
+        }
+        
+        let mut seconds = 0.0;
+        for (i, change) in self.changes.iter().enumerate() {
+            let segment_end = self.changes.get(i + 1)
+                .map_or(beat, |next| next.beat.min(beat));
+            if segment_end <= change.beat {
+                break;
+            }
+            seconds += (segment_end - change.beat) * 60.0 / change.bpm;
+        }
+        seconds
+    }
+    
+    pub fn seconds_to_beats(&self, seconds: f32) -> f32 {
+        if seconds <= 0.0 {
+            return seconds * self.initial_bpm() / 60.0;
+        }
+        
+        let mut elapsed = 0.0;
+        for (i, change) in self.changes.iter().enumerate() {
+            if let Some(next) = self.changes.get(i + 1) {
+                let segment = (next.beat - change.beat) * 60.0 / change.bpm;
+                if elapsed + segment < seconds {
+                    elapsed += segment;
+                    continue;
+                }
+            }
+            return change.beat + (seconds - elapsed) * change.bpm / 60.0;
+        }
+        
+        // The last change always returns above
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn change(beat: f32, bpm: f32) -> TempoChange {
+        TempoChange { beat, bpm }
+    }
+    
+    #[test]
+    fn changes_are_ordered_merged_and_start_at_beat_zero() {
+        let map = TempoMap::from_changes(vec![
+            change(8.0, 90.0),
+            change(4.0, 60.0),
+            change(4.0, 100.0),
+            change(6.0, 100.0),
+            change(2.0, f32::NAN),
+            change(3.0, 0.0),
+        ]);
+        assert_eq!(map.get_changes(), &[change(0.0, DEFAULT_TEMPO_BPM), change(4.0, 100.0), change(8.0, 90.0)]);
+        assert_eq!(TempoMap::from_changes(Vec::new()), TempoMap::default());
+    }
+    
+    #[test]
+    fn converts_across_tempo_changes() {
+        // Four beats at 120, four at 60, then 240
+        let map = TempoMap::from_changes(vec![change(0.0, 120.0), change(4.0, 60.0), change(8.0, 240.0)]);
+        for (beats, seconds) in [(0.0, 0.0), (2.0, 1.0), (4.0, 2.0), (6.0, 4.0), (8.0, 6.0), (12.0, 7.0)] {
+            assert_eq!(map.beats_to_seconds(beats), seconds);
+            assert_eq!(map.seconds_to_beats(seconds), beats);
+        }
+        // A count-in runs at the opening tempo
+        assert_eq!(map.beats_to_seconds(-4.0), -2.0);
+        assert_eq!(map.seconds_to_beats(-1.0), -2.0);
+        
+        let faster = map.scaled(2.0);
+        assert_eq!(faster.initial_bpm(), 240.0);
+        assert_eq!(faster.beats_to_seconds(12.0), 3.5);
+    }
+    
+    #[test]
+    fn loading_goes_through_from_changes() {
+        let empty: TempoMap = serde_json::from_str(r#"{ "changes": [] }"#).unwrap();
+        assert_eq!(empty.initial_bpm(), DEFAULT_TEMPO_BPM);
+        let missing: TempoMap = serde_json::from_str("{}").unwrap();
+        assert_eq!(missing, TempoMap::default());
+        
+        let unordered: TempoMap = serde_json::from_str(r#"{ "changes": [{ "beat": 4.0, "bpm": 60.0 }, { "beat": 0.0, "bpm": 90.0 }] }"#).unwrap();
+        assert_eq!(unordered.get_changes(), &[change(0.0, 90.0), change(4.0, 60.0)]);
+        
+        let map = TempoMap::from_changes(vec![change(0.0, 72.0), change(16.0, 144.0)]);
+        let saved = serde_json::to_string(&map).unwrap();
+        assert_eq!(serde_json::from_str::<TempoMap>(&saved).unwrap(), map);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,393 @@
+use eframe::egui::{Color32, Pos2, Stroke};
+use std::ops::Range;
+use super::{NoteType, NoteValue, Staff};
+use super::canvas::Canvas;
+use super::glyphs::{self, Glyph};
+use super::signature::Measure;
+
+// Distance from a notehead's center to the stem
+const STEM_OFFSET: f32 = 6.0;
+
+// Lengths in line spaces
+const STEM_LENGTH: f32 = 3.5;
+const MIN_BEAMED_STEM_LENGTH: f32 = 2.5;
+const BEAM_THICKNESS: f32 = 0.5;
+const BEAM_SPACING: f32 = 0.75; // From one beam to the next
+const FLAG_SPACING: f32 = 0.75;
+const BEAM_STUB_LENGTH: f32 = 1.0;
+
+// Beams are kept shallow: at most this rise per unit of run, and never more
+// than one line space over the whole group
+const MAX_BEAM_SLOPE: f32 = 0.25;
+const MAX_BEAM_RISE: f32 = 1.0;
+
+const EPSILON: f32 = 0.001;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StemDirection {
+    Up,
+    Down,
+}
+
+impl StemDirection {
+    // Heads on or above the middle line take down-stems. When several heads
+    // share a stem or a beam, the one furthest from the middle line decides.
+    pub fn for_heads(staff: &Staff, heads: &[f32]) -> Self {
+        let middle = staff.get_middle_line();
+        let furthest = heads.iter()
+            .copied()
+            .max_by(|a, b| (a - middle).abs().total_cmp(&(b - middle).abs()))
+            .unwrap_or(middle);
+        
+        if furthest <= middle + EPSILON {
+            StemDirection::Down
+        } else {
+            StemDirection::Up
+        }
+    }
+    
+    // Screen direction from the heads towards the stem tip
+    fn sign(&self) -> f32 {
+        match self {
+            StemDirection::Up => -1.0,
+            StemDirection::Down => 1.0,
+        }
+    }
+}
+
+// The noteheads hanging from one stem, placed on the page
+#[derive(Debug, Clone)]
+pub struct Stem<'a> {
+    pub staff: &'a Staff,
+    pub system: usize,
+    pub measure: Measure,
+    pub position: f32,
+    pub value: NoteValue,
+    pub length: f32, // Beats the value takes, which a tuplet shortens
+    pub x: f32, // Notehead center
+    pub heads: Vec<f32>, // Notehead heights
+    pub color: Color32,
+    pub direction: Option<StemDirection>, // Set when the voice decides it
+}
+
+impl Stem<'_> {
+    fn flag_count(&self) -> usize {
+        self.value.note_type.flag_count()
+    }
+    
+    // Up-stems sit on the right of the heads, down-stems on the left
+    fn stem_x(&self, direction: StemDirection) -> f32 {
+        let stem_up = direction == StemDirection::Up;
+        if let Some(font) = glyphs::music_font() {
+            let thickness = stem_thickness(self.staff.get_line_spacing());
+            let inset = if stem_up { -thickness / 2.0 } else { thickness / 2.0 };
+            return self.x + font.stem_offset(self.value.note_type, stem_up, self.staff.get_line_spacing()) + inset;
+        }
+        if stem_up { self.x + STEM_OFFSET } else { self.x - STEM_OFFSET }
+    }
+    
+    // Head the stem starts from, furthest from the tip
+    fn base(&self, direction: StemDirection) -> f32 {
+        let lowest = self.heads.iter().copied().fold(f32::MIN, f32::max);
+        let highest = self.heads.iter().copied().fold(f32::MAX, f32::min);
+        match direction {
+            StemDirection::Up => lowest,
+            StemDirection::Down => highest,
+        }
+    }
+    
+    // Head nearest the tip, which the stem length is measured from
+    fn end_head(&self, direction: StemDirection) -> f32 {
+        match direction {
+            StemDirection::Up => self.base(StemDirection::Down),
+            StemDirection::Down => self.base(StemDirection::Up),
+        }
+    }
+    
+    // Tip of an unbeamed stem: an octave long, reaching at least the middle
+    // line for notes on ledger lines, and longer when it carries extra flags
+    fn natural_tip(&self, direction: StemDirection) -> f32 {
+        let line_spacing = self.staff.get_line_spacing();
+        let middle = self.staff.get_middle_line();
+        let extra_flags = self.flag_count().saturating_sub(1) as f32 * FLAG_SPACING;
+        let tip = self.end_head(direction) + direction.sign() * (STEM_LENGTH + extra_flags) * line_spacing;
+        
+        match direction {
+            StemDirection::Up => tip.min(middle),
+            StemDirection::Down => tip.max(middle),
+        }
+    }
+    
+    // Which beat of the measure the stem falls in, for grouping beams
+    fn beat_group(&self) -> i32 {
+        let group_beats = self.measure.time_signature.beam_group_beats();
+        ((self.position - self.measure.start + EPSILON) / group_beats).floor() as i32
+    }
+    
+    fn beams_with(&self, next: &Stem) -> bool {
+        next.flag_count() > 0
+            && next.system == self.system
+            && std::ptr::eq(next.staff, self.staff)
+            && next.measure.index == self.measure.index
+            && next.beat_group() == self.beat_group()
+            && (next.position - (self.position + self.length)).abs() < EPSILON
+    }
+}
+
+// Runs of eighths and shorter in the same beat are beamed together; any other
+// stem stands alone. `stems` must be in time order.
+fn beam_groups(stems: &[Stem]) -> Vec<Range<usize>> {
+    let mut groups = Vec::new();
+    let mut start = 0;
+    while start < stems.len() {
+        let mut end = start + 1;
+        if stems[start].flag_count() > 0 {
+            while end < stems.len() && stems[end - 1].beams_with(&stems[end]) {
+                end += 1;
+            }
+        }
+        groups.push(start..end);
+        start = end;
+    }
+    groups
+}
+
+// Direction of each stem; a beamed group shares one direction, which the first
+// stem's voice may fix
+pub fn stem_directions(stems: &[Stem]) -> Vec<StemDirection> {
+    let mut directions = Vec::with_capacity(stems.len());
+    for group in beam_groups(stems) {
+        let group = &stems[group];
+        let heads: Vec<f32> = group.iter().flat_map(|stem| stem.heads.iter().copied()).collect();
+        let direction = group[0].direction.unwrap_or_else(|| StemDirection::for_heads(group[0].staff, &heads));
+        directions.extend(std::iter::repeat_n(direction, group.len()));
+    }
+    directions
+}
+
+// Draws stems, flags and beams for one staff. `stems` must be in time order.
+pub fn draw_stems(canvas: &mut dyn Canvas, stems: &[Stem]) {
+    let directions = stem_directions(stems);
+    for group in beam_groups(stems) {
+        let direction = directions[group.start];
+        if group.len() > 1 {
+            draw_beamed_group(canvas, &stems[group], direction);
+        } else {
+            draw_unbeamed(canvas, &stems[group.start], direction);
+        }
+    }
+}
+
+fn draw_unbeamed(canvas: &mut dyn Canvas, stem: &Stem, direction: StemDirection) {
+    if stem.value.note_type == NoteType::Whole {
+        return;
+    }
+    
+    let line_spacing = stem.staff.get_line_spacing();
+    let x = stem.stem_x(direction);
+    let tip = stem.natural_tip(direction);
+    canvas.line_segment([Pos2::new(x, stem.base(direction)), Pos2::new(x, tip)], Stroke::new(stem_thickness(line_spacing), stem.color));
+    
+    let stem_up = direction == StemDirection::Up;
+    if let (Some(font), Some(flag)) = (glyphs::music_font(), Glyph::flag(stem.value.note_type, stem_up)) {
+        let origin_x = x - stem_thickness(line_spacing) / 2.0;
+        font.draw(canvas, flag, Pos2::new(origin_x, tip), line_spacing, stem.color);
+        return;
+    }
+    
+    // Flags hang from the tip back towards the heads, on the right of the stem
+    let sign = direction.sign();
+    for flag in 0..stem.flag_count() {
+        let flag_y = tip - sign * flag as f32 * FLAG_SPACING * line_spacing;
+        canvas.line_segment(
+            [Pos2::new(x, flag_y), Pos2::new(x + 0.6 * line_spacing, flag_y - sign * line_spacing)],
+            Stroke::new(2.0, stem.color),
+        );
+    }
+}
+
+fn draw_beamed_group(canvas: &mut dyn Canvas, group: &[Stem], direction: StemDirection) {
+    let line_spacing = group[0].staff.get_line_spacing();
+    let sign = direction.sign();
+    
+    let first = &group[0];
+    let last = &group[group.len() - 1];
+    let first_x = first.stem_x(direction);
+    let run = (last.stem_x(direction) - first_x).max(1.0);
+    
+    // The beam follows the outer notes, flattened to a gentle slope
+    let max_rise = (MAX_BEAM_SLOPE * run).min(MAX_BEAM_RISE * line_spacing);
+    let rise = (last.end_head(direction) - first.end_head(direction)).clamp(-max_rise, max_rise);
+    let slope = rise / run;
+    
+    // Then it is moved away from the heads until every stem is long enough
+    let mut beam_start = first.natural_tip(direction);
+    for stem in group {
+        let dx = stem.stem_x(direction) - first_x;
+        let required = stem.end_head(direction) + sign * MIN_BEAMED_STEM_LENGTH * line_spacing - slope * dx;
+        beam_start = match direction {
+            StemDirection::Up => beam_start.min(required),
+            StemDirection::Down => beam_start.max(required),
+        };
+    }
+    let beam_y = |x: f32| beam_start + slope * (x - first_x);
+    
+    for stem in group {
+        let x = stem.stem_x(direction);
+        canvas.line_segment([Pos2::new(x, stem.base(direction)), Pos2::new(x, beam_y(x))], Stroke::new(stem_thickness(line_spacing), stem.color));
+    }
+    
+    // Primary beam across the group, then one level per extra flag. A short
+    // value with no neighbour at its level gets a stub pointing into the group.
+    let max_flags = group.iter().map(Stem::flag_count).max().unwrap_or(1);
+    for level in 0..max_flags {
+        let offset = -sign * level as f32 * BEAM_SPACING * line_spacing;
+        let mut i = 0;
+        while i < group.len() {
+            if group[i].flag_count() <= level {
+                i += 1;
+                continue;
+            }
+            let mut j = i;
+            while j + 1 < group.len() && group[j + 1].flag_count() > level {
+                j += 1;
+            }
+            
+            let (from, to) = if j > i {
+                (group[i].stem_x(direction), group[j].stem_x(direction))
+            } else {
+                let x = group[i].stem_x(direction);
+                let stub = BEAM_STUB_LENGTH * line_spacing;
+                if i == 0 { (x, x + stub) } else { (x - stub, x) }
+            };
+            draw_beam(canvas, from, to, |x| beam_y(x) + offset, -sign * beam_thickness(line_spacing));
+            i = j + 1;
+        }
+    }
+}
+
+// A beam segment whose outer edge follows `edge_y`, extending `thickness`
+// towards the noteheads
+fn draw_beam(canvas: &mut dyn Canvas, from: f32, to: f32, edge_y: impl Fn(f32) -> f32, thickness: f32) {
+    let points = vec![
+        Pos2::new(from, edge_y(from)),
+        Pos2::new(to, edge_y(to)),
+        Pos2::new(to, edge_y(to) + thickness),
+        Pos2::new(from, edge_y(from) + thickness),
+    ];
+    canvas.convex_polygon(points, Color32::BLACK);
+}
+
+// Line widths come from the music font's engraving defaults when there is one
+fn stem_thickness(line_spacing: f32) -> f32 {
+    glyphs::music_font().map_or(1.5, |font| font.get_engraving_defaults().stem_thickness * line_spacing)
+}
+
+fn beam_thickness(line_spacing: f32) -> f32 {
+    glyphs::music_font().map_or(BEAM_THICKNESS, |font| font.get_engraving_defaults().beam_thickness) * line_spacing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::{Clef, SignatureTimeline, TimeSignature};
+    
+    fn staff() -> Staff {
+        Staff::new(Clef::Treble, Pos2::new(0.0, 100.0), 400.0)
+    }
+    
+    // Stems of the given (position, type, length) in 4/4, or in `meter`
+    fn stems<'a>(staff: &'a Staff, meter: TimeSignature, values: &[(f32, NoteType, f32)]) -> Vec<Stem<'a>> {
+        let mut timeline = SignatureTimeline::default();
+        timeline.add_time_signature(0.0, meter);
+        let measures = timeline.measures(16.0);
+        values.iter()
+            .map(|&(position, note_type, length)| Stem {
+                staff,
+                system: 0,
+                measure: measures[measures.partition_point(|measure| measure.start <= position + EPSILON) - 1],
+                position,
+                value: NoteValue::new(note_type, 0),
+                length,
+                x: position * 40.0,
+                heads: vec![staff.get_middle_line()],
+                color: Color32::BLACK,
+                direction: None,
+            })
+            .collect()
+    }
+    
+    fn groups(meter: TimeSignature, values: &[(f32, NoteType, f32)]) -> Vec<Range<usize>> {
+        let staff = staff();
+        beam_groups(&stems(&staff, meter, values))
+    }
+    
+    const EIGHTH: NoteType = NoteType::Eighth;
+    const SIXTEENTH: NoteType = NoteType::Sixteenth;
+    
+    #[test]
+    fn beams_break_at_beats_and_barlines() {
+        let common = TimeSignature::new(4, 4);
+        let eighths: Vec<(f32, NoteType, f32)> = (0..10).map(|i| (i as f32 * 0.5, EIGHTH, 0.5)).collect();
+        assert_eq!(groups(common, &eighths), vec![0..2, 2..4, 4..6, 6..8, 8..10]);
+        
+        // Compound meters group by the dotted quarter
+        let compound = TimeSignature::new(6, 8);
+        assert_eq!(groups(compound, &eighths[..6]), vec![0..3, 3..6]);
+        
+        let sixteenths = [(0.0, SIXTEENTH, 0.25), (0.25, SIXTEENTH, 0.25), (0.5, EIGHTH, 0.5), (1.0, SIXTEENTH, 0.25)];
+        assert_eq!(groups(common, &sixteenths), vec![0..3, 3..4]);
+    }
+    
+    #[test]
+    fn rests_and_longer_values_break_beams() {
+        let common = TimeSignature::new(4, 4);
+        // An eighth rest between the first two eighths of the beat
+        assert_eq!(groups(common, &[(0.0, SIXTEENTH, 0.25), (0.5, SIXTEENTH, 0.25), (0.75, SIXTEENTH, 0.25)]), vec![0..1, 1..3]);
+        assert_eq!(groups(common, &[(0.0, NoteType::Quarter, 1.0), (1.0, EIGHTH, 0.5), (1.5, EIGHTH, 0.5), (2.0, NoteType::Half, 2.0)]), vec![0..1, 1..3, 3..4]);
+    }
+    
+    #[test]
+    fn tuplets_beam_by_the_time_they_take() {
+        let common = TimeSignature::new(4, 4);
+        let third = 1.0 / 3.0;
+        let triplets = [
+            (0.0, EIGHTH, third),
+            (third, EIGHTH, third),
+            (2.0 * third, EIGHTH, third),
+            (1.0, EIGHTH, 0.5),
+            (1.5, EIGHTH, 0.5),
+        ];
+        assert_eq!(groups(common, &triplets), vec![0..3, 3..5]);
+        
+        // Inside one beat a tuplet beams on into plain values, but a gap left by
+        // its real length breaks the beam
+        let sixth = third / 2.0;
+        let mixed = [(0.0, SIXTEENTH, sixth), (sixth, SIXTEENTH, sixth), (third, SIXTEENTH, sixth), (0.5, SIXTEENTH, 0.25), (0.75, SIXTEENTH, 0.25)];
+        assert_eq!(groups(common, &mixed), vec![0..5]);
+        let gap = [(0.0, SIXTEENTH, sixth), (sixth, SIXTEENTH, sixth), (0.5, SIXTEENTH, 0.25)];
+        assert_eq!(groups(common, &gap), vec![0..2, 2..3]);
+    }
+    
+    #[test]
+    fn stems_point_away_from_the_furthest_head() {
+        let staff = staff();
+        let middle = staff.get_middle_line();
+        let space = staff.get_line_spacing();
+        assert_eq!(StemDirection::for_heads(&staff, &[middle]), StemDirection::Down);
+        assert_eq!(StemDirection::for_heads(&staff, &[middle - space]), StemDirection::Down);
+        assert_eq!(StemDirection::for_heads(&staff, &[middle + space]), StemDirection::Up);
+        assert_eq!(StemDirection::for_heads(&staff, &[middle - space, middle + 2.0 * space]), StemDirection::Up);
+        
+        // A beamed group shares the direction of its furthest head, unless its
+        // voice sets one
+        let mut group = stems(&staff, TimeSignature::new(4, 4), &[(0.0, EIGHTH, 0.5), (0.5, EIGHTH, 0.5), (1.0, NoteType::Quarter, 1.0)]);
+        group[0].heads = vec![middle - space];
+        group[1].heads = vec![middle + 3.0 * space];
+        group[2].heads = vec![middle + space];
+        assert_eq!(stem_directions(&group), vec![StemDirection::Up, StemDirection::Up, StemDirection::Up]);
+        group[0].direction = Some(StemDirection::Down);
+        group[2].direction = Some(StemDirection::Down);
+        assert_eq!(stem_directions(&group), vec![StemDirection::Down, StemDirection::Down, StemDirection::Down]);
+    }
+}
\ No newline at end of file
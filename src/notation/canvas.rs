@@ -0,0 +1,77 @@
+use eframe::egui::{epaint::CubicBezierShape, Align2, Color32, FontId, Painter, Pos2, Rect, Shape, Stroke};
+
+use super::glyphs::{Glyph, MusicFont};
+
+// The drawing operations notation is engraved with. The renderer, staves and
+// notes only talk to this trait, so the same layout can be painted into the
+// egui window or written out as an SVG or PNG file.
+pub trait Canvas {
+    fn line_segment(&mut self, points: [Pos2; 2], stroke: Stroke);
+    fn circle_filled(&mut self, center: Pos2, radius: f32, color: Color32);
+    fn circle_stroke(&mut self, center: Pos2, radius: f32, stroke: Stroke);
+    fn rect_filled(&mut self, rect: Rect, color: Color32);
+    fn convex_polygon(&mut self, points: Vec<Pos2>, color: Color32);
+    fn cubic_bezier(&mut self, points: [Pos2; 4], stroke: Stroke);
+
+    // Text in the proportional UI font, `size` pixels per em
+    fn text(&mut self, pos: Pos2, anchor: Align2, text: &str, size: f32, color: Color32);
+
+    // A music font glyph with its SMuFL origin at `origin`, one em being four
+    // staff spaces
+    fn glyph(&mut self, font: &MusicFont, glyph: Glyph, origin: Pos2, line_spacing: f32, color: Color32);
+}
+
+// Paints straight into an egui window
+pub struct EguiCanvas<'a> {
+    painter: &'a Painter,
+}
+
+impl<'a> EguiCanvas<'a> {
+    pub fn new(painter: &'a Painter) -> Self {
+        Self { painter }
+    }
+}
+
+impl Canvas for EguiCanvas<'_> {
+    fn line_segment(&mut self, points: [Pos2; 2], stroke: Stroke) {
+        self.painter.line_segment(points, stroke);
+    }
+
+    fn circle_filled(&mut self, center: Pos2, radius: f32, color: Color32) {
+        self.painter.circle_filled(center, radius, color);
+    }
+
+    fn circle_stroke(&mut self, center: Pos2, radius: f32, stroke: Stroke) {
+        self.painter.circle_stroke(center, radius, stroke);
+    }
+
+    fn rect_filled(&mut self, rect: Rect, color: Color32) {
+        self.painter.rect_filled(rect, 0.0, color);
+    }
+
+    fn convex_polygon(&mut self, points: Vec<Pos2>, color: Color32) {
+        self.painter.add(Shape::convex_polygon(points, color, Stroke::NONE));
+    }
+
+    fn cubic_bezier(&mut self, points: [Pos2; 4], stroke: Stroke) {
+        self.painter.add(Shape::CubicBezier(CubicBezierShape::from_points_stroke(
+            points,
+            false,
+            Color32::TRANSPARENT,
+            stroke,
+        )));
+    }
+
+    fn text(&mut self, pos: Pos2, anchor: Align2, text: &str, size: f32, color: Color32) {
+        self.painter.text(pos, anchor, text, FontId::proportional(size), color);
+    }
+
+    fn glyph(&mut self, font: &MusicFont, glyph: Glyph, origin: Pos2, line_spacing: f32, color: Color32) {
+        // egui lays text out from the top of the line, so shift by the baseline
+        let galley = self.painter.layout_no_wrap(glyph.codepoint.to_string(), font.font_id(line_spacing), color);
+        let baseline = galley.rows.first()
+            .and_then(|row| row.glyphs.first())
+            .map_or(Pos2::ZERO, |laid_out| laid_out.pos);
+        self.painter.galley(origin - baseline.to_vec2(), galley, color);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,365 @@
+use super::signature::Measure;
+use super::{Note, NoteType, Rest, Tuplet, WrittenRest};
+
+// Shortest written value (a thirty-second note); imported durations are
+// rounded to multiples of it
+pub const DURATION_GRID: f32 = 0.125;
+
+const EPSILON: f32 = 0.001;
+
+// Rounds an imported beat to the thirty-second grid. Inside a tuplet the grid
+// is scaled by the tuplet and counted from the start of its bracket (itself on
+// the grid), so tuplet notes keep their exact places.
+pub fn quantize(beat: f32, tuplet: Option<Tuplet>) -> f32 {
+    let step = grid_step(tuplet);
+    let origin = tuplet.map_or(0.0, |tuplet| tuplet.start);
+    origin + ((beat - origin) / step).round() * step
+}
+
+// Shortest length `quantize` leaves, which a tuplet shortens
+pub fn grid_step(tuplet: Option<Tuplet>) -> f32 {
+    tuplet.map_or(DURATION_GRID, |tuplet| DURATION_GRID / tuplet.ratio())
+}
+
+const NOTE_TYPES: [NoteType; 6] = [
+    NoteType::Whole,
+    NoteType::Half,
+    NoteType::Quarter,
+    NoteType::Eighth,
+    NoteType::Sixteenth,
+    NoteType::ThirtySecond,
+];
+
+// A single written duration: a note type plus augmentation dots
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoteValue {
+    pub note_type: NoteType,
+    pub dots: u8,
+}
+
+impl NoteValue {
+    pub fn new(note_type: NoteType, dots: u8) -> Self {
+        Self { note_type, dots }
+    }
+    
+    pub fn beats(&self) -> f32 {
+        // Each dot adds half of the previous value
+        self.note_type.beats() * (2.0 - 0.5f32.powi(self.dots as i32))
+    }
+    
+    // Largest single value no longer than `beats`
+    pub fn fitting(beats: f32) -> Self {
+        Self::candidates(true)
+            .find(|value| value.beats() <= beats + EPSILON)
+            .unwrap_or(Self::new(NoteType::ThirtySecond, 0))
+    }
+    
+    // Longest first; dotted values sit just above their plain version
+    fn candidates(allow_dots: bool) -> impl Iterator<Item = NoteValue> {
+        NOTE_TYPES.iter().flat_map(move |&note_type| {
+            let dotted = (allow_dots && note_type != NoteType::ThirtySecond).then(|| NoteValue::new(note_type, 1));
+            dotted.into_iter().chain(std::iter::once(NoteValue::new(note_type, 0)))
+        })
+    }
+    
+    // Tied values making up a held note of `length` beats, longest first
+    pub fn split_note(length: f32) -> Vec<NoteValue> {
+        let mut values = Vec::new();
+        let mut remaining = length;
+        
+        while remaining > EPSILON {
+            let value = Self::fitting(remaining);
+            values.push(value);
+            remaining -= value.beats();
+        }
+        values
+    }
+    
+    // Rests filling `length` beats from `offset` beats into a measure. Each rest
+    // starts on a multiple of its own length so the beats stay visible.
+    pub fn split_rest(offset: f32, length: f32) -> Vec<NoteValue> {
+        let mut values = Vec::new();
+        let mut offset = offset;
+        let mut remaining = length;
+        
+        while remaining > EPSILON {
+            let value = Self::candidates(false)
+                .find(|value| {
+                    let beats = value.beats();
+                    beats <= remaining + EPSILON && Self::is_multiple(offset, beats)
+                })
+                .unwrap_or(Self::new(NoteType::ThirtySecond, 0));
+            values.push(value);
+            offset += value.beats();
+            remaining -= value.beats();
+        }
+        values
+    }
+    
+    fn is_multiple(value: f32, step: f32) -> bool {
+        let ratio = value / step;
+        (ratio - ratio.round()).abs() < EPSILON
+    }
+}
+
+// Rests for one staff: the gaps between its notes, measure by measure, and a
+// whole-measure rest in measures with no notes at all. `notes` must be in time
+// order.
+pub fn infer_rests<'a>(notes: impl IntoIterator<Item = &'a Note>, measures: &[Measure]) -> Vec<Rest> {
+    let spans: Vec<(f32, f32)> = notes.into_iter().map(|note| (note.position, note.end())).collect();
+    let mut rests = Vec::new();
+    let mut next_span = 0;
+    let mut covered_until = 0.0f32;
+    
+    for measure in measures {
+        let end = measure.start + measure.length;
+        let mut cursor = measure.start.max(covered_until);
+        let mut has_notes = covered_until > measure.start + EPSILON;
+        
+        while let Some(&(start, stop)) = spans.get(next_span) {
+            if start >= end - EPSILON {
+                break;
+            }
+            if start > cursor + EPSILON {
+                push_rests(&mut rests, measure, cursor, start);
+            }
+            has_notes = true;
+            cursor = cursor.max(stop);
+            covered_until = covered_until.max(stop);
+            next_span += 1;
+        }
+        
+        if !has_notes {
+            rests.push(Rest {
+                position: measure.start,
+                value: NoteValue::new(NoteType::Whole, 0),
+                full_measure: true,
+                voice: None,
+            });
+        } else if cursor < end - EPSILON {
+            push_rests(&mut rests, measure, cursor, end);
+        }
+    }
+    
+    rests
+}
+
+fn push_rests(rests: &mut Vec<Rest>, measure: &Measure, from: f32, to: f32) {
+    let mut position = from;
+    for value in NoteValue::split_rest(from - measure.start, to - from) {
+        rests.push(Rest {
+            position,
+            value,
+            full_measure: false,
+            voice: None,
+        });
+        position += value.beats();
+    }
+}
+
+// Rests for one staff: the ones its source wrote, and in measures where it
+// wrote none (such as the start of a pickup measure), those inferred from the
+// gaps between its notes
+pub fn staff_rests<'a, 'b>(notes: impl IntoIterator<Item = &'a Note>, written: impl IntoIterator<Item = &'b WrittenRest>, measures: &[Measure]) -> Vec<Rest> {
+    let measure_of = |position: f32| measures.partition_point(|measure| measure.start <= position + EPSILON);
+    let mut rests = Vec::new();
+    for rest in written {
+        if rest.full_measure {
+            rests.push(Rest {
+                position: rest.position,
+                value: NoteValue::new(NoteType::Whole, 0),
+                full_measure: true,
+                voice: rest.voice,
+            });
+            continue;
+        }
+        
+        for (position, value) in rest_segments(rest) {
+            rests.push(Rest {
+                position,
+                value,
+                full_measure: false,
+                voice: rest.voice,
+            });
+        }
+    }
+    
+    let written_measures: Vec<usize> = rests.iter().map(|rest| measure_of(rest.position)).collect();
+    rests.extend(
+        infer_rests(notes, measures)
+            .into_iter()
+            .filter(|rest| !written_measures.contains(&measure_of(rest.position))),
+    );
+    rests
+}
+
+// Values a written rest is drawn with: one, unless its length needs several
+pub fn rest_segments(rest: &WrittenRest) -> Vec<(f32, NoteValue)> {
+    let ratio = rest.tuplet.map_or(1.0, |tuplet| tuplet.ratio());
+    let mut position = rest.position;
+    NoteValue::split_note(rest.duration * ratio)
+        .into_iter()
+        .map(|value| {
+            let segment = (position, value);
+            position += value.beats() / ratio;
+            segment
+        })
+        .collect()
+}
+
+// Written pieces of a note: split at barlines, then into tied values. A note
+// in a tuplet stays inside its bracket, so it is only split into values.
+pub fn note_segments(note: &Note, measures: &[Measure]) -> Vec<(f32, NoteValue)> {
+    let mut segments = Vec::new();
+    let mut position = note.position;
+    let end = note.end();
+    
+    if let Some(tuplet) = note.tuplet {
+        let ratio = tuplet.ratio();
+        for value in NoteValue::split_note(note.duration * ratio) {
+            segments.push((position, value));
+            position += value.beats() / ratio;
+        }
+        return segments;
+    }
+    
+    let first = measures.partition_point(|measure| measure.start <= position + EPSILON).saturating_sub(1);
+    for measure in &measures[first..] {
+        if position >= end - EPSILON {
+            break;
+        }
+        let segment_end = end.min(measure.start + measure.length);
+        for value in NoteValue::split_note(segment_end - position) {
+            segments.push((position, value));
+            position += value.beats();
+        }
+    }
+    
+    // Anything past the last measure is written without further barlines
+    if position < end - EPSILON {
+        for value in NoteValue::split_note(end - position) {
+            segments.push((position, value));
+            position += value.beats();
+        }
+    }
+    
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::SignatureTimeline;
+    
+    fn value(note_type: NoteType, dots: u8) -> NoteValue {
+        NoteValue::new(note_type, dots)
+    }
+    
+    fn common_time(end_beat: f32) -> Vec<Measure> {
+        SignatureTimeline::default().measures(end_beat)
+    }
+    
+    #[test]
+    fn held_notes_split_into_the_longest_values() {
+        assert_eq!(value(NoteType::Quarter, 1).beats(), 1.5);
+        assert_eq!(value(NoteType::Half, 2).beats(), 3.5);
+        assert_eq!(NoteValue::fitting(3.0), value(NoteType::Half, 1));
+        assert_eq!(NoteValue::fitting(2.9), value(NoteType::Half, 0));
+        assert_eq!(NoteValue::split_note(5.0), vec![value(NoteType::Whole, 0), value(NoteType::Quarter, 0)]);
+        assert_eq!(NoteValue::split_note(3.5), vec![value(NoteType::Half, 1), value(NoteType::Eighth, 0)]);
+    }
+    
+    #[test]
+    fn rests_start_on_multiples_of_their_length() {
+        assert_eq!(NoteValue::split_rest(0.0, 3.0), vec![value(NoteType::Half, 0), value(NoteType::Quarter, 0)]);
+        assert_eq!(NoteValue::split_rest(1.0, 3.0), vec![value(NoteType::Quarter, 0), value(NoteType::Half, 0)]);
+        assert_eq!(NoteValue::split_rest(0.5, 3.5), vec![
+            value(NoteType::Eighth, 0),
+            value(NoteType::Quarter, 0),
+            value(NoteType::Half, 0),
+        ]);
+        // Never dotted, so the beat in the middle stays visible
+        assert_eq!(NoteValue::split_rest(0.0, 1.5), vec![value(NoteType::Quarter, 0), value(NoteType::Eighth, 0)]);
+        assert_eq!(NoteValue::split_rest(0.75, 0.25), vec![value(NoteType::Sixteenth, 0)]);
+    }
+    
+    #[test]
+    fn gaps_between_notes_become_rests() {
+        let notes = [Note::with_duration(60, 0.5, 1.0), Note::with_duration(62, 8.0, 6.0)];
+        let rests: Vec<(f32, NoteValue, bool)> = infer_rests(&notes, &common_time(16.0)).iter()
+            .map(|rest| (rest.position, rest.value, rest.full_measure))
+            .collect();
+        
+        assert_eq!(rests, vec![
+            (0.0, value(NoteType::Eighth, 0), false),
+            (1.5, value(NoteType::Eighth, 0), false),
+            (2.0, value(NoteType::Half, 0), false),
+            (4.0, value(NoteType::Whole, 0), true),
+            // The note held over the barline covers the start of the last measure
+            (14.0, value(NoteType::Half, 0), false),
+        ]);
+    }
+    
+    #[test]
+    fn written_rests_replace_inferred_ones_in_their_measure() {
+        let notes = [Note::with_duration(60, 3.0, 1.0)];
+        let written = [WrittenRest {
+            position: 0.0,
+            duration: 3.0,
+            hand: None,
+            voice: Some(1),
+            tuplet: None,
+            full_measure: false,
+        }];
+        let rests: Vec<(f32, NoteValue, Option<u8>)> = staff_rests(&notes, &written, &common_time(8.0)).iter()
+            .map(|rest| (rest.position, rest.value, rest.voice))
+            .collect();
+        
+        assert_eq!(rests, vec![
+            (0.0, value(NoteType::Half, 1), Some(1)),
+            (4.0, value(NoteType::Whole, 0), None),
+        ]);
+    }
+    
+    #[test]
+    fn notes_are_tied_across_barlines() {
+        let measures = common_time(8.0);
+        assert_eq!(note_segments(&Note::with_duration(60, 3.0, 2.0), &measures), vec![
+            (3.0, value(NoteType::Quarter, 0)),
+            (4.0, value(NoteType::Quarter, 0)),
+        ]);
+        assert_eq!(note_segments(&Note::with_duration(60, 2.5, 3.0), &measures), vec![
+            (2.5, value(NoteType::Quarter, 1)),
+            (4.0, value(NoteType::Quarter, 1)),
+        ]);
+        // Held past the last measure without further barlines
+        assert_eq!(note_segments(&Note::with_duration(60, 4.0, 5.0), &measures), vec![
+            (4.0, value(NoteType::Whole, 0)),
+            (8.0, value(NoteType::Quarter, 0)),
+        ]);
+    }
+    
+    #[test]
+    fn tuplet_notes_keep_their_written_values() {
+        let tuplet = Tuplet { actual: 3, normal: 2, start: 3.0 };
+        let third = 1.0 / 3.0;
+        assert_eq!(quantize(3.0 + third + 0.01, Some(tuplet)), 3.0 + third);
+        assert_eq!(quantize(3.3, None), 3.25);
+        assert_eq!(grid_step(Some(tuplet)), DURATION_GRID * 2.0 / 3.0);
+        
+        // Not split at the barline its bracket crosses
+        let mut note = Note::with_duration(60, 3.0 + 2.0 * third, 2.0 * third);
+        note.tuplet = Some(tuplet);
+        assert_eq!(note_segments(&note, &common_time(8.0)), vec![(3.0 + 2.0 * third, value(NoteType::Quarter, 0))]);
+        
+        let rest = WrittenRest {
+            position: 3.0,
+            duration: third,
+            hand: None,
+            voice: None,
+            tuplet: Some(tuplet),
+            full_measure: false,
+        };
+        assert_eq!(rest_segments(&rest), vec![(3.0, value(NoteType::Eighth, 0))]);
+    }
+}
\ No newline at end of file
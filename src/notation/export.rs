@@ -0,0 +1,41 @@
+use anyhow::{bail, Context, Result};
+use eframe::egui::{Pos2, Rect};
+use std::fs;
+use std::path::Path;
+
+use super::{glyphs, Note, NotationRenderer, SignatureTimeline, WrittenRest};
+use super::raster::PixmapCanvas;
+use super::svg::SvgCanvas;
+
+// Engraves notes (and any written rests) without a window and writes them to
+// `path`. The format follows the extension: `.svg`, or `.png` rendered at
+// `scale` pixels per unit.
+pub fn export_score(notes: &[Note], rests: &[WrittenRest], signatures: &SignatureTimeline, path: &Path, width: f32, scale: f32) -> Result<()> {
+    glyphs::load();
+    
+    let mut renderer = NotationRenderer::new();
+    let height = renderer.score_size(notes, rests, signatures, width).y;
+    let rect = Rect::from_min_size(Pos2::ZERO, [width, height].into());
+    
+    let extension = path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_lowercase);
+    match extension.as_deref() {
+        Some("svg") => {
+            let mut canvas = SvgCanvas::new(width, height);
+            renderer.render_score(&mut canvas, rect, notes, rests, signatures);
+            fs::write(path, canvas.finish())
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        Some("png") => {
+            let mut canvas = PixmapCanvas::new(width, height, scale)?;
+            renderer.render_score(&mut canvas, rect, notes, rests, signatures);
+            canvas.save_png(path)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        _ => bail!("Unsupported score format {} (expected .svg or .png)", path.display()),
+    }
+    
+    log::info!("Exported score to {}", path.display());
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,353 @@
+use ab_glyph::FontArc;
+use eframe::egui::{self, Color32, FontFamily, FontId, Pos2, Vec2};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use super::{Accidental, Articulation, NoteType};
+use super::canvas::Canvas;
+use crate::storage;
+
+// Music symbols are drawn from Bravura, built in from `assets/fonts`. Another
+// SMuFL font (Leland, Petaluma, ...) with its metadata JSON can replace it from
+// a `fonts` directory in the data directory or next to the executable. Builds
+// without the bundled font and no font installed draw with the vector
+// fallbacks in each drawing function.
+
+const FAMILY: &str = "smufl";
+const FONT_EXTENSIONS: [&str; 2] = ["otf", "ttf"];
+
+// The font data and metadata JSON built into the binary
+#[cfg(bundled_music_font)]
+const BUNDLED_FONT: Option<(&[u8], &str)> = Some((
+    include_bytes!("../../assets/fonts/Bravura.otf"),
+    include_str!("../../assets/fonts/bravura_metadata.json"),
+));
+#[cfg(not(bundled_music_font))]
+const BUNDLED_FONT: Option<(&[u8], &str)> = None;
+
+static MUSIC_FONT: OnceLock<Option<MusicFont>> = OnceLock::new();
+
+// A SMuFL glyph: its canonical name (the key into the font metadata) and its
+// code point in the Private Use Area
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Glyph {
+    pub name: &'static str,
+    pub codepoint: char,
+}
+
+const fn glyph(name: &'static str, codepoint: char) -> Glyph {
+    Glyph { name, codepoint }
+}
+
+pub const G_CLEF: Glyph = glyph("gClef", '\u{E050}');
+pub const F_CLEF: Glyph = glyph("fClef", '\u{E062}');
+pub const AUGMENTATION_DOT: Glyph = glyph("augmentationDot", '\u{E1E7}');
+
+const TIME_SIGNATURE_DIGITS: [Glyph; 10] = [
+    glyph("timeSig0", '\u{E080}'),
+    glyph("timeSig1", '\u{E081}'),
+    glyph("timeSig2", '\u{E082}'),
+    glyph("timeSig3", '\u{E083}'),
+    glyph("timeSig4", '\u{E084}'),
+    glyph("timeSig5", '\u{E085}'),
+    glyph("timeSig6", '\u{E086}'),
+    glyph("timeSig7", '\u{E087}'),
+    glyph("timeSig8", '\u{E088}'),
+    glyph("timeSig9", '\u{E089}'),
+];
+
+impl Glyph {
+    pub fn notehead(note_type: NoteType) -> Self {
+        match note_type {
+            NoteType::Whole => glyph("noteheadWhole", '\u{E0A2}'),
+            NoteType::Half => glyph("noteheadHalf", '\u{E0A3}'),
+            _ => glyph("noteheadBlack", '\u{E0A4}'),
+        }
+    }
+    
+    // Flag glyphs already contain every flag for their value
+    pub fn flag(note_type: NoteType, stem_up: bool) -> Option<Self> {
+        let glyph = match (note_type, stem_up) {
+            (NoteType::Eighth, true) => glyph("flag8thUp", '\u{E240}'),
+            (NoteType::Eighth, false) => glyph("flag8thDown", '\u{E241}'),
+            (NoteType::Sixteenth, true) => glyph("flag16thUp", '\u{E242}'),
+            (NoteType::Sixteenth, false) => glyph("flag16thDown", '\u{E243}'),
+            (NoteType::ThirtySecond, true) => glyph("flag32ndUp", '\u{E244}'),
+            (NoteType::ThirtySecond, false) => glyph("flag32ndDown", '\u{E245}'),
+            _ => return None,
+        };
+        Some(glyph)
+    }
+    
+    pub fn rest(note_type: NoteType) -> Self {
+        match note_type {
+            NoteType::Whole => glyph("restWhole", '\u{E4E3}'),
+            NoteType::Half => glyph("restHalf", '\u{E4E4}'),
+            NoteType::Quarter => glyph("restQuarter", '\u{E4E5}'),
+            NoteType::Eighth => glyph("rest8th", '\u{E4E6}'),
+            NoteType::Sixteenth => glyph("rest16th", '\u{E4E7}'),
+            NoteType::ThirtySecond => glyph("rest32nd", '\u{E4E8}'),
+        }
+    }
+    
+    pub fn accidental(accidental: Accidental) -> Self {
+        match accidental {
+            Accidental::DoubleFlat => glyph("accidentalDoubleFlat", '\u{E264}'),
+            Accidental::Flat => glyph("accidentalFlat", '\u{E260}'),
+            Accidental::Natural => glyph("accidentalNatural", '\u{E261}'),
+            Accidental::Sharp => glyph("accidentalSharp", '\u{E262}'),
+            Accidental::DoubleSharp => glyph("accidentalDoubleSharp", '\u{E263}'),
+        }
+    }
+    
+    pub fn articulation(articulation: Articulation, above: bool) -> Self {
+        match (articulation, above) {
+            (Articulation::Accent, true) => glyph("articAccentAbove", '\u{E4A0}'),
+            (Articulation::Accent, false) => glyph("articAccentBelow", '\u{E4A1}'),
+            (Articulation::Staccato, true) => glyph("articStaccatoAbove", '\u{E4A2}'),
+            (Articulation::Staccato, false) => glyph("articStaccatoBelow", '\u{E4A3}'),
+            (Articulation::Tenuto, true) => glyph("articTenutoAbove", '\u{E4A4}'),
+            (Articulation::Tenuto, false) => glyph("articTenutoBelow", '\u{E4A5}'),
+            (Articulation::Staccatissimo, true) => glyph("articStaccatissimoAbove", '\u{E4A6}'),
+            (Articulation::Staccatissimo, false) => glyph("articStaccatissimoBelow", '\u{E4A7}'),
+            (Articulation::Marcato, true) => glyph("articMarcatoAbove", '\u{E4AC}'),
+            (Articulation::Marcato, false) => glyph("articMarcatoBelow", '\u{E4AD}'),
+            (Articulation::Fermata, true) => glyph("fermataAbove", '\u{E4C0}'),
+            (Articulation::Fermata, false) => glyph("fermataBelow", '\u{E4C1}'),
+        }
+    }
+    
+    pub fn time_signature_digits(value: u8) -> Vec<Self> {
+        value.to_string()
+            .bytes()
+            .map(|digit| TIME_SIGNATURE_DIGITS[(digit - b'0') as usize])
+            .collect()
+    }
+}
+
+// Glyph extents and anchors from the font metadata, in staff spaces with y
+// pointing up from the glyph origin
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct BoundingBox {
+    #[serde(rename = "bBoxNE")]
+    north_east: [f32; 2],
+    #[serde(rename = "bBoxSW")]
+    south_west: [f32; 2],
+}
+
+// Line thicknesses in staff spaces
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EngravingDefaults {
+    pub stem_thickness: f32,
+    pub beam_thickness: f32,
+    pub leger_line_thickness: f32,
+    pub staff_line_thickness: f32,
+}
+
+impl Default for EngravingDefaults {
+    // Bravura's values
+    fn default() -> Self {
+        Self {
+            stem_thickness: 0.12,
+            beam_thickness: 0.5,
+            leger_line_thickness: 0.16,
+            staff_line_thickness: 0.13,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FontMetadata {
+    #[serde(rename = "engravingDefaults", default)]
+    engraving_defaults: EngravingDefaults,
+    #[serde(rename = "glyphBBoxes", default)]
+    bounding_boxes: HashMap<String, BoundingBox>,
+    #[serde(rename = "glyphsWithAnchors", default)]
+    anchors: HashMap<String, HashMap<String, [f32; 2]>>,
+}
+
+pub struct MusicFont {
+    data: Vec<u8>,
+    outlines: FontArc, // For backends that draw glyphs as paths
+    metadata: FontMetadata,
+}
+
+// Loads the music font on first use: an installed font if there is one, the
+// bundled one otherwise. Headless exports only need this; the window also has
+// to `install` it into egui.
+pub fn load() -> Option<&'static MusicFont> {
+    MUSIC_FONT.get_or_init(|| {
+        find_font().and_then(|path| load_installed(&path)).or_else(load_bundled).or_else(|| {
+            log::info!("No SMuFL music font found, drawing notation with vector fallbacks");
+            None
+        })
+    }).as_ref()
+}
+
+fn load_installed(path: &Path) -> Option<MusicFont> {
+    let data = std::fs::read(path)
+        .map_err(|e| log::error!("Failed to read music font {}: {}", path.display(), e))
+        .ok()?;
+    let outlines = FontArc::try_from_vec(data.clone())
+        .map_err(|e| log::error!("Invalid music font {}: {}", path.display(), e))
+        .ok()?;
+    
+    let name = path.file_stem().map_or_else(|| FAMILY.to_owned(), |stem| stem.to_string_lossy().into_owned());
+    let metadata = load_metadata(path).unwrap_or_else(|| {
+        log::warn!("No metadata found for music font {}, using default glyph metrics", name);
+        FontMetadata::default()
+    });
+    
+    log::info!("Loaded music font {}", name);
+    Some(MusicFont { data, outlines, metadata })
+}
+
+fn load_bundled() -> Option<MusicFont> {
+    let (data, metadata) = BUNDLED_FONT?;
+    let outlines = FontArc::try_from_slice(data)
+        .map_err(|e| log::error!("Invalid bundled music font: {}", e))
+        .ok()?;
+    let metadata = serde_json::from_str(metadata)
+        .map_err(|e| log::error!("Invalid bundled music font metadata: {}", e))
+        .ok()?;
+    
+    log::info!("Loaded bundled music font Bravura");
+    Some(MusicFont { data: data.to_vec(), outlines, metadata })
+}
+
+// Loads the music font into egui's font definitions. Call once at startup,
+// before the first frame.
+pub fn install(ctx: &egui::Context) {
+    let Some(font) = load() else {
+        return;
+    };
+    
+    let mut fonts = egui::FontDefinitions::default();
+    fonts.font_data.insert(FAMILY.to_owned(), egui::FontData::from_owned(font.data.clone()));
+    fonts.families.insert(FontFamily::Name(FAMILY.into()), vec![FAMILY.to_owned()]);
+    ctx.set_fonts(fonts);
+}
+
+pub fn music_font() -> Option<&'static MusicFont> {
+    MUSIC_FONT.get().and_then(Option::as_ref)
+}
+
+fn font_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(dir) = storage::data_dir() {
+        dirs.push(dir.join("fonts"));
+    }
+    if let Some(dir) = std::env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf)) {
+        dirs.push(dir.join("fonts"));
+    }
+    dirs
+}
+
+fn find_font() -> Option<PathBuf> {
+    font_dirs().into_iter().find_map(|dir| {
+        let mut fonts: Vec<PathBuf> = std::fs::read_dir(dir).ok()?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| FONT_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+            })
+            .collect();
+        fonts.sort();
+        fonts.into_iter().next()
+    })
+}
+
+// SMuFL fonts ship their metadata as `<name>_metadata.json` (e.g.
+// bravura_metadata.json) or `metadata.json` beside the font file
+fn load_metadata(font_path: &Path) -> Option<FontMetadata> {
+    let dir = font_path.parent()?;
+    let stem = font_path.file_stem()?.to_string_lossy().to_lowercase();
+    
+    [format!("{}_metadata.json", stem), "metadata.json".to_owned()]
+        .iter()
+        .map(|file_name| dir.join(file_name))
+        .find(|path| path.exists())
+        .and_then(|path| {
+            let contents = std::fs::read_to_string(&path).ok()?;
+            serde_json::from_str(&contents)
+                .map_err(|e| log::warn!("Invalid music font metadata {}: {}", path.display(), e))
+                .ok()
+        })
+}
+
+impl MusicFont {
+    pub fn get_engraving_defaults(&self) -> &EngravingDefaults {
+        &self.metadata.engraving_defaults
+    }
+    
+    // Horizontal extent of a glyph from its origin, in staff spaces
+    fn horizontal_extent(&self, glyph: Glyph) -> (f32, f32) {
+        self.metadata.bounding_boxes.get(glyph.name)
+            .map_or((0.0, 1.18), |bbox| (bbox.south_west[0], bbox.north_east[0]))
+    }
+    
+    // Vertical extent of a glyph from its origin, in staff spaces with y up
+    fn vertical_extent(&self, glyph: Glyph) -> (f32, f32) {
+        self.metadata.bounding_boxes.get(glyph.name)
+            .map_or((0.0, 0.0), |bbox| (bbox.south_west[1], bbox.north_east[1]))
+    }
+    
+    pub fn width(&self, glyph: Glyph, line_spacing: f32) -> f32 {
+        let (left, right) = self.horizontal_extent(glyph);
+        (right - left) * line_spacing
+    }
+    
+    // Anchor point relative to the glyph origin, in staff spaces with y up
+    fn anchor(&self, glyph: Glyph, anchor: &str) -> Option<Vec2> {
+        self.metadata.anchors.get(glyph.name)?
+            .get(anchor)
+            .map(|&[x, y]| Vec2::new(x, y))
+    }
+    
+    // Where a stem attaches to a notehead centered on x = 0: the right edge of
+    // the head for up-stems, the left edge for down-stems
+    pub fn stem_offset(&self, note_type: NoteType, stem_up: bool, line_spacing: f32) -> f32 {
+        let notehead = Glyph::notehead(note_type);
+        let (left, right) = self.horizontal_extent(notehead);
+        let center = (left + right) / 2.0;
+        let anchor = if stem_up { "stemUpSE" } else { "stemDownNW" };
+        let x = self.anchor(notehead, anchor)
+            .map_or(if stem_up { right } else { left }, |anchor| anchor.x);
+        (x - center) * line_spacing
+    }
+    
+    // One em is four staff spaces
+    pub fn font_id(&self, line_spacing: f32) -> FontId {
+        FontId::new(4.0 * line_spacing, FontFamily::Name(FAMILY.into()))
+    }
+    
+    pub fn get_outlines(&self) -> &FontArc {
+        &self.outlines
+    }
+    
+    // Draws a glyph with its SMuFL origin at `origin`. The origin's height is
+    // the staff position the glyph belongs to (the line a clef names, the
+    // center of a notehead).
+    pub fn draw(&self, canvas: &mut dyn Canvas, glyph: Glyph, origin: Pos2, line_spacing: f32, color: Color32) {
+        canvas.glyph(self, glyph, origin, line_spacing, color);
+    }
+    
+    // Draws a glyph centered horizontally on `center`
+    pub fn draw_centered(&self, canvas: &mut dyn Canvas, glyph: Glyph, center: Pos2, line_spacing: f32, color: Color32) {
+        let (left, right) = self.horizontal_extent(glyph);
+        let origin_x = center.x - (left + right) / 2.0 * line_spacing;
+        self.draw(canvas, glyph, Pos2::new(origin_x, center.y), line_spacing, color);
+    }
+    
+    // Draws a glyph with the middle of its bounding box on `center`, for marks
+    // that have no staff position of their own
+    pub fn draw_box_centered(&self, canvas: &mut dyn Canvas, glyph: Glyph, center: Pos2, line_spacing: f32, color: Color32) {
+        let (bottom, top) = self.vertical_extent(glyph);
+        let origin_y = center.y + (bottom + top) / 2.0 * line_spacing;
+        self.draw_centered(canvas, glyph, Pos2::new(center.x, origin_y), line_spacing, color);
+    }
+}
\ No newline at end of file
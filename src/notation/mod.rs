@@ -1,7 +1,22 @@
 pub mod renderer;
 pub mod staff;
 pub mod notes;
+pub mod signature;
+pub mod pitch;
+pub mod duration;
+pub mod beam;
+pub mod spacing;
+pub mod glyphs;
+pub mod canvas;
+pub mod outline;
+pub mod svg;
+pub mod raster;
+pub mod export;
 
-pub use renderer::NotationRenderer;
+pub use renderer::{NotationRenderer, ScoreLayout};
+pub use export::export_score;
 pub use staff::{Staff, Clef};
-pub use notes::{Note, NoteType};
\ No newline at end of file
+pub use notes::{Articulation, Hand, Note, NoteType, PedalMarking, Rest, Tuplet, WrittenRest};
+pub use signature::{KeySignature, SignatureTimeline, TimeSignature};
+pub use pitch::{Accidental, MeasureAccidentals, SpelledPitch};
+pub use duration::{NoteValue, DURATION_GRID};
\ No newline at end of file
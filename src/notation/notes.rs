@@ -1,201 +1,394 @@
-use eframe::egui::{self, Painter, Pos2, Color32, Stroke};
+use eframe::egui::{Pos2, Rect, Vec2, Color32, Stroke};
+use serde::{Deserialize, Serialize};
+use super::{Clef, KeySignature, NoteValue, SpelledPitch, Staff};
+use super::canvas::Canvas;
+use super::glyphs::{self, Glyph};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum NoteType {
     Whole,
     Half,
     Quarter,
     Eighth,
+    Sixteenth,
+    ThirtySecond,
+}
+
+impl NoteType {
+    // Length in quarter-note beats
+    pub fn beats(&self) -> f32 {
+        match self {
+            NoteType::Whole => 4.0,
+            NoteType::Half => 2.0,
+            NoteType::Quarter => 1.0,
+            NoteType::Eighth => 0.5,
+            NoteType::Sixteenth => 0.25,
+            NoteType::ThirtySecond => 0.125,
+        }
+    }
+    
+    // Number of flags on the stem (or beams, when grouped)
+    pub fn flag_count(&self) -> usize {
+        match self {
+            NoteType::Whole | NoteType::Half | NoteType::Quarter => 0,
+            NoteType::Eighth => 1,
+            NoteType::Sixteenth => 2,
+            NoteType::ThirtySecond => 3,
+        }
+    }
+}
+
+// Which hand plays a note, and so which staff it is written on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Hand {
+    Right,
+    Left,
+}
+
+impl Hand {
+    pub fn clef(&self) -> Clef {
+        match self {
+            Hand::Right => Clef::Treble,
+            Hand::Left => Clef::Bass,
+        }
+    }
+    
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Hand::Right => "Right hand",
+            Hand::Left => "Left hand",
+        }
+    }
+}
+
+// Marks written above or below a notehead
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Articulation {
+    Staccato,
+    Staccatissimo,
+    Tenuto,
+    Accent,
+    Marcato,
+    Fermata,
+}
+
+impl Articulation {
+    // Fermatas always go above the staff; the others sit on the notehead side
+    // away from the stem
+    pub fn always_above(&self) -> bool {
+        matches!(self, Articulation::Fermata)
+    }
+    
+    // Draws the mark centered on `center`, opening away from the note
+    pub fn draw(&self, canvas: &mut dyn Canvas, center: Pos2, above: bool, line_spacing: f32, color: Color32) {
+        if let Some(font) = glyphs::music_font() {
+            font.draw_box_centered(canvas, Glyph::articulation(*self, above), center, line_spacing, color);
+            return;
+        }
+        
+        let s = line_spacing;
+        let side = if above { -1.0 } else { 1.0 };
+        let stroke = Stroke::new(1.5, color);
+        let Pos2 { x, y } = center;
+        match self {
+            Articulation::Staccato => canvas.circle_filled(center, 0.2 * s, color),
+            Articulation::Staccatissimo => {
+                canvas.convex_polygon(vec![
+                    Pos2::new(x - 0.2 * s, y + side * 0.4 * s),
+                    Pos2::new(x + 0.2 * s, y + side * 0.4 * s),
+                    Pos2::new(x, y - side * 0.4 * s),
+                ], color);
+            }
+            Articulation::Tenuto => {
+                canvas.line_segment([Pos2::new(x - 0.6 * s, y), Pos2::new(x + 0.6 * s, y)], Stroke::new(2.0, color));
+            }
+            Articulation::Accent => {
+                canvas.line_segment([Pos2::new(x - 0.7 * s, y - 0.35 * s), Pos2::new(x + 0.7 * s, y)], stroke);
+                canvas.line_segment([Pos2::new(x + 0.7 * s, y), Pos2::new(x - 0.7 * s, y + 0.35 * s)], stroke);
+            }
+            Articulation::Marcato => {
+                canvas.line_segment([Pos2::new(x - 0.45 * s, y - side * 0.5 * s), Pos2::new(x, y + side * 0.5 * s)], stroke);
+                canvas.line_segment([Pos2::new(x, y + side * 0.5 * s), Pos2::new(x + 0.45 * s, y - side * 0.5 * s)], stroke);
+            }
+            Articulation::Fermata => {
+                canvas.cubic_bezier([
+                    Pos2::new(x - 1.1 * s, y - side * 0.5 * s),
+                    Pos2::new(x - 1.0 * s, y + side * 0.8 * s),
+                    Pos2::new(x + 1.0 * s, y + side * 0.8 * s),
+                    Pos2::new(x + 1.1 * s, y - side * 0.5 * s),
+                ], stroke);
+                canvas.circle_filled(Pos2::new(x, y - side * 0.25 * s), 0.18 * s, color);
+            }
+        }
+    }
+}
+
+// Range of beats over which the sustain pedal should be held ("Ped. ... *")
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PedalMarking {
+    pub start: f32,
+    pub end: f32,
+}
+
+impl PedalMarking {
+    pub fn contains(&self, position: f32) -> bool {
+        position >= self.start && position < self.end
+    }
 }
 
-#[derive(Debug, Clone)]
+// Notes squeezed into the time of a different number of the same value, such
+// as three eighths in the time of two. Each note of a bracket carries it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Tuplet {
+    pub actual: u8,
+    pub normal: u8,
+    pub start: f32, // Beat where the bracket begins
+}
+
+impl Tuplet {
+    // Written length over the time actually taken
+    pub fn ratio(&self) -> f32 {
+        self.actual as f32 / self.normal as f32
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Note {
     pub pitch: u8,
-    pub note_type: NoteType,
     pub position: f32,
+    pub duration: f32, // In beats; may need several tied values to write
+    #[serde(default)]
+    pub hand: Option<Hand>, // None until the song assigns hands
+    // Notation carried over from engraved sources such as MusicXML; MIDI files
+    // leave these empty
+    #[serde(default)]
+    pub spelling: Option<SpelledPitch>, // None = spelled from the key signature
+    #[serde(default)]
+    pub voice: Option<u8>,
+    #[serde(default)]
+    pub articulations: Vec<Articulation>,
+    #[serde(default)]
+    pub fingering: Option<u8>,
+    #[serde(default)]
+    pub tuplet: Option<Tuplet>,
+    #[serde(skip)]
     pub is_correct: Option<bool>, // None = not played, Some(true) = correct, Some(false) = incorrect
 }
 
 impl Note {
-    pub fn new(pitch: u8, note_type: NoteType, position: f32) -> Self {
+    pub fn with_duration(pitch: u8, position: f32, duration: f32) -> Self {
         Self {
             pitch,
-            note_type,
             position,
+            duration,
+            hand: None,
+            spelling: None,
+            voice: None,
+            articulations: Vec::new(),
+            fingering: None,
+            tuplet: None,
             is_correct: None,
         }
     }
     
-    pub fn draw(&self, painter: &Painter, x: f32, y: f32) {
-        let color = match self.is_correct {
-            None => Color32::BLACK,
-            Some(true) => Color32::from_rgb(0, 150, 0),
-            Some(false) => Color32::from_rgb(200, 0, 0),
-        };
-        
-        match self.note_type {
-            NoteType::Whole => self.draw_whole_note(painter, x, y, color),
-            NoteType::Half => self.draw_half_note(painter, x, y, color),
-            NoteType::Quarter => self.draw_quarter_note(painter, x, y, color),
-            NoteType::Eighth => self.draw_eighth_note(painter, x, y, color),
+    // Hand whose staff the note is written on. Songs assign every note a hand;
+    // the pitch split only covers notes built without one.
+    pub fn written_hand(&self) -> Hand {
+        match self.hand {
+            Some(hand) => hand,
+            None if self.pitch >= 60 => Hand::Right,
+            None => Hand::Left,
         }
     }
     
-    pub fn draw_with_staff_info(&self, painter: &Painter, x: f32, y: f32, staff_top: f32, staff_bottom: f32, line_spacing: f32) {
-        let color = match self.is_correct {
+    // How the note is written in `key`: as the source spelled it, if it did
+    pub fn spelled_in(&self, key: KeySignature) -> SpelledPitch {
+        self.spelling.unwrap_or_else(|| key.spell(self.pitch))
+    }
+    
+    // Beat at which the note is released
+    pub fn end(&self) -> f32 {
+        self.position + self.duration
+    }
+    
+    pub fn color(&self) -> Color32 {
+        match self.is_correct {
             None => Color32::BLACK,
             Some(true) => Color32::from_rgb(0, 150, 0),
             Some(false) => Color32::from_rgb(200, 0, 0),
-        };
-        
-        // Draw ledger lines first (so they appear behind the note)
-        self.draw_ledger_lines(painter, x, y, staff_top, staff_bottom, line_spacing, color);
-        
-        // Draw the note
-        match self.note_type {
-            NoteType::Whole => self.draw_whole_note(painter, x, y, color),
-            NoteType::Half => self.draw_half_note(painter, x, y, color),
-            NoteType::Quarter => self.draw_quarter_note(painter, x, y, color),
-            NoteType::Eighth => self.draw_eighth_note(painter, x, y, color),
         }
     }
     
-    fn draw_whole_note(&self, painter: &Painter, x: f32, y: f32, color: Color32) {
-        painter.circle_stroke(
-            Pos2::new(x, y),
-            6.0,
-            Stroke::new(2.0, color),
-        );
-    }
-    
-    fn draw_half_note(&self, painter: &Painter, x: f32, y: f32, color: Color32) {
-        painter.circle_stroke(
-            Pos2::new(x, y),
-            6.0,
-            Stroke::new(2.0, color),
-        );
+    // Draws the notehead for one written value of the note (a held note may be
+    // several tied heads). Stems, flags and beams are added afterwards by the
+    // beaming pass, which needs to see the neighbouring notes.
+    pub fn draw_with_staff_info(&self, canvas: &mut dyn Canvas, value: NoteValue, x: f32, y: f32, staff: &Staff) {
+        let color = self.color();
         
-        // Draw stem
-        painter.line_segment(
-            [Pos2::new(x + 6.0, y), Pos2::new(x + 6.0, y - 24.0)],
-            Stroke::new(1.5, color),
-        );
-    }
-    
-    fn draw_quarter_note(&self, painter: &Painter, x: f32, y: f32, color: Color32) {
-        painter.circle_filled(
-            Pos2::new(x, y),
-            6.0,
-            color,
-        );
+        // Draw ledger lines first (so they appear behind the note)
+        self.draw_ledger_lines(canvas, x, y, staff, color);
         
-        // Draw stem
-        painter.line_segment(
-            [Pos2::new(x + 6.0, y), Pos2::new(x + 6.0, y - 24.0)],
-            Stroke::new(1.5, color),
-        );
-    }
-    
-    fn draw_eighth_note(&self, painter: &Painter, x: f32, y: f32, color: Color32) {
-        painter.circle_filled(
-            Pos2::new(x, y),
-            6.0,
-            color,
-        );
+        let dot_y = if staff.is_on_line(y) { y - staff.get_line_spacing() / 2.0 } else { y };
         
-        // Draw stem
-        painter.line_segment(
-            [Pos2::new(x + 6.0, y), Pos2::new(x + 6.0, y - 24.0)],
-            Stroke::new(1.5, color),
-        );
+        if let Some(font) = glyphs::music_font() {
+            let line_spacing = staff.get_line_spacing();
+            let notehead = Glyph::notehead(value.note_type);
+            font.draw_centered(canvas, notehead, Pos2::new(x, y), line_spacing, color);
+            
+            let mut dot_x = x + font.width(notehead, line_spacing) / 2.0 + 0.5 * line_spacing;
+            for _ in 0..value.dots {
+                font.draw(canvas, glyphs::AUGMENTATION_DOT, Pos2::new(dot_x, dot_y), line_spacing, color);
+                dot_x += font.width(glyphs::AUGMENTATION_DOT, line_spacing) + 0.3 * line_spacing;
+            }
+            return;
+        }
         
-        // Draw flag (simplified)
-        painter.line_segment(
-            [Pos2::new(x + 6.0, y - 24.0), Pos2::new(x + 12.0, y - 18.0)],
-            Stroke::new(2.0, color),
-        );
-    }
-    
-    fn draw_ledger_lines(&self, painter: &Painter, x: f32, y: f32, staff_top: f32, staff_bottom: f32, line_spacing: f32, color: Color32) {
-        let stroke = Stroke::new(1.0, color);
-        let ledger_line_length = 18.0; // Slightly longer than note head
-        let ledger_half_length = ledger_line_length / 2.0;
+        // Whole and half notes are hollow
+        match value.note_type {
+            NoteType::Whole | NoteType::Half => {
+                canvas.circle_stroke(
+                    Pos2::new(x, y),
+                    6.0,
+                    Stroke::new(2.0, color),
+                );
+            }
+            _ => {
+                canvas.circle_filled(
+                    Pos2::new(x, y),
+                    6.0,
+                    color,
+                );
+            }
+        }
         
-        // Determine if note is above or below staff
-        if y < staff_top - line_spacing / 4.0 {
-            // Note is above the staff - draw ledger lines above
-            self.draw_ledger_lines_above(painter, x, y, staff_top, line_spacing, ledger_half_length, stroke);
-        } else if y > staff_bottom + line_spacing / 4.0 {
-            // Note is below the staff - draw ledger lines below  
-            self.draw_ledger_lines_below(painter, x, y, staff_bottom, line_spacing, ledger_half_length, stroke);
+        for dot in 0..value.dots {
+            canvas.circle_filled(Pos2::new(x + 11.0 + dot as f32 * 5.0, dot_y), 1.8, color);
         }
-        // If note is within staff range, no ledger lines needed
     }
     
-    fn draw_ledger_lines_above(&self, painter: &Painter, x: f32, note_y: f32, staff_top: f32, line_spacing: f32, ledger_half_length: f32, stroke: Stroke) {
-        // Calculate the first ledger line position above the staff
-        let first_ledger_y = staff_top - line_spacing;
-        
-        // Find which ledger line position the note is closest to
-        let mut current_ledger_y = first_ledger_y;
+    fn draw_ledger_lines(&self, canvas: &mut dyn Canvas, x: f32, y: f32, staff: &Staff, color: Color32) {
+        let line_spacing = staff.get_line_spacing();
+        let thickness = glyphs::music_font()
+            .map_or(1.0, |font| font.get_engraving_defaults().leger_line_thickness * line_spacing);
+        let stroke = Stroke::new(thickness, color);
+        let ledger_half_length = 9.0; // Slightly longer than note head
         
-        // Continue drawing ledger lines until we've covered the note's position
-        while current_ledger_y >= note_y - line_spacing {
-            // Check if we need a ledger line at this position
-            if self.note_requires_ledger_line_at(note_y, current_ledger_y, line_spacing) {
-                painter.line_segment(
-                    [
-                        Pos2::new(x - ledger_half_length, current_ledger_y),
-                        Pos2::new(x + ledger_half_length, current_ledger_y),
-                    ],
+        // Determine if note is above or below staff
+        if y < staff.get_staff_top() - line_spacing / 4.0 {
+            // Note is above the staff - draw ledger lines above
+            let mut ledger_y = staff.get_staff_top() - line_spacing;
+            while ledger_y >= y - line_spacing / 4.0 {
+                canvas.line_segment(
+                    [Pos2::new(x - ledger_half_length, ledger_y), Pos2::new(x + ledger_half_length, ledger_y)],
                     stroke,
                 );
+                ledger_y -= line_spacing;
             }
-            current_ledger_y -= line_spacing;
-        }
-    }
-    
-    fn draw_ledger_lines_below(&self, painter: &Painter, x: f32, note_y: f32, staff_bottom: f32, line_spacing: f32, ledger_half_length: f32, stroke: Stroke) {
-        // Calculate the first ledger line position below the staff
-        let first_ledger_y = staff_bottom + line_spacing;
-        
-        // Find which ledger line position the note is closest to
-        let mut current_ledger_y = first_ledger_y;
-        
-        // Continue drawing ledger lines until we've covered the note's position
-        while current_ledger_y <= note_y + line_spacing {
-            // Check if we need a ledger line at this position
-            if self.note_requires_ledger_line_at(note_y, current_ledger_y, line_spacing) {
-                painter.line_segment(
-                    [
-                        Pos2::new(x - ledger_half_length, current_ledger_y),
-                        Pos2::new(x + ledger_half_length, current_ledger_y),
-                    ],
+        } else if y > staff.get_staff_bottom() + line_spacing / 4.0 {
+            // Note is below the staff - draw ledger lines below
+            let mut ledger_y = staff.get_staff_bottom() + line_spacing;
+            while ledger_y <= y + line_spacing / 4.0 {
+                canvas.line_segment(
+                    [Pos2::new(x - ledger_half_length, ledger_y), Pos2::new(x + ledger_half_length, ledger_y)],
                     stroke,
                 );
+                ledger_y += line_spacing;
             }
-            current_ledger_y += line_spacing;
         }
+        // If note is within staff range, no ledger lines needed
     }
-    
-    fn note_requires_ledger_line_at(&self, note_y: f32, ledger_y: f32, line_spacing: f32) -> bool {
-        let half_space = line_spacing / 2.0;
-        let tolerance = line_spacing / 8.0; // Small tolerance for floating point comparison
+}
+
+// A rest as an engraved source wrote it. Staves with written rests show
+// those instead of the ones inferred from the gaps.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WrittenRest {
+    pub position: f32,
+    pub duration: f32, // In beats, like a note's
+    pub hand: Option<Hand>, // None until the song assigns hands
+    #[serde(default)]
+    pub voice: Option<u8>,
+    #[serde(default)]
+    pub tuplet: Option<Tuplet>,
+    #[serde(default)]
+    pub full_measure: bool,
+}
+
+// Silence in one staff, written by the source or inferred from the gaps
+// between its notes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rest {
+    pub position: f32,
+    pub value: NoteValue,
+    pub full_measure: bool, // Drawn as a whole rest in the middle of the measure, whatever the meter
+    pub voice: Option<u8>, // As written; inferred rests belong to no voice
+}
+
+impl Rest {
+    // `shift` moves the rest down by that many line spaces (up when negative),
+    // out of the way of another voice
+    pub fn draw(&self, canvas: &mut dyn Canvas, x: f32, staff: &Staff, shift: f32) {
+        let color = Color32::BLACK;
+        let s = staff.get_line_spacing();
+        let top = staff.get_staff_top() + shift * s;
+        let middle = top + 2.0 * s;
+        let stroke = Stroke::new(1.5, color);
         
-        // Check if note is positioned on this ledger line
-        if (note_y - ledger_y).abs() < tolerance {
-            return true;
-        }
+        let note_type = if self.full_measure { NoteType::Whole } else { self.value.note_type };
         
-        // Check if note is in the space immediately above or below this ledger line
-        // Notes in spaces also need the bounding ledger lines
-        let space_above = ledger_y - half_space;
-        let space_below = ledger_y + half_space;
+        if let Some(font) = glyphs::music_font() {
+            // Whole rests hang from the fourth line; the others sit on or straddle the middle line
+            let origin_y = if note_type == NoteType::Whole { top + s } else { middle };
+            let glyph = Glyph::rest(note_type);
+            font.draw_centered(canvas, glyph, Pos2::new(x, origin_y), s, color);
+            
+            let mut dot_x = x + font.width(glyph, s) / 2.0 + 0.5 * s;
+            for _ in 0..self.value.dots {
+                font.draw(canvas, glyphs::AUGMENTATION_DOT, Pos2::new(dot_x, middle - 0.5 * s), s, color);
+                dot_x += font.width(glyphs::AUGMENTATION_DOT, s) + 0.3 * s;
+            }
+            return;
+        }
         
-        if (note_y - space_above).abs() < tolerance || (note_y - space_below).abs() < tolerance {
-            return true;
+        match note_type {
+            NoteType::Whole => {
+                // Hangs from the second line from the top
+                canvas.rect_filled(Rect::from_min_size(Pos2::new(x - 0.6 * s, top + s), Vec2::new(1.2 * s, 0.5 * s)), color);
+            }
+            NoteType::Half => {
+                // Sits on the middle line
+                canvas.rect_filled(Rect::from_min_size(Pos2::new(x - 0.6 * s, middle - 0.5 * s), Vec2::new(1.2 * s, 0.5 * s)), color);
+            }
+            NoteType::Quarter => {
+                let points = [
+                    Pos2::new(x - 0.2 * s, middle - 1.5 * s),
+                    Pos2::new(x + 0.35 * s, middle - 0.8 * s),
+                    Pos2::new(x - 0.25 * s, middle - 0.1 * s),
+                    Pos2::new(x + 0.3 * s, middle + 0.6 * s),
+                    Pos2::new(x - 0.2 * s, middle + 0.4 * s),
+                    Pos2::new(x + 0.1 * s, middle + 1.3 * s),
+                ];
+                for pair in points.windows(2) {
+                    canvas.line_segment([pair[0], pair[1]], Stroke::new(2.0, color));
+                }
+            }
+            NoteType::Eighth | NoteType::Sixteenth | NoteType::ThirtySecond => {
+                // A slanted stem with one hook per flag
+                let flags = note_type.flag_count();
+                let stem_top = Pos2::new(x + 0.4 * s, middle - 0.8 * s);
+                let stem_bottom = Pos2::new(x - 0.1 * s, middle + 0.4 * s + (flags - 1) as f32 * s);
+                canvas.line_segment([stem_top, stem_bottom], stroke);
+                
+                for flag in 0..flags {
+                    let hook = stem_top + (stem_bottom - stem_top) * (flag as f32 * s / (stem_bottom.y - stem_top.y));
+                    canvas.circle_filled(hook + Vec2::new(-0.5 * s, 0.1 * s), 0.22 * s, color);
+                    canvas.line_segment([hook + Vec2::new(-0.5 * s, 0.2 * s), hook], stroke);
+                }
+            }
         }
         
-        false
+        for dot in 0..self.value.dots {
+            canvas.circle_filled(Pos2::new(x + 0.9 * s + dot as f32 * 5.0, middle - 0.5 * s), 1.8, color);
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,138 @@
+use ab_glyph::{Font, FontArc, GlyphId, OutlineCurve};
+use eframe::egui::{self, Align, Align2, FontFamily, Pos2};
+use std::sync::OnceLock;
+
+use super::glyphs::{Glyph, MusicFont};
+
+// File backends have no text renderer of their own, so glyphs and labels are
+// turned into filled outlines here. Labels use the same fonts as the window,
+// including its fallbacks for symbols such as the clefs.
+
+static TEXT_OUTLINES: OnceLock<Vec<FontArc>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathCommand {
+    MoveTo(Pos2),
+    LineTo(Pos2),
+    QuadTo(Pos2, Pos2),
+    CubicTo(Pos2, Pos2, Pos2),
+    Close,
+}
+
+// Outline of one glyph with its origin (on the baseline) at `origin`, scaled
+// so one em is `size` pixels
+fn glyph_outline(font: &FontArc, id: GlyphId, origin: Pos2, size: f32, path: &mut Vec<PathCommand>) {
+    let Some(outline) = font.outline(id) else {
+        return;
+    };
+    let scale = size / font.units_per_em().unwrap_or(1000.0);
+    // Font units point up
+    let to_page = |point: ab_glyph::Point| Pos2::new(origin.x + point.x * scale, origin.y - point.y * scale);
+    
+    let mut last: Option<Pos2> = None;
+    for curve in &outline.curves {
+        let start = match curve {
+            OutlineCurve::Line(p0, _) | OutlineCurve::Quad(p0, _, _) | OutlineCurve::Cubic(p0, _, _, _) => to_page(*p0),
+        };
+        // A curve that does not continue the previous one starts a new contour
+        if last.is_none_or(|last| last.distance(start) > 0.01) {
+            if last.is_some() {
+                path.push(PathCommand::Close);
+            }
+            path.push(PathCommand::MoveTo(start));
+        }
+        let end = match curve {
+            OutlineCurve::Line(_, p1) => {
+                path.push(PathCommand::LineTo(to_page(*p1)));
+                to_page(*p1)
+            }
+            OutlineCurve::Quad(_, p1, p2) => {
+                path.push(PathCommand::QuadTo(to_page(*p1), to_page(*p2)));
+                to_page(*p2)
+            }
+            OutlineCurve::Cubic(_, p1, p2, p3) => {
+                path.push(PathCommand::CubicTo(to_page(*p1), to_page(*p2), to_page(*p3)));
+                to_page(*p3)
+            }
+        };
+        last = Some(end);
+    }
+    if last.is_some() {
+        path.push(PathCommand::Close);
+    }
+}
+
+// A music font glyph, one em being four staff spaces
+pub fn music_glyph(font: &MusicFont, glyph: Glyph, origin: Pos2, line_spacing: f32) -> Vec<PathCommand> {
+    let outlines = font.get_outlines();
+    let mut path = Vec::new();
+    glyph_outline(outlines, outlines.glyph_id(glyph.codepoint), origin, 4.0 * line_spacing, &mut path);
+    path
+}
+
+// egui's proportional fonts, in fallback order
+fn text_fonts() -> &'static [FontArc] {
+    TEXT_OUTLINES.get_or_init(|| {
+        let mut definitions = egui::FontDefinitions::default();
+        let names = definitions.families.remove(&FontFamily::Proportional).unwrap_or_default();
+        names.iter()
+            .filter_map(|name| {
+                let data = definitions.font_data.remove(name)?;
+                FontArc::try_from_vec(data.font.into_owned())
+                    .map_err(|e| log::error!("Invalid text font {}: {}", name, e))
+                    .ok()
+            })
+            .collect()
+    })
+}
+
+// First font with a glyph for `c`, like egui's fallback
+fn font_for(fonts: &[FontArc], c: char) -> (&FontArc, GlyphId) {
+    fonts.iter()
+        .map(|font| (font, font.glyph_id(c)))
+        .find(|(_, id)| id.0 != 0)
+        .unwrap_or_else(|| (&fonts[0], fonts[0].glyph_id(c)))
+}
+
+// A line of text placed the way egui's `Painter::text` places it
+pub fn text(text: &str, pos: Pos2, anchor: Align2, size: f32) -> Vec<PathCommand> {
+    let mut path = Vec::new();
+    let fonts = text_fonts();
+    let Some(main) = fonts.first() else {
+        return path;
+    };
+    let scale = |font: &FontArc| size / font.units_per_em().unwrap_or(1000.0);
+    
+    // Place each glyph along the line, kerning pairs from the same font
+    let mut glyphs = Vec::new();
+    let mut x = 0.0;
+    let mut previous: Option<(&FontArc, GlyphId)> = None;
+    for c in text.chars() {
+        let (font, id) = font_for(fonts, c);
+        if let Some((previous_font, previous_id)) = previous.filter(|(previous_font, _)| std::ptr::eq(*previous_font, font)) {
+            x += previous_font.kern_unscaled(previous_id, id) * scale(font);
+        }
+        glyphs.push((font, id, x));
+        x += font.h_advance_unscaled(id) * scale(font);
+        previous = Some((font, id));
+    }
+    let width = x;
+    let ascent = main.ascent_unscaled() * scale(main);
+    let height = ascent - main.descent_unscaled() * scale(main);
+    
+    let left = match anchor.x() {
+        Align::Min => pos.x,
+        Align::Center => pos.x - width / 2.0,
+        Align::Max => pos.x - width,
+    };
+    let top = match anchor.y() {
+        Align::Min => pos.y,
+        Align::Center => pos.y - height / 2.0,
+        Align::Max => pos.y - height,
+    };
+    
+    for (font, id, x) in glyphs {
+        glyph_outline(font, id, Pos2::new(left + x, top + ascent), size, &mut path);
+    }
+    path
+}
\ No newline at end of file
@@ -0,0 +1,355 @@
+use eframe::egui::{Color32, Pos2, Stroke};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::{Clef, KeySignature};
+use super::canvas::Canvas;
+use super::glyphs::{self, Glyph};
+
+const STEP_NAMES: [&str; 7] = ["C", "D", "E", "F", "G", "A", "B"];
+const STEP_PITCH_CLASSES: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+// Staff steps in the order sharps and flats are added to a key signature
+const SHARP_ORDER: [u8; 7] = [3, 0, 4, 1, 5, 2, 6]; // F C G D A E B
+const FLAT_ORDER: [u8; 7] = [6, 2, 5, 1, 4, 0, 3]; // B E A D G C F
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Accidental {
+    DoubleFlat,
+    Flat,
+    Natural,
+    Sharp,
+    DoubleSharp,
+}
+
+impl Accidental {
+    pub fn from_alter(alter: i8) -> Self {
+        match alter {
+            i8::MIN..=-2 => Accidental::DoubleFlat,
+            -1 => Accidental::Flat,
+            0 => Accidental::Natural,
+            1 => Accidental::Sharp,
+            _ => Accidental::DoubleSharp,
+        }
+    }
+    
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Accidental::DoubleFlat => "bb",
+            Accidental::Flat => "b",
+            Accidental::Natural => "",
+            Accidental::Sharp => "#",
+            Accidental::DoubleSharp => "##",
+        }
+    }
+    
+    // Horizontal room the symbol needs to the left of a notehead
+    pub fn width(&self, line_spacing: f32) -> f32 {
+        if let Some(font) = glyphs::music_font() {
+            return font.width(Glyph::accidental(*self), line_spacing);
+        }
+        match self {
+            Accidental::DoubleFlat | Accidental::DoubleSharp => line_spacing * 1.4,
+            _ => line_spacing * 0.9,
+        }
+    }
+    
+    // Draws the symbol centered on the given staff position
+    pub fn draw(&self, canvas: &mut dyn Canvas, center: Pos2, line_spacing: f32, color: Color32) {
+        if let Some(font) = glyphs::music_font() {
+            font.draw_centered(canvas, Glyph::accidental(*self), center, line_spacing, color);
+            return;
+        }
+        
+        let thin = Stroke::new(1.0, color);
+        let thick = Stroke::new(2.0, color);
+        let s = line_spacing;
+        let (x, y) = (center.x, center.y);
+        
+        match self {
+            Accidental::Sharp => {
+                for dx in [-0.15 * s, 0.15 * s] {
+                    canvas.line_segment([Pos2::new(x + dx, y - 1.1 * s), Pos2::new(x + dx, y + 1.1 * s)], thin);
+                }
+                for dy in [-0.35 * s, 0.35 * s] {
+                    canvas.line_segment([Pos2::new(x - 0.4 * s, y + dy + 0.12 * s), Pos2::new(x + 0.4 * s, y + dy - 0.12 * s)], thick);
+                }
+            }
+            Accidental::Flat | Accidental::DoubleFlat => {
+                let offsets: &[f32] = if *self == Accidental::Flat { &[0.0] } else { &[-0.35 * s, 0.35 * s] };
+                for &dx in offsets {
+                    let stem_x = x + dx - 0.25 * s;
+                    canvas.line_segment([Pos2::new(stem_x, y - 1.4 * s), Pos2::new(stem_x, y + 0.5 * s)], thin);
+                    canvas.cubic_bezier(
+                        [
+                            Pos2::new(stem_x, y - 0.1 * s),
+                            Pos2::new(stem_x + 0.7 * s, y - 0.5 * s),
+                            Pos2::new(stem_x + 0.7 * s, y + 0.1 * s),
+                            Pos2::new(stem_x, y + 0.5 * s),
+                        ],
+                        thick,
+                    );
+                }
+            }
+            Accidental::Natural => {
+                canvas.line_segment([Pos2::new(x - 0.25 * s, y - 1.1 * s), Pos2::new(x - 0.25 * s, y + 0.45 * s)], thin);
+                canvas.line_segment([Pos2::new(x + 0.25 * s, y - 0.45 * s), Pos2::new(x + 0.25 * s, y + 1.1 * s)], thin);
+                for dy in [-0.3 * s, 0.3 * s] {
+                    canvas.line_segment([Pos2::new(x - 0.25 * s, y + dy + 0.1 * s), Pos2::new(x + 0.25 * s, y + dy - 0.1 * s)], thick);
+                }
+            }
+            Accidental::DoubleSharp => {
+                let arm = 0.3 * s;
+                canvas.line_segment([Pos2::new(x - arm, y - arm), Pos2::new(x + arm, y + arm)], thick);
+                canvas.line_segment([Pos2::new(x - arm, y + arm), Pos2::new(x + arm, y - arm)], thick);
+            }
+        }
+    }
+}
+
+// A MIDI pitch written as a letter name with an alteration, which fixes its
+// line or space on the staff
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpelledPitch {
+    pub step: u8, // 0 = C ... 6 = B
+    pub alter: i8, // -1 = flat, 1 = sharp
+    pub octave: i8, // Scientific octave: middle C is C4
+}
+
+impl SpelledPitch {
+    // Spells `pitch` on the given letter; the alteration is whatever it takes to
+    // reach the pitch, and the octave follows the letter (B#3 is MIDI 60)
+    pub fn on_step(pitch: u8, step: u8) -> Self {
+        let natural_class = STEP_PITCH_CLASSES[step as usize];
+        let alter = ((pitch as i32 - natural_class + 6).rem_euclid(12) - 6) as i8;
+        let natural_pitch = pitch as i32 - alter as i32;
+        
+        Self {
+            step,
+            alter,
+            octave: (natural_pitch.div_euclid(12) - 1) as i8,
+        }
+    }
+    
+    // Number of staff steps above C-1; one step is half a line space
+    pub fn diatonic_index(&self) -> i32 {
+        (self.octave as i32 + 1) * 7 + self.step as i32
+    }
+    
+    pub fn name(&self) -> String {
+        format!("{}{}{}", STEP_NAMES[self.step as usize], Accidental::from_alter(self.alter).as_str(), self.octave)
+    }
+    
+    // The MIDI pitch sounded, or None when it falls outside 0-127
+    pub fn midi_pitch(&self) -> Option<u8> {
+        let pitch = (self.octave as i32 + 1) * 12 + STEP_PITCH_CLASSES[self.step as usize] + self.alter as i32;
+        u8::try_from(pitch).ok().filter(|&pitch| pitch <= 127)
+    }
+}
+
+impl KeySignature {
+    // Alteration the key signature gives to a letter
+    pub fn alter_for_step(&self, step: u8) -> i8 {
+        let count = self.fifths.unsigned_abs() as usize;
+        if self.fifths > 0 && SHARP_ORDER[..count].contains(&step) {
+            1
+        } else if self.fifths < 0 && FLAT_ORDER[..count].contains(&step) {
+            -1
+        } else {
+            0
+        }
+    }
+    
+    // Letters carrying a sharp or flat, in the order they are written
+    pub fn altered_steps(&self) -> &'static [u8] {
+        let count = self.fifths.unsigned_abs() as usize;
+        if self.fifths >= 0 {
+            &SHARP_ORDER[..count]
+        } else {
+            &FLAT_ORDER[..count]
+        }
+    }
+    
+    // Letter of the major or minor tonic
+    fn tonic_step(&self) -> u8 {
+        // Each fifth up moves the tonic four letters; minor keys sit a third lower
+        let major = (self.fifths as i32 * 4).rem_euclid(7);
+        let step = if self.minor { major + 5 } else { major };
+        (step % 7) as u8
+    }
+    
+    // Chooses how to write a pitch in this key: the scale's own spelling first,
+    // then a natural, then sharps in sharp keys and flats in flat keys. In minor
+    // keys the raised leading tone keeps the seventh degree's letter.
+    pub fn spell(&self, pitch: u8) -> SpelledPitch {
+        let pitch_class = (pitch % 12) as i32;
+        
+        if let Some(step) = (0..7).find(|&step| {
+            (STEP_PITCH_CLASSES[step as usize] + self.alter_for_step(step) as i32).rem_euclid(12) == pitch_class
+        }) {
+            return SpelledPitch::on_step(pitch, step);
+        }
+        
+        if self.minor {
+            let leading_step = (self.tonic_step() + 6) % 7;
+            let leading_tone = SpelledPitch::on_step(pitch, leading_step);
+            if leading_tone.alter == self.alter_for_step(leading_step) + 1 {
+                return leading_tone;
+            }
+        }
+        
+        if let Some(step) = (0..7).find(|&step| STEP_PITCH_CLASSES[step as usize] == pitch_class) {
+            return SpelledPitch::on_step(pitch, step);
+        }
+        
+        let neighbour_class = if self.fifths >= 0 { pitch_class - 1 } else { (pitch_class + 1) % 12 };
+        let step = (0..7)
+            .find(|&step| STEP_PITCH_CLASSES[step as usize] == neighbour_class)
+            .unwrap_or(0);
+        SpelledPitch::on_step(pitch, step)
+    }
+}
+
+// Decides which noteheads need an accidental. An accidental holds for the rest
+// of its measure on the same line or space of the same staff; the first note
+// back in the key after an altered measure gets a courtesy accidental.
+#[derive(Debug, Default)]
+pub struct MeasureAccidentals {
+    measure: Option<usize>,
+    current: HashMap<(Clef, i32), i8>,
+    previous: HashMap<(Clef, i32), i8>,
+}
+
+impl MeasureAccidentals {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    // Returns the accidental to draw, and whether it is only a courtesy
+    pub fn accidental_for(&mut self, clef: Clef, measure: usize, key: KeySignature, pitch: SpelledPitch) -> Option<(Accidental, bool)> {
+        if self.measure != Some(measure) {
+            let consecutive = self.measure.is_some_and(|previous| previous + 1 == measure);
+            self.previous = if consecutive { std::mem::take(&mut self.current) } else { HashMap::new() };
+            self.current.clear();
+            self.measure = Some(measure);
+        }
+        
+        let slot = (clef, pitch.diatonic_index());
+        let in_effect = self.current.get(&slot).copied();
+        let expected = in_effect.unwrap_or_else(|| key.alter_for_step(pitch.step));
+        
+        let accidental = if pitch.alter != expected {
+            Some((Accidental::from_alter(pitch.alter), false))
+        } else if in_effect.is_none() && self.previous.get(&slot).is_some_and(|&alter| alter != pitch.alter) {
+            Some((Accidental::from_alter(pitch.alter), true))
+        } else {
+            None
+        };
+        
+        self.current.insert(slot, pitch.alter);
+        accidental
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn spelled(key: KeySignature, pitch: u8) -> String {
+        key.spell(pitch).name()
+    }
+    
+    #[test]
+    fn spells_letters_with_their_octave() {
+        let b_sharp = SpelledPitch::on_step(60, 6);
+        assert_eq!((b_sharp.name(), b_sharp.midi_pitch()), ("B#3".to_string(), Some(60)));
+        let c_flat = SpelledPitch::on_step(71, 0);
+        assert_eq!((c_flat.name(), c_flat.midi_pitch()), ("Cb5".to_string(), Some(71)));
+        assert_eq!(SpelledPitch::on_step(60, 0).diatonic_index(), 35);
+        assert_eq!(SpelledPitch { step: 6, alter: 1, octave: 9 }.midi_pitch(), None);
+    }
+    
+    #[test]
+    fn sharp_keys_spell_with_sharps() {
+        let c = KeySignature::new(0, false);
+        assert_eq!(spelled(c, 61), "C#4");
+        assert_eq!(spelled(c, 70), "A#4");
+        
+        let d = KeySignature::new(2, false);
+        assert_eq!(d.altered_steps(), &[3, 0]);
+        assert_eq!(spelled(d, 66), "F#4");
+        assert_eq!(spelled(d, 73), "C#5");
+        assert_eq!(spelled(d, 65), "F4");
+        assert_eq!(spelled(d, 68), "G#4");
+        
+        // The scale's own spelling comes before the natural letter
+        let f_sharp = KeySignature::new(6, false);
+        assert_eq!(spelled(f_sharp, 65), "E#4");
+        assert_eq!(spelled(f_sharp, 60), "C4");
+        assert_eq!(spelled(KeySignature::new(7, false), 60), "B#3");
+    }
+    
+    #[test]
+    fn flat_keys_spell_with_flats() {
+        let b_flat = KeySignature::new(-2, false);
+        assert_eq!(b_flat.altered_steps(), &[6, 2]);
+        assert_eq!(spelled(b_flat, 70), "Bb4");
+        assert_eq!(spelled(b_flat, 63), "Eb4");
+        assert_eq!(spelled(b_flat, 61), "Db4");
+        assert_eq!(spelled(b_flat, 71), "B4");
+        
+        let g_flat = KeySignature::new(-6, false);
+        assert_eq!(spelled(g_flat, 71), "Cb5");
+    }
+    
+    #[test]
+    fn minor_keys_raise_the_leading_tone() {
+        assert_eq!(spelled(KeySignature::new(0, true), 68), "G#4");
+        // D minor writes C#, not the Db its flat would suggest
+        assert_eq!(spelled(KeySignature::new(-1, true), 61), "C#4");
+        // C minor: a natural on the B its signature flattens
+        assert_eq!(spelled(KeySignature::new(-3, true), 71), "B4");
+        assert_eq!(spelled(KeySignature::new(-3, false), 66), "Gb4");
+    }
+    
+    #[test]
+    fn accidentals_carry_through_the_measure() {
+        let c = KeySignature::new(0, false);
+        let mut accidentals = MeasureAccidentals::new();
+        let mut next = |clef, measure, pitch| accidentals.accidental_for(clef, measure, c, c.spell(pitch));
+        
+        assert_eq!(next(Clef::Treble, 0, 66), Some((Accidental::Sharp, false)));
+        assert_eq!(next(Clef::Treble, 0, 66), None);
+        // Another octave and another staff are not affected
+        assert_eq!(next(Clef::Treble, 0, 78), Some((Accidental::Sharp, false)));
+        assert_eq!(next(Clef::Bass, 0, 66), Some((Accidental::Sharp, false)));
+        // Going back to the key cancels it for the rest of the measure
+        assert_eq!(next(Clef::Treble, 0, 65), Some((Accidental::Natural, false)));
+        assert_eq!(next(Clef::Treble, 0, 65), None);
+        assert_eq!(next(Clef::Treble, 0, 66), Some((Accidental::Sharp, false)));
+    }
+    
+    #[test]
+    fn barlines_reset_accidentals_with_a_courtesy_in_the_next_measure() {
+        let c = KeySignature::new(0, false);
+        let mut accidentals = MeasureAccidentals::new();
+        let mut next = |measure, pitch| accidentals.accidental_for(Clef::Treble, measure, c, c.spell(pitch));
+        
+        assert_eq!(next(0, 66), Some((Accidental::Sharp, false)));
+        assert_eq!(next(1, 66), Some((Accidental::Sharp, false)));
+        assert_eq!(next(2, 65), Some((Accidental::Natural, true)));
+        assert_eq!(next(2, 65), None);
+        // Only the measure right after gets a courtesy
+        assert_eq!(next(3, 66), Some((Accidental::Sharp, false)));
+        assert_eq!(next(5, 65), None);
+    }
+    
+    #[test]
+    fn key_signatures_set_the_expected_alteration() {
+        let d = KeySignature::new(2, false);
+        let mut accidentals = MeasureAccidentals::new();
+        assert_eq!(accidentals.accidental_for(Clef::Treble, 0, d, d.spell(66)), None);
+        assert_eq!(accidentals.accidental_for(Clef::Treble, 0, d, d.spell(65)), Some((Accidental::Natural, false)));
+        assert_eq!(accidentals.accidental_for(Clef::Treble, 0, d, SpelledPitch::on_step(63, 1)), Some((Accidental::Sharp, false)));
+    }
+}
\ No newline at end of file
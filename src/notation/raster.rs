@@ -0,0 +1,120 @@
+use anyhow::{anyhow, Result};
+use eframe::egui::{Align2, Color32, Pos2, Rect, Stroke};
+use std::path::Path;
+use tiny_skia::{FillRule, Paint, PathBuilder, Pixmap, Transform};
+
+use super::canvas::Canvas;
+use super::glyphs::{Glyph, MusicFont};
+use super::outline::{self, PathCommand};
+
+// Rasterizes the score into an image. Drawing happens in the same units as
+// the window and is scaled up by `scale` for sharper output.
+pub struct PixmapCanvas {
+    pixmap: Pixmap,
+    transform: Transform,
+}
+
+impl PixmapCanvas {
+    pub fn new(width: f32, height: f32, scale: f32) -> Result<Self> {
+        let mut pixmap = Pixmap::new((width * scale).ceil() as u32, (height * scale).ceil() as u32)
+            .ok_or_else(|| anyhow!("Invalid image size {}x{}", width * scale, height * scale))?;
+        pixmap.fill(tiny_skia::Color::WHITE);
+        
+        Ok(Self {
+            pixmap,
+            transform: Transform::from_scale(scale, scale),
+        })
+    }
+    
+    pub fn save_png(&self, path: &Path) -> Result<()> {
+        self.pixmap.save_png(path)?;
+        Ok(())
+    }
+    
+    fn fill(&mut self, path: Option<tiny_skia::Path>, color: Color32) {
+        if let Some(path) = path {
+            self.pixmap.fill_path(&path, &paint(color), FillRule::Winding, self.transform, None);
+        }
+    }
+    
+    fn stroke(&mut self, path: Option<tiny_skia::Path>, stroke: Stroke) {
+        if let Some(path) = path {
+            let line = tiny_skia::Stroke { width: stroke.width, ..Default::default() };
+            self.pixmap.stroke_path(&path, &paint(stroke.color), &line, self.transform, None);
+        }
+    }
+    
+    fn fill_outline(&mut self, commands: &[PathCommand], color: Color32) {
+        let mut builder = PathBuilder::new();
+        for command in commands {
+            match *command {
+                PathCommand::MoveTo(p) => builder.move_to(p.x, p.y),
+                PathCommand::LineTo(p) => builder.line_to(p.x, p.y),
+                PathCommand::QuadTo(c, p) => builder.quad_to(c.x, c.y, p.x, p.y),
+                PathCommand::CubicTo(c1, c2, p) => builder.cubic_to(c1.x, c1.y, c2.x, c2.y, p.x, p.y),
+                PathCommand::Close => builder.close(),
+            }
+        }
+        self.fill(builder.finish(), color);
+    }
+}
+
+fn paint(color: Color32) -> Paint<'static> {
+    let [r, g, b, a] = color.to_srgba_unmultiplied();
+    let mut paint = Paint::default();
+    paint.set_color_rgba8(r, g, b, a);
+    paint.anti_alias = true;
+    paint
+}
+
+impl Canvas for PixmapCanvas {
+    fn line_segment(&mut self, points: [Pos2; 2], stroke: Stroke) {
+        let mut builder = PathBuilder::new();
+        builder.move_to(points[0].x, points[0].y);
+        builder.line_to(points[1].x, points[1].y);
+        self.stroke(builder.finish(), stroke);
+    }
+    
+    fn circle_filled(&mut self, center: Pos2, radius: f32, color: Color32) {
+        self.fill(PathBuilder::from_circle(center.x, center.y, radius), color);
+    }
+    
+    fn circle_stroke(&mut self, center: Pos2, radius: f32, stroke: Stroke) {
+        self.stroke(PathBuilder::from_circle(center.x, center.y, radius), stroke);
+    }
+    
+    fn rect_filled(&mut self, rect: Rect, color: Color32) {
+        if let Some(rect) = tiny_skia::Rect::from_ltrb(rect.min.x, rect.min.y, rect.max.x, rect.max.y) {
+            self.fill(Some(PathBuilder::from_rect(rect)), color);
+        }
+    }
+    
+    fn convex_polygon(&mut self, points: Vec<Pos2>, color: Color32) {
+        let mut builder = PathBuilder::new();
+        for (i, point) in points.iter().enumerate() {
+            if i == 0 {
+                builder.move_to(point.x, point.y);
+            } else {
+                builder.line_to(point.x, point.y);
+            }
+        }
+        builder.close();
+        self.fill(builder.finish(), color);
+    }
+    
+    fn cubic_bezier(&mut self, points: [Pos2; 4], stroke: Stroke) {
+        let [p0, p1, p2, p3] = points;
+        let mut builder = PathBuilder::new();
+        builder.move_to(p0.x, p0.y);
+        builder.cubic_to(p1.x, p1.y, p2.x, p2.y, p3.x, p3.y);
+        self.stroke(builder.finish(), stroke);
+    }
+    
+    fn text(&mut self, pos: Pos2, anchor: Align2, text: &str, size: f32, color: Color32) {
+        self.fill_outline(&outline::text(text, pos, anchor, size), color);
+    }
+    
+    fn glyph(&mut self, font: &MusicFont, glyph: Glyph, origin: Pos2, line_spacing: f32, color: Color32) {
+        self.fill_outline(&outline::music_glyph(font, glyph, origin, line_spacing), color);
+    }
+}
\ No newline at end of file
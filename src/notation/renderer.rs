@@ -1,118 +1,765 @@
-use eframe::egui::{self, Ui, Rect, Pos2};
+use eframe::egui::{self, Ui, Rect, Pos2, Stroke, Color32, Vec2};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use crate::game::GameEngine;
-use super::{Staff, Clef};
+use super::{Staff, Clef, MeasureAccidentals, Note, NoteValue, SignatureTimeline, SpelledPitch, WrittenRest};
+use super::canvas::{Canvas, EguiCanvas};
+use super::beam::{draw_stems, stem_directions, Stem, StemDirection};
+use super::duration::{note_segments, staff_rests};
+use super::signature::Measure;
+use super::spacing::{space_measures, ACCIDENTAL_COLUMN_STEPS, ACCIDENTAL_COLUMN_WIDTH, ACCIDENTAL_OFFSET, HEAD_RADIUS};
+
+// Space between a barline and the nearest note
+const MEASURE_PADDING: f32 = 16.0;
+// Room for a time signature printed after a barline
+const INLINE_TIME_SIGNATURE_WIDTH: f32 = 26.0;
+// The last system is only justified when it is at least this full
+const LAST_SYSTEM_MIN_FILL: f32 = 0.75;
+// How far the cursor reaches beyond the outer staff lines
+const CURSOR_OVERHANG: f32 = 12.0;
+const CURSOR_COLOR: Color32 = Color32::from_rgba_premultiplied(20, 60, 110, 70);
+// Articulations and fingerings, in line spaces from the notehead and from each other
+const MARK_DISTANCE: f32 = 1.2;
+const MARK_SPACING: f32 = 1.0;
+const FINGERING_SIZE: f32 = 1.3;
+// Rests of the upper and lower voices move this many line spaces off the middle
+const VOICE_REST_SHIFT: f32 = 2.0;
+// Tuplet numbers sit past the tips of the stems, which reach this far from the
+// outermost head and at least to the middle line; both in line spaces
+const TUPLET_STEM_REACH: f32 = 3.5;
+const TUPLET_NUMBER_DISTANCE: f32 = 1.0;
+const TUPLET_NUMBER_SIZE: f32 = 1.4;
+
+// How systems are arranged: down the page, or all on one line that scrolls
+// sideways past the cursor
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScoreLayout {
+    #[default]
+    Page,
+    Ticker,
+}
+
+pub struct MeasureLayout {
+    pub measure: Measure,
+    pub x: f32, // Left barline
+    pub width: f32,
+    pub shows_time_signature: bool, // Meter change printed after the left barline
+    pub columns: Vec<(f32, f32)>, // Beat and x of every note and rest onset
+}
+
+impl MeasureLayout {
+    fn content_start(&self) -> f32 {
+        let meter_width = if self.shows_time_signature { INLINE_TIME_SIGNATURE_WIDTH } else { 0.0 };
+        self.x + meter_width + MEASURE_PADDING
+    }
+    
+    // Notes sit on the column of their beat; beats between columns (or in
+    // a measure without any) are interpolated
+    pub fn note_x(&self, position: f32) -> f32 {
+        let start = (self.measure.start, self.content_start());
+        let end = (self.measure.start + self.measure.length, self.x + self.width - MEASURE_PADDING);
+        let after = self.columns.partition_point(|&(beat, _)| beat <= position + 0.001);
+        
+        let (from_beat, from_x) = after.checked_sub(1).map_or(start, |i| self.columns[i]);
+        if (from_beat - position).abs() < 0.001 {
+            return from_x;
+        }
+        let (to_beat, to_x) = self.columns.get(after).copied().unwrap_or(end);
+        from_x + (position - from_beat) / (to_beat - from_beat).max(f32::EPSILON) * (to_x - from_x)
+    }
+}
+
+// One notehead placed on the page; long notes have several, tied together
+struct PlacedHead<'a> {
+    note_index: usize,
+    segment: usize, // Tied heads of the same note count up from 0
+    system: &'a StaffSystem,
+    layout: &'a MeasureLayout,
+    staff: &'a Staff,
+    position: f32,
+    value: NoteValue,
+    spelled: SpelledPitch,
+    x: f32,
+    y: f32,
+    tie_above: bool,
+}
+
+// A tuplet bracket on one staff: its system, voice and starting beat in
+// thousandths
+type TupletKey = (usize, Option<u8>, i64);
 
 pub struct StaffSystem {
     pub treble_staff: Staff,
     pub bass_staff: Staff,
     pub system_number: usize,
+    pub measures: Vec<MeasureLayout>,
 }
 
 pub struct NotationRenderer {
     staff_systems: Vec<StaffSystem>,
-    notes_per_system: usize,
     system_height: f32,
     system_spacing: f32,
+    show_note_names: bool,
+    layout: ScoreLayout,
+    cursor: Option<f32>, // Beat marked by the cursor
 }
 
 impl NotationRenderer {
     pub fn new() -> Self {
         Self {
             staff_systems: Vec::new(),
-            notes_per_system: 8, // Number of notes per staff system
             system_height: 120.0, // Height of each staff system (treble + bass + spacing)
             system_spacing: 40.0, // Spacing between systems
+            show_note_names: false,
+            layout: ScoreLayout::Page,
+            cursor: None,
         }
     }
     
-    pub fn calculate_content_height(&self, game_engine: &GameEngine) -> f32 {
-        let notes = game_engine.get_current_notes();
-        let num_systems = ((notes.len() as f32) / (self.notes_per_system as f32)).ceil() as usize;
-        let num_systems = num_systems.max(1); // At least one system
+    pub fn set_show_note_names(&mut self, show: bool) {
+        self.show_note_names = show;
+    }
+    
+    pub fn set_layout(&mut self, layout: ScoreLayout) {
+        self.layout = layout;
+    }
+    
+    pub fn calculate_content_size(&mut self, game_engine: &GameEngine, width: f32) -> Vec2 {
+        self.score_size(game_engine.get_current_notes(), game_engine.get_current_rests(), game_engine.get_signatures(), width)
+    }
+    
+    // Room needed to engrave the given notes at this width. A ticker is one
+    // system high and as wide as the music.
+    pub fn score_size(&mut self, notes: &[Note], rests: &[WrittenRest], signatures: &SignatureTimeline, width: f32) -> Vec2 {
+        self.layout_systems(Pos2::ZERO, width, notes, rests, signatures);
         
-        (num_systems as f32) * (self.system_height + self.system_spacing) + 40.0
+        match self.layout {
+            ScoreLayout::Page => {
+                let num_systems = self.staff_systems.len().max(1); // At least one system
+                Vec2::new(width, (num_systems as f32) * (self.system_height + self.system_spacing) + 40.0)
+            }
+            ScoreLayout::Ticker => {
+                let end = self.staff_systems.last()
+                    .map_or(width, |system| system.treble_staff.position.x + system.treble_staff.width + 20.0);
+                Vec2::new(end.max(width), self.system_height + self.system_spacing + 40.0)
+            }
+        }
     }
-
+    
     pub fn render(&mut self, ui: &mut Ui, rect: Rect, game_engine: &GameEngine) {
-        let painter = ui.painter();
-        let notes = game_engine.get_current_notes();
-        
-        // Calculate number of systems needed
-        let num_systems = ((notes.len() as f32) / (self.notes_per_system as f32)).ceil() as usize;
-        let num_systems = num_systems.max(1);
+        self.cursor = game_engine.get_cursor_beat();
+        let mut canvas = EguiCanvas::new(ui.painter());
+        self.render_score(&mut canvas, rect, game_engine.get_current_notes(), game_engine.get_current_rests(), game_engine.get_signatures());
+    }
+    
+    // Area the cursor covers at `beat` in the last layout, from above the
+    // treble staff to below the bass staff
+    pub fn cursor_rect(&self, beat: f32) -> Option<Rect> {
+        let (system, layout) = self.staff_systems.iter()
+            .flat_map(|system| system.measures.iter().map(move |layout| (system, layout)))
+            .take_while(|(_, layout)| layout.measure.start <= beat + 0.001)
+            .last()?;
         
-        // Create staff systems if needed
-        self.update_staff_systems(num_systems, rect);
+        let x = layout.note_x(beat.min(layout.measure.start + layout.measure.length));
+        let half_width = 1.5 * HEAD_RADIUS;
+        Some(Rect::from_min_max(
+            Pos2::new(x - half_width, system.treble_staff.get_staff_top() - CURSOR_OVERHANG),
+            Pos2::new(x + half_width, system.bass_staff.get_staff_bottom() + CURSOR_OVERHANG),
+        ))
+    }
+    
+    pub fn render_score(&mut self, canvas: &mut dyn Canvas, rect: Rect, notes: &[Note], rests: &[WrittenRest], signatures: &SignatureTimeline) {
+        // Break the song into systems of whole measures
+        self.layout_systems(rect.min, rect.width(), notes, rests, signatures);
         
         // Draw all staff systems
         for system in &self.staff_systems {
-            system.treble_staff.draw(&painter);
-            system.bass_staff.draw(&painter);
+            system.treble_staff.draw(canvas);
+            system.bass_staff.draw(canvas);
+            self.draw_measures(canvas, system);
+        }
+        
+        // The cursor sits behind the notes it passes over
+        if let Some(rect) = self.cursor.and_then(|beat| self.cursor_rect(beat)) {
+            canvas.rect_filled(rect, CURSOR_COLOR);
         }
         
         // Draw notes across multiple systems
-        self.draw_notes_across_systems(&painter, game_engine);
+        self.draw_notes_across_systems(canvas, notes, rests);
     }
     
-    fn update_staff_systems(&mut self, num_systems: usize, rect: Rect) {
+    fn layout_systems(&mut self, origin: Pos2, width: f32, notes: &[Note], rests: &[WrittenRest], signatures: &SignatureTimeline) {
         self.staff_systems.clear();
-        let staff_width = rect.width() - 40.0;
+        let staff_width = width - 40.0;
+        
+        let end_beat = notes.iter()
+            .map(|note| note.end())
+            .chain(rests.iter().map(|rest| rest.position + rest.duration))
+            .fold(0.0, f32::max);
+        let measures = signatures.measures(end_beat);
+        let line_spacing = Staff::new(Clef::Treble, origin, staff_width).get_line_spacing();
+        let spacings = space_measures(notes, rests, &measures, line_spacing, Self::staff_for);
         
-        for i in 0..num_systems {
-            let system_y = rect.min.y + 20.0 + (i as f32) * (self.system_height + self.system_spacing);
-            let treble_y = system_y;
-            let bass_y = system_y + 80.0; // 80 pixels between treble and bass staff
+        // The meter is printed at the start of the piece and wherever it changes
+        let meter_changes = |measure: &Measure| {
+            measure.index == 0 || measures[measure.index - 1].time_signature != measure.time_signature
+        };
+        
+        let ticker = self.layout == ScoreLayout::Ticker;
+        let mut remaining = &measures[..];
+        while let Some(first) = remaining.first() {
+            let system_number = self.staff_systems.len();
             
-            let treble_staff = Staff::new(
-                Clef::Treble,
-                Pos2::new(rect.min.x + 20.0, treble_y),
-                staff_width
-            );
+            // A ticker only starts a new system for a key change, placed after
+            // the previous one on the same line
+            let system_origin = match (self.layout, self.staff_systems.last()) {
+                (ScoreLayout::Ticker, Some(previous)) => {
+                    Pos2::new(previous.treble_staff.position.x + previous.treble_staff.width + 20.0, origin.y + 20.0)
+                }
+                (ScoreLayout::Ticker, None) => Pos2::new(origin.x + 20.0, origin.y + 20.0),
+                (ScoreLayout::Page, _) => {
+                    Pos2::new(origin.x + 20.0, origin.y + 20.0 + (system_number as f32) * (self.system_height + self.system_spacing))
+                }
+            };
             
-            let bass_staff = Staff::new(
-                Clef::Bass,
-                Pos2::new(rect.min.x + 20.0, bass_y),
-                staff_width
-            );
+            let mut treble_staff = Staff::new(Clef::Treble, system_origin, staff_width);
+            let mut bass_staff = Staff::new(Clef::Bass, system_origin + Vec2::new(0.0, 80.0), staff_width); // 80 pixels between treble and bass staff
+            
+            for staff in [&mut treble_staff, &mut bass_staff] {
+                staff.set_key_signature(first.key_signature);
+                staff.set_time_signature(meter_changes(first).then_some(first.time_signature));
+            }
+            
+            // Fill the system with whole measures. A key change starts a new system
+            // so the new key appears in its header.
+            let content_start = treble_staff.position.x + treble_staff.header_width();
+            let available = if ticker { f32::INFINITY } else { treble_staff.position.x + staff_width - content_start };
+            let mut count = 0;
+            let mut fixed_width = 0.0;
+            let mut spaced_width = 0.0;
+            
+            for (i, measure) in remaining.iter().enumerate() {
+                if i > 0 && measure.key_signature != first.key_signature {
+                    break;
+                }
+                let measure_fixed = Self::measure_fixed_width(i > 0 && meter_changes(measure));
+                let measure_spaced = spacings[measure.index].natural_width();
+                if i > 0 && fixed_width + measure_fixed + spaced_width + measure_spaced > available {
+                    break;
+                }
+                fixed_width += measure_fixed;
+                spaced_width += measure_spaced;
+                count += 1;
+            }
+            
+            // Stretch the spacing to fill the line, except in a short final system
+            let natural_width = fixed_width + spaced_width;
+            let is_last = count == remaining.len();
+            let stretch = if ticker || (is_last && natural_width < available * LAST_SYSTEM_MIN_FILL) {
+                1.0
+            } else {
+                (available - fixed_width).max(0.0) / spaced_width.max(f32::EPSILON)
+            };
+            
+            let mut x = content_start;
+            let mut layouts = Vec::with_capacity(count);
+            for (i, measure) in remaining[..count].iter().enumerate() {
+                let shows_time_signature = i > 0 && meter_changes(measure);
+                let spacing = &spacings[measure.index];
+                let width = Self::measure_fixed_width(shows_time_signature) + spacing.natural_width() * stretch;
+                let mut layout = MeasureLayout {
+                    measure: *measure,
+                    x,
+                    width,
+                    shows_time_signature,
+                    columns: Vec::new(),
+                };
+                let content_start = layout.content_start();
+                layout.columns = spacing.positions.iter()
+                    .zip(spacing.offsets(stretch))
+                    .map(|(&beat, offset)| (beat, content_start + offset))
+                    .collect();
+                layouts.push(layout);
+                x += width;
+            }
+            
+            // A short final system (or a ticker's) ends at its last barline
+            for staff in [&mut treble_staff, &mut bass_staff] {
+                staff.width = x - staff.position.x;
+            }
             
             self.staff_systems.push(StaffSystem {
                 treble_staff,
                 bass_staff,
-                system_number: i,
+                system_number,
+                measures: layouts,
             });
+            remaining = &remaining[count..];
+        }
+    }
+    
+    fn measure_fixed_width(shows_time_signature: bool) -> f32 {
+        let meter_width = if shows_time_signature { INLINE_TIME_SIGNATURE_WIDTH } else { 0.0 };
+        2.0 * MEASURE_PADDING + meter_width
+    }
+    
+    // Barlines across the grand staff, inline meter changes and the measure number
+    // at the start of each system
+    fn draw_measures(&self, canvas: &mut dyn Canvas, system: &StaffSystem) {
+        let top = system.treble_staff.get_staff_top();
+        let bottom = system.bass_staff.get_staff_bottom();
+        let thin = Stroke::new(1.0, Color32::BLACK);
+        
+        // The two staves of a piano system are joined at the left
+        let left = system.treble_staff.position.x;
+        canvas.line_segment([Pos2::new(left, top), Pos2::new(left, bottom)], thin);
+        
+        let last_index = self.staff_systems.last()
+            .and_then(|last| last.measures.last())
+            .map(|layout| layout.measure.index);
+        
+        for layout in &system.measures {
+            let right = layout.x + layout.width;
+            
+            if Some(layout.measure.index) == last_index {
+                // Final barline: thin then thick
+                canvas.line_segment([Pos2::new(right - 5.0, top), Pos2::new(right - 5.0, bottom)], thin);
+                canvas.line_segment([Pos2::new(right - 1.5, top), Pos2::new(right - 1.5, bottom)], Stroke::new(3.0, Color32::BLACK));
+            } else {
+                canvas.line_segment([Pos2::new(right, top), Pos2::new(right, bottom)], thin);
+            }
+            
+            if layout.shows_time_signature {
+                let x = layout.x + INLINE_TIME_SIGNATURE_WIDTH / 2.0 + 4.0;
+                system.treble_staff.draw_time_signature(canvas, x, layout.measure.time_signature);
+                system.bass_staff.draw_time_signature(canvas, x, layout.measure.time_signature);
+            }
+        }
+        
+        if system.system_number > 0 {
+            if let Some(first) = system.measures.first() {
+                canvas.text(
+                    Pos2::new(left, top - 14.0),
+                    egui::Align2::LEFT_BOTTOM,
+                    &(first.measure.index + 1).to_string(),
+                    11.0,
+                    Color32::DARK_GRAY,
+                );
+            }
         }
     }
     
-    fn draw_notes_across_systems(&self, painter: &egui::Painter, game_engine: &GameEngine) {
-        let current_notes = game_engine.get_current_notes();
+    fn draw_notes_across_systems(&self, canvas: &mut dyn Canvas, current_notes: &[Note], current_rests: &[WrittenRest]) {
+        
+        // Every measure of the song in order, so a measure's index is its position
+        let layouts: Vec<(&StaffSystem, &MeasureLayout)> = self.staff_systems.iter()
+            .flat_map(|system| system.measures.iter().map(move |layout| (system, layout)))
+            .collect();
+        if layouts.is_empty() {
+            return;
+        }
+        let measures: Vec<Measure> = layouts.iter().map(|(_, layout)| layout.measure).collect();
+        let layout_at = |position: f32| {
+            let index = measures.partition_point(|measure| measure.start <= position + 0.001);
+            layouts[index.saturating_sub(1).min(layouts.len() - 1)]
+        };
+        
+        for clef in [Clef::Treble, Clef::Bass] {
+            let staff_notes: Vec<&Note> = current_notes.iter().filter(|note| Self::staff_for(note) == clef).collect();
+            let staff_written = current_rests.iter().filter(|rest| rest.hand.map(|hand| hand.clef()) == Some(clef));
+            let rests = staff_rests(staff_notes.iter().copied(), staff_written, &measures);
+            
+            // In a measure with several voices, the first voice's rests move up
+            // and the others' down
+            let mut measure_voices: BTreeMap<usize, BTreeSet<Option<u8>>> = BTreeMap::new();
+            for note in &staff_notes {
+                measure_voices.entry(layout_at(note.position).1.measure.index).or_default().insert(note.voice);
+            }
+            for rest in rests.iter().filter(|rest| rest.voice.is_some()) {
+                measure_voices.entry(layout_at(rest.position).1.measure.index).or_default().insert(rest.voice);
+            }
+            
+            for rest in rests {
+                let (system, layout) = layout_at(rest.position);
+                let staff = if clef == Clef::Treble { &system.treble_staff } else { &system.bass_staff };
+                let x = if rest.full_measure {
+                    layout.x + layout.width / 2.0
+                } else {
+                    layout.note_x(rest.position)
+                };
+                let voices = measure_voices.get(&layout.measure.index);
+                let shift = match voices {
+                    Some(voices) if voices.len() > 1 && rest.voice.is_some() => {
+                        if voices.first() == Some(&rest.voice) { -VOICE_REST_SHIFT } else { VOICE_REST_SHIFT }
+                    }
+                    _ => 0.0,
+                };
+                rest.draw(canvas, x, staff, shift);
+            }
+        }
         
-        for (i, note) in current_notes.iter().enumerate() {
-            // Determine which system this note belongs to
-            let system_index = i / self.notes_per_system;
-            let note_index_in_system = i % self.notes_per_system;
+        // A note held across a barline or too long for one value is written as
+        // several heads joined by ties
+        let mut heads = Vec::new();
+        for (note_index, note) in current_notes.iter().enumerate() {
+            for (segment, (position, value)) in note_segments(note, &measures).into_iter().enumerate() {
+                let (system, layout) = layout_at(position);
+                
+                // Each hand has its own staff, so a chord spanning both hands is
+                // split between the staves
+                let staff = if Self::staff_for(note) == Clef::Treble { &system.treble_staff } else { &system.bass_staff };
+                let spelled = note.spelled_in(layout.measure.key_signature);
+                
+                heads.push(PlacedHead {
+                    note_index,
+                    segment,
+                    system,
+                    layout,
+                    staff,
+                    position,
+                    value,
+                    spelled,
+                    x: layout.note_x(position),
+                    y: staff.note_y_position(spelled),
+                    tie_above: false,
+                });
+            }
+        }
+        // Stable, so the tied continuations of long notes keep their order
+        heads.sort_by(|a, b| a.position.total_cmp(&b.position));
+        
+        for clef in [Clef::Treble, Clef::Bass] {
+            // Heads on one staff at the same beat with the same value and voice
+            // share a stem
+            let voice_of = |head: &PlacedHead| current_notes[head.note_index].voice;
+            let mut chords: Vec<Vec<usize>> = Vec::new();
+            for (i, head) in heads.iter().enumerate().filter(|(_, head)| head.staff.clef == clef) {
+                let same_beat = chords.iter_mut().rev()
+                    .take_while(|chord| (heads[chord[0]].position - head.position).abs() < 0.001)
+                    .find(|chord| heads[chord[0]].value == head.value && voice_of(&heads[chord[0]]) == voice_of(head));
+                match same_beat {
+                    Some(chord) => chord.push(i),
+                    None => chords.push(vec![i]),
+                }
+            }
+            
+            // Where a measure has several voices on this staff, the first takes
+            // up-stems and the others down-stems
+            let mut measure_voices: BTreeMap<usize, BTreeSet<Option<u8>>> = BTreeMap::new();
+            for chord in &chords {
+                let head = &heads[chord[0]];
+                measure_voices.entry(head.layout.measure.index).or_default().insert(voice_of(head));
+            }
+            let voices: BTreeSet<Option<u8>> = measure_voices.values().flatten().copied().collect();
             
-            if system_index < self.staff_systems.len() {
-                let system = &self.staff_systems[system_index];
+            // Each voice is stemmed and beamed on its own
+            let mut directions = vec![StemDirection::Up; chords.len()];
+            let mut voice_stems = Vec::new();
+            for voice in voices {
+                let members: Vec<usize> = (0..chords.len()).filter(|&c| voice_of(&heads[chords[c][0]]) == voice).collect();
+                let stems: Vec<Stem> = members.iter()
+                    .map(|&c| {
+                        let chord = &chords[c];
+                        let first = &heads[chord[0]];
+                        let measure_voices = &measure_voices[&first.layout.measure.index];
+                        let colors: Vec<Color32> = chord.iter().map(|&i| current_notes[heads[i].note_index].color()).collect();
+                        Stem {
+                            staff: first.staff,
+                            system: first.system.system_number,
+                            measure: first.layout.measure,
+                            position: first.position,
+                            value: first.value,
+                            length: first.value.beats() / current_notes[first.note_index].tuplet.map_or(1.0, |tuplet| tuplet.ratio()),
+                            x: first.x,
+                            heads: chord.iter().map(|&i| heads[i].y).collect(),
+                            // Mixed results in one chord leave the stem black
+                            color: if colors.iter().all(|&color| color == colors[0]) { colors[0] } else { Color32::BLACK },
+                            direction: (measure_voices.len() > 1).then(|| {
+                                if measure_voices.first() == Some(&voice) { StemDirection::Up } else { StemDirection::Down }
+                            }),
+                        }
+                    })
+                    .collect();
+                for (&c, direction) in members.iter().zip(stem_directions(&stems)) {
+                    directions[c] = direction;
+                }
+                voice_stems.push(stems);
+            }
+            
+            // Chords of each tuplet bracket, with their stem directions
+            let mut tuplets: BTreeMap<TupletKey, Vec<(usize, StemDirection)>> = BTreeMap::new();
+            let mut accidentals = MeasureAccidentals::new();
+            for (c, (chord, direction)) in chords.iter_mut().zip(directions).enumerate() {
+                let first = &heads[chord[0]];
+                if let Some(tuplet) = current_notes[first.note_index].tuplet {
+                    let key = (first.system.system_number, voice_of(first), (tuplet.start * 1000.0).round() as i64);
+                    tuplets.entry(key).or_default().push((c, direction));
+                }
                 
-                // Choose staff based on note pitch
-                let staff = if note.pitch >= 60 { &system.treble_staff } else { &system.bass_staff };
+                // Top to bottom
+                chord.sort_by_key(|&i| std::cmp::Reverse(heads[i].spelled.diatonic_index()));
+                Self::offset_seconds(&mut heads, chord, direction);
                 
-                // Calculate horizontal position within the system
-                let notes_width = staff.width - 160.0; // Leave space for clefs and margins
-                let note_spacing = notes_width / (self.notes_per_system as f32);
-                let x = staff.position.x + 80.0 + (note_index_in_system as f32 * note_spacing);
-                let y = staff.note_y_position(note.pitch);
+                // Ties curve away from the stem; in a chord the upper notes tie above
+                let middle = heads[chord[0]].y.midpoint(heads[chord[chord.len() - 1]].y);
+                for &i in chord.iter() {
+                    heads[i].tie_above = if chord.len() == 1 {
+                        direction == StemDirection::Down
+                    } else {
+                        heads[i].y < middle
+                    };
+                }
                 
-                // Draw note with ledger lines
-                note.draw_with_staff_info(
-                    painter, 
-                    x, 
-                    y, 
-                    staff.get_staff_top(), 
-                    staff.get_staff_bottom(), 
-                    staff.get_line_spacing()
+                self.draw_chord(canvas, &heads, chord, current_notes, &mut accidentals);
+                Self::draw_marks(canvas, &heads, chord, direction, current_notes);
+            }
+            
+            for stems in &voice_stems {
+                draw_stems(canvas, stems);
+            }
+            
+            for members in tuplets.values() {
+                Self::draw_tuplet_number(canvas, &heads, &chords, members, current_notes);
+            }
+        }
+        
+        // Ties between the consecutive heads of each note
+        let mut previous_heads: Vec<Option<&PlacedHead>> = vec![None; current_notes.len()];
+        let mut by_note: Vec<&PlacedHead> = heads.iter().collect();
+        by_note.sort_by_key(|head| (head.note_index, head.segment));
+        for head in by_note {
+            let note = &current_notes[head.note_index];
+            if let Some(previous) = previous_heads[head.note_index] {
+                let (from, to) = (Pos2::new(previous.x, previous.y), Pos2::new(head.x, head.y));
+                if std::ptr::eq(previous.system, head.system) {
+                    Self::draw_tie(canvas, from, to, previous.tie_above, note.color());
+                } else {
+                    // The tie is split at the system break
+                    let system_end = previous.system.treble_staff.position.x + previous.system.treble_staff.width;
+                    let system_start = head.system.measures.first().map_or(head.x - 20.0, |first| first.x);
+                    Self::draw_tie(canvas, from, Pos2::new(system_end + 8.0, from.y), previous.tie_above, note.color());
+                    Self::draw_tie(canvas, Pos2::new(system_start - 8.0, to.y), to, head.tie_above, note.color());
+                }
+            }
+            previous_heads[head.note_index] = Some(head);
+        }
+    }
+    
+    // Heads a second apart cannot sit side by side on one stem, so one of each
+    // pair moves to the other side: to the right of an up-stem, working up from
+    // the bottom, or to the left of a down-stem, working down from the top.
+    // `chord` is ordered top to bottom.
+    fn offset_seconds(heads: &mut [PlacedHead], chord: &[usize], direction: StemDirection) {
+        let order: Vec<usize> = match direction {
+            StemDirection::Up => chord.iter().rev().copied().collect(),
+            StemDirection::Down => chord.to_vec(),
+        };
+        let shift = match direction {
+            StemDirection::Up => 2.0 * HEAD_RADIUS,
+            StemDirection::Down => -2.0 * HEAD_RADIUS,
+        };
+        
+        let mut previous_displaced = false;
+        for pair in order.windows(2) {
+            let step = (heads[pair[1]].spelled.diatonic_index() - heads[pair[0]].spelled.diatonic_index()).abs();
+            let displaced = step == 1 && !previous_displaced;
+            if displaced {
+                heads[pair[1]].x += shift;
+            }
+            previous_displaced = displaced;
+        }
+    }
+    
+    // Noteheads of one chord, with their accidentals stacked in columns to the
+    // left so that accidentals too close vertically never collide
+    fn draw_chord(&self, canvas: &mut dyn Canvas, heads: &[PlacedHead], chord: &[usize], notes: &[Note], accidentals: &mut MeasureAccidentals) {
+        let first = &heads[chord[0]];
+        let staff = first.staff;
+        let line_spacing = staff.get_line_spacing();
+        let leftmost_head = chord.iter().map(|&i| heads[i].x).fold(f32::MAX, f32::min);
+        
+        // Only the first head of a tied note carries an accidental
+        let mut columns: Vec<Vec<i32>> = Vec::new();
+        for &i in chord {
+            let head = &heads[i];
+            if head.segment > 0 {
+                continue;
+            }
+            let key_signature = head.layout.measure.key_signature;
+            let Some((accidental, courtesy)) = accidentals.accidental_for(staff.clef, head.layout.measure.index, key_signature, head.spelled) else {
+                continue;
+            };
+            
+            // Accidentals need a seventh between them to share a column
+            let index = head.spelled.diatonic_index();
+            let column = columns.iter()
+                .position(|column| column.iter().all(|&other| (other - index).abs() >= ACCIDENTAL_COLUMN_STEPS))
+                .unwrap_or(columns.len());
+            if column == columns.len() {
+                columns.push(Vec::new());
+            }
+            columns[column].push(index);
+            
+            let column_width = line_spacing * ACCIDENTAL_COLUMN_WIDTH;
+            let accidental_x = leftmost_head - ACCIDENTAL_OFFSET - accidental.width(line_spacing) / 2.0 - column as f32 * column_width;
+            accidental.draw(canvas, Pos2::new(accidental_x, head.y), line_spacing, Color32::BLACK);
+            
+            if courtesy {
+                // Cautionary accidentals are shown in parentheses
+                let half_width = accidental.width(line_spacing) / 2.0 + 3.0;
+                for (text, offset) in [("(", -half_width), (")", half_width)] {
+                    canvas.text(
+                        Pos2::new(accidental_x + offset, head.y),
+                        egui::Align2::CENTER_CENTER,
+                        text,
+                        line_spacing * 1.4,
+                        Color32::BLACK,
+                    );
+                }
+            }
+        }
+        
+        for &i in chord {
+            let head = &heads[i];
+            let note = &notes[head.note_index];
+            
+            // Draw note with ledger lines
+            note.draw_with_staff_info(canvas, head.value, head.x, head.y, staff);
+            
+            if self.show_note_names && head.segment == 0 {
+                canvas.text(
+                    Pos2::new(head.x, head.y + line_spacing * 1.2),
+                    egui::Align2::CENTER_TOP,
+                    &head.spelled.name(),
+                    10.0,
+                    Color32::DARK_GRAY,
                 );
             }
         }
     }
+    
+    // Articulations and fingerings of the notes starting in a chord (ordered top
+    // to bottom). They go on the notehead side away from the stem, in the order
+    // the source listed them, except fermatas, which go above the staff.
+    fn draw_marks(canvas: &mut dyn Canvas, heads: &[PlacedHead], chord: &[usize], direction: StemDirection, notes: &[Note]) {
+        let starting: Vec<&PlacedHead> = chord.iter()
+            .map(|&i| &heads[i])
+            .filter(|head| head.segment == 0)
+            .collect();
+        let (Some(top), Some(bottom)) = (starting.first(), starting.last()) else {
+            return;
+        };
+        let staff = top.staff;
+        let line_spacing = staff.get_line_spacing();
+        let x = starting.iter().map(|head| head.x).sum::<f32>() / starting.len() as f32;
+        let color = notes[top.note_index].color();
+        
+        // Each mark is written once per chord
+        let mut articulations = Vec::new();
+        for head in &starting {
+            for &articulation in &notes[head.note_index].articulations {
+                if !articulations.contains(&articulation) {
+                    articulations.push(articulation);
+                }
+            }
+        }
+        
+        let mut near = 0.0;
+        let mut far = 0.0;
+        let stem_up = direction == StemDirection::Up;
+        for articulation in articulations {
+            let (above, y) = if articulation.always_above() {
+                let y = top.y.min(staff.get_staff_top()) - (MARK_DISTANCE + far) * line_spacing;
+                far += MARK_SPACING;
+                (true, y)
+            } else if stem_up {
+                let y = bottom.y + (MARK_DISTANCE + near) * line_spacing;
+                near += MARK_SPACING;
+                (false, y)
+            } else {
+                let y = top.y - (MARK_DISTANCE + near) * line_spacing;
+                near += MARK_SPACING;
+                (true, y)
+            };
+            articulation.draw(canvas, Pos2::new(x, y), above, line_spacing, color);
+        }
+        
+        // Finger numbers continue outwards past the articulations, the top
+        // number belonging to the top note
+        let fingerings: Vec<u8> = starting.iter()
+            .filter_map(|head| notes[head.note_index].fingering)
+            .collect();
+        let (mut y, step, ordered) = if stem_up {
+            (bottom.y + (MARK_DISTANCE + near) * line_spacing, 1.0, fingerings)
+        } else {
+            (top.y - (MARK_DISTANCE + near) * line_spacing, -1.0, fingerings.into_iter().rev().collect())
+        };
+        for finger in ordered {
+            canvas.text(
+                Pos2::new(x, y),
+                egui::Align2::CENTER_CENTER,
+                &finger.to_string(),
+                FINGERING_SIZE * line_spacing,
+                color,
+            );
+            y += step * FINGERING_SIZE * line_spacing;
+        }
+    }
+    
+    // The number over (or under) a tuplet bracket, on the stem side of its
+    // first chord and centered between the outer chords
+    fn draw_tuplet_number(canvas: &mut dyn Canvas, heads: &[PlacedHead], chords: &[Vec<usize>], members: &[(usize, StemDirection)], notes: &[Note]) {
+        let Some(&(first, direction)) = members.first() else {
+            return;
+        };
+        let first_head = &heads[chords[first][0]];
+        let Some(tuplet) = notes[first_head.note_index].tuplet else {
+            return;
+        };
+        let staff = first_head.staff;
+        let line_spacing = staff.get_line_spacing();
+        let member_heads = || members.iter().flat_map(|&(c, _)| chords[c].iter().map(|&i| &heads[i]));
+        let left = member_heads().map(|head| head.x).fold(f32::MAX, f32::min);
+        let right = member_heads().map(|head| head.x).fold(f32::MIN, f32::max);
+        
+        let middle = staff.get_middle_line();
+        let y = match direction {
+            StemDirection::Up => {
+                let highest = member_heads().map(|head| head.y).fold(f32::MAX, f32::min);
+                (highest - TUPLET_STEM_REACH * line_spacing).min(middle) - TUPLET_NUMBER_DISTANCE * line_spacing
+            }
+            StemDirection::Down => {
+                let lowest = member_heads().map(|head| head.y).fold(f32::MIN, f32::max);
+                (lowest + TUPLET_STEM_REACH * line_spacing).max(middle) + TUPLET_NUMBER_DISTANCE * line_spacing
+            }
+        };
+        canvas.text(
+            Pos2::new(left.midpoint(right), y),
+            egui::Align2::CENTER_CENTER,
+            &tuplet.actual.to_string(),
+            TUPLET_NUMBER_SIZE * line_spacing,
+            Color32::BLACK,
+        );
+    }
+    
+    fn staff_for(note: &Note) -> Clef {
+        note.written_hand().clef()
+    }
+    
+    // Arc between two noteheads, above or below them
+    fn draw_tie(canvas: &mut dyn Canvas, from: Pos2, to: Pos2, above: bool, color: Color32) {
+        let side = if above { -1.0 } else { 1.0 };
+        let start = Pos2::new(from.x + 7.0, from.y + side * 5.0);
+        let end = Pos2::new(to.x - 7.0, to.y + side * 5.0);
+        let depth = side * ((end.x - start.x) * 0.15).clamp(3.0, 8.0);
+        canvas.cubic_bezier(
+            [
+                start,
+                Pos2::new(start.x + (end.x - start.x) * 0.25, start.y + depth),
+                Pos2::new(start.x + (end.x - start.x) * 0.75, end.y + depth),
+                end,
+            ],
+            Stroke::new(1.5, color),
+        );
+    }
 }
\ No newline at end of file
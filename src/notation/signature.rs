@@ -0,0 +1,226 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeSignature {
+    pub numerator: u8,
+    pub denominator: u8, // Power of two: 4 = quarter note
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self::new(4, 4)
+    }
+}
+
+impl TimeSignature {
+    pub fn new(numerator: u8, denominator: u8) -> Self {
+        Self {
+            numerator: numerator.max(1),
+            denominator: denominator.max(1),
+        }
+    }
+    
+    // Measure length in quarter-note beats, the unit of Note::position
+    pub fn beats_per_measure(&self) -> f32 {
+        self.numerator as f32 * 4.0 / self.denominator as f32
+    }
+    
+    // Length of the beat that eighths and shorter are beamed within: a dotted
+    // quarter in compound meters like 6/8, otherwise the written beat (but at
+    // least a quarter, so 4/8 still beams in pairs)
+    pub fn beam_group_beats(&self) -> f32 {
+        let unit = 4.0 / self.denominator as f32;
+        if self.denominator >= 8 && self.numerator.is_multiple_of(3) {
+            3.0 * unit
+        } else {
+            unit.max(1.0)
+        }
+    }
+}
+
+const MAJOR_KEY_NAMES: [&str; 15] = [
+    "Cb", "Gb", "Db", "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#",
+];
+const MINOR_KEY_NAMES: [&str; 15] = [
+    "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#", "G#", "D#", "A#",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeySignature {
+    pub fifths: i8, // Positive = number of sharps, negative = number of flats
+    pub minor: bool,
+}
+
+impl KeySignature {
+    pub fn new(fifths: i8, minor: bool) -> Self {
+        Self {
+            fifths: fifths.clamp(-7, 7),
+            minor,
+        }
+    }
+    
+    pub fn name(&self) -> String {
+        let index = (self.fifths.clamp(-7, 7) + 7) as usize;
+        if self.minor {
+            format!("{} minor", MINOR_KEY_NAMES[index])
+        } else {
+            format!("{} major", MAJOR_KEY_NAMES[index])
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measure {
+    pub index: usize, // Zero-based; the printed measure number is one higher
+    pub start: f32, // Beat of the first barline
+    pub length: f32, // In beats
+    pub time_signature: TimeSignature,
+    pub key_signature: KeySignature,
+}
+
+// Time and key signature changes over a song, by beat
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SignatureTimeline {
+    time_signatures: Vec<(f32, TimeSignature)>,
+    key_signatures: Vec<(f32, KeySignature)>,
+}
+
+impl SignatureTimeline {
+    pub fn add_time_signature(&mut self, beat: f32, signature: TimeSignature) {
+        Self::insert(&mut self.time_signatures, beat, signature);
+    }
+    
+    pub fn add_key_signature(&mut self, beat: f32, signature: KeySignature) {
+        Self::insert(&mut self.key_signatures, beat, signature);
+    }
+    
+    // Keeps the list in beat order; a later change at the same beat replaces the
+    // earlier one
+    fn insert<T>(changes: &mut Vec<(f32, T)>, beat: f32, signature: T) {
+        match changes.iter().position(|(existing, _)| *existing >= beat - f32::EPSILON) {
+            Some(index) if (changes[index].0 - beat).abs() <= f32::EPSILON => changes[index].1 = signature,
+            Some(index) => changes.insert(index, (beat, signature)),
+            None => changes.push((beat, signature)),
+        }
+    }
+    
+    pub fn time_signature_at(&self, beat: f32) -> TimeSignature {
+        Self::value_at(&self.time_signatures, beat)
+    }
+    
+    pub fn key_signature_at(&self, beat: f32) -> KeySignature {
+        Self::value_at(&self.key_signatures, beat)
+    }
+    
+    fn value_at<T: Copy + Default>(changes: &[(f32, T)], beat: f32) -> T {
+        changes.iter()
+            .take_while(|(start, _)| *start <= beat + f32::EPSILON)
+            .last()
+            .map(|(_, signature)| *signature)
+            .unwrap_or_default()
+    }
+    
+    // Splits the song into measures up to `end_beat` (at least one). A meter
+    // change part way through a measure cuts that measure short.
+    pub fn measures(&self, end_beat: f32) -> Vec<Measure> {
+        let mut measures = Vec::new();
+        let mut start = 0.0;
+        
+        loop {
+            let time_signature = self.time_signature_at(start);
+            let full_length = time_signature.beats_per_measure();
+            let length = self.time_signatures.iter()
+                .map(|(beat, _)| *beat)
+                .find(|&beat| beat > start + 0.001 && beat < start + full_length - 0.001)
+                .map_or(full_length, |beat| beat - start);
+            
+            measures.push(Measure {
+                index: measures.len(),
+                start,
+                length,
+                time_signature,
+                key_signature: self.key_signature_at(start),
+            });
+            
+            start += length;
+            if start >= end_beat - 0.001 {
+                break;
+            }
+        }
+        
+        measures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // Start, length and meter of each measure
+    fn layout(timeline: &SignatureTimeline, end_beat: f32) -> Vec<(f32, f32, (u8, u8))> {
+        timeline.measures(end_beat).iter()
+            .map(|measure| (measure.start, measure.length, (measure.time_signature.numerator, measure.time_signature.denominator)))
+            .collect()
+    }
+    
+    #[test]
+    fn measure_lengths_follow_the_meter() {
+        assert_eq!(TimeSignature::new(3, 4).beats_per_measure(), 3.0);
+        assert_eq!(TimeSignature::new(6, 8).beats_per_measure(), 3.0);
+        assert_eq!(TimeSignature::new(2, 2).beats_per_measure(), 4.0);
+        assert_eq!(TimeSignature::new(6, 8).beam_group_beats(), 1.5);
+        assert_eq!(TimeSignature::new(4, 8).beam_group_beats(), 1.0);
+        assert_eq!(TimeSignature::new(2, 2).beam_group_beats(), 2.0);
+    }
+    
+    #[test]
+    fn an_empty_timeline_is_common_time_in_c() {
+        let timeline = SignatureTimeline::default();
+        assert_eq!(layout(&timeline, 9.0), vec![(0.0, 4.0, (4, 4)), (4.0, 4.0, (4, 4)), (8.0, 4.0, (4, 4))]);
+        // Always at least one measure
+        assert_eq!(layout(&timeline, 0.0), vec![(0.0, 4.0, (4, 4))]);
+        assert_eq!(layout(&timeline, 8.0).len(), 2);
+    }
+    
+    #[test]
+    fn meter_changes_start_new_measures() {
+        let mut timeline = SignatureTimeline::default();
+        timeline.add_time_signature(0.0, TimeSignature::new(3, 4));
+        timeline.add_time_signature(6.0, TimeSignature::new(6, 8));
+        // A change part way through a measure cuts it short
+        timeline.add_time_signature(10.0, TimeSignature::new(2, 4));
+        
+        assert_eq!(layout(&timeline, 14.0), vec![
+            (0.0, 3.0, (3, 4)),
+            (3.0, 3.0, (3, 4)),
+            (6.0, 3.0, (6, 8)),
+            (9.0, 1.0, (6, 8)),
+            (10.0, 2.0, (2, 4)),
+            (12.0, 2.0, (2, 4)),
+        ]);
+        let indices: Vec<usize> = timeline.measures(14.0).iter().map(|measure| measure.index).collect();
+        assert_eq!(indices, (0..6).collect::<Vec<_>>());
+    }
+    
+    #[test]
+    fn a_short_opening_meter_makes_a_pickup() {
+        // How MIDI files usually write an upbeat
+        let mut timeline = SignatureTimeline::default();
+        timeline.add_time_signature(0.0, TimeSignature::new(1, 4));
+        timeline.add_time_signature(1.0, TimeSignature::new(4, 4));
+        assert_eq!(layout(&timeline, 9.0), vec![(0.0, 1.0, (1, 4)), (1.0, 4.0, (4, 4)), (5.0, 4.0, (4, 4))]);
+    }
+    
+    #[test]
+    fn later_changes_at_the_same_beat_replace_earlier_ones() {
+        let mut timeline = SignatureTimeline::default();
+        timeline.add_key_signature(4.0, KeySignature::new(1, false));
+        timeline.add_key_signature(0.0, KeySignature::new(-2, false));
+        timeline.add_key_signature(4.0, KeySignature::new(3, true));
+        
+        let keys: Vec<String> = timeline.measures(12.0).iter().map(|measure| measure.key_signature.name()).collect();
+        assert_eq!(keys, vec!["Bb major", "F# minor", "F# minor"]);
+        assert_eq!(timeline.key_signature_at(3.999), KeySignature::new(-2, false));
+        assert_eq!(KeySignature::new(9, false), KeySignature::new(7, false));
+    }
+}
\ No newline at end of file
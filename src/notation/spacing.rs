@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+use super::{Clef, MeasureAccidentals, Note, SpelledPitch, WrittenRest, DURATION_GRID};
+use super::duration::{note_segments, staff_rests};
+use super::signature::Measure;
+
+// Horizontal spacing the way engravers do it: the room after each note grows
+// with the logarithm of the time until the next note, so a half note gets a
+// little more room than a quarter rather than twice as much. Accidentals,
+// displaced chord seconds and dots widen a column when its spring would be too
+// tight for them.
+
+// Room after a thirty-second note, and what each doubling of the duration adds
+const SHORTEST_SPACE: f32 = 14.0;
+const DOUBLING_SPACE: f32 = 7.0;
+
+// Shared with the renderer, so the room made here is the room drawn into
+pub const HEAD_RADIUS: f32 = 6.0;
+// From a notehead's center to its nearest accidental
+pub const ACCIDENTAL_OFFSET: f32 = 9.0;
+// Width of each accidental column, in line spaces
+pub const ACCIDENTAL_COLUMN_WIDTH: f32 = 1.6;
+// Accidentals can share a column when their notes are at least a seventh (six
+// staff steps) apart
+pub const ACCIDENTAL_COLUMN_STEPS: i32 = 6;
+// Clear space kept between the symbols of neighbouring columns
+const MIN_GAP: f32 = 4.0;
+const DOT_SPACE: f32 = 5.0;
+
+const POSITION_EPSILON: f32 = 0.001;
+
+// Every note or rest starting at one beat of a measure, in either staff
+#[derive(Debug, Clone, Copy)]
+struct Column {
+    position: f32,
+    left: f32, // Room the symbols need left of the notehead center
+    right: f32, // And right of it
+}
+
+impl Column {
+    fn new(position: f32) -> Self {
+        Self {
+            position,
+            left: HEAD_RADIUS,
+            right: HEAD_RADIUS,
+        }
+    }
+}
+
+// A notehead in a column, with whether it starts its note (only those can
+// carry an accidental)
+struct ColumnHead {
+    spelled: SpelledPitch,
+    dots: u8,
+    first: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct MeasureSpacing {
+    pub positions: Vec<f32>, // Beat of each column
+    pub lead: f32, // From the start of the content to the first column
+    pub widths: Vec<f32>, // From each column to the next, the last one to the end of the content
+}
+
+impl MeasureSpacing {
+    pub fn natural_width(&self) -> f32 {
+        self.lead + self.widths.iter().sum::<f32>()
+    }
+    
+    // Column offsets from the start of the content, with the springs stretched
+    // (or squeezed) by `factor`
+    pub fn offsets(&self, factor: f32) -> Vec<f32> {
+        let mut x = self.lead * factor;
+        self.widths.iter()
+            .map(|width| {
+                let offset = x;
+                x += width * factor;
+                offset
+            })
+            .collect()
+    }
+}
+
+// Space taken by a gap of `beats` before the next column
+fn spring(beats: f32) -> f32 {
+    SHORTEST_SPACE + DOUBLING_SPACE * (beats.max(DURATION_GRID) / DURATION_GRID).log2()
+}
+
+// Natural spacing of every measure. `clef_of` says which staff a note is written on.
+pub fn space_measures(notes: &[Note], rests: &[WrittenRest], measures: &[Measure], line_spacing: f32, clef_of: impl Fn(&Note) -> Clef) -> Vec<MeasureSpacing> {
+    let mut columns: Vec<BTreeMap<i64, Column>> = vec![BTreeMap::new(); measures.len()];
+    let key = |position: f32| (position / POSITION_EPSILON).round() as i64;
+    let measure_of = |position: f32| {
+        measures.partition_point(|measure| measure.start <= position + POSITION_EPSILON).saturating_sub(1)
+    };
+    
+    for clef in [Clef::Treble, Clef::Bass] {
+        let staff_notes: Vec<&Note> = notes.iter().filter(|note| clef_of(note) == clef).collect();
+        
+        let staff_written = rests.iter().filter(|rest| rest.hand.map(|hand| hand.clef()) == Some(clef));
+        for rest in staff_rests(staff_notes.iter().copied(), staff_written, measures) {
+            let Some(measure_columns) = columns.get_mut(measure_of(rest.position)) else {
+                continue;
+            };
+            measure_columns.entry(key(rest.position)).or_insert_with(|| Column::new(rest.position));
+        }
+        
+        // Heads of this staff grouped by beat
+        let mut chords: BTreeMap<i64, (f32, Vec<ColumnHead>)> = BTreeMap::new();
+        for note in &staff_notes {
+            for (segment, (position, value)) in note_segments(note, measures).into_iter().enumerate() {
+                let spelled = note.spelled_in(measures[measure_of(position)].key_signature);
+                chords.entry(key(position))
+                    .or_insert_with(|| (position, Vec::new()))
+                    .1
+                    .push(ColumnHead { spelled, dots: value.dots, first: segment == 0 });
+            }
+        }
+        
+        // Accidentals are decided in time order, as they are when drawing
+        let mut accidentals = MeasureAccidentals::new();
+        for (position_key, (position, heads)) in chords {
+            let measure = &measures[measure_of(position)];
+            let column = columns[measure.index].entry(position_key).or_insert_with(|| Column::new(position));
+            
+            // Accidentals closer than a seventh need separate columns
+            let mut accidental_columns: Vec<Vec<i32>> = Vec::new();
+            for head in heads.iter().filter(|head| head.first) {
+                if accidentals.accidental_for(clef, measure.index, measure.key_signature, head.spelled).is_none() {
+                    continue;
+                }
+                let index = head.spelled.diatonic_index();
+                match accidental_columns.iter_mut().find(|column| column.iter().all(|&other| (other - index).abs() >= ACCIDENTAL_COLUMN_STEPS)) {
+                    Some(column) => column.push(index),
+                    None => accidental_columns.push(vec![index]),
+                }
+            }
+            
+            let mut steps: Vec<i32> = heads.iter().map(|head| head.spelled.diatonic_index()).collect();
+            steps.sort_unstable();
+            let has_seconds = steps.windows(2).any(|pair| pair[1] - pair[0] == 1);
+            let displaced = if has_seconds { 2.0 * HEAD_RADIUS } else { 0.0 };
+            let dots = heads.iter().map(|head| head.dots).max().unwrap_or(0);
+            
+            let mut left = HEAD_RADIUS + displaced;
+            if !accidental_columns.is_empty() {
+                left += ACCIDENTAL_OFFSET + accidental_columns.len() as f32 * ACCIDENTAL_COLUMN_WIDTH * line_spacing;
+            }
+            let mut right = HEAD_RADIUS + displaced;
+            if dots > 0 {
+                right += DOT_SPACE * (dots as f32 + 1.0);
+            }
+            column.left = column.left.max(left);
+            column.right = column.right.max(right);
+        }
+    }
+    
+    measures.iter()
+        .zip(columns)
+        .map(|(measure, columns)| {
+            let columns: Vec<Column> = columns.into_values().collect();
+            let end = measure.start + measure.length;
+            let widths = (0..columns.len())
+                .map(|i| {
+                    let next_position = columns.get(i + 1).map_or(end, |next| next.position);
+                    let next_left = columns.get(i + 1).map_or(0.0, |next| next.left);
+                    let min_width = columns[i].right + MIN_GAP + next_left;
+                    spring(next_position - columns[i].position).max(min_width)
+                })
+                .collect();
+            
+            MeasureSpacing {
+                positions: columns.iter().map(|column| column.position).collect(),
+                lead: columns.first().map_or(0.0, |first| first.left),
+                widths,
+            }
+        })
+        .collect()
+}
\ No newline at end of file
@@ -1,6 +1,44 @@
-use eframe::egui::{self, Painter, Pos2, Color32, Stroke};
+use eframe::egui::{self, Pos2, Color32, Stroke};
+use super::{Accidental, KeySignature, SpelledPitch, TimeSignature};
+use super::canvas::Canvas;
+use super::glyphs::{self, Glyph};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+// Diatonic index (see SpelledPitch::diatonic_index) of each clef's bottom line
+const TREBLE_BOTTOM_LINE: i32 = 37; // E4
+const BASS_BOTTOM_LINE: i32 = 25; // G2
+
+// Where key signature sharps and flats sit on the treble staff, in order; the
+// bass staff uses the same shape two octaves lower
+const TREBLE_SHARP_POSITIONS: [i32; 7] = [45, 42, 46, 43, 40, 44, 41]; // F5 C5 G5 D5 A4 E5 B4
+const TREBLE_FLAT_POSITIONS: [i32; 7] = [41, 44, 40, 43, 39, 42, 38]; // B4 E5 A4 D5 G4 C5 F4
+
+// Horizontal space taken by the clef before the key signature starts
+const CLEF_WIDTH: f32 = 34.0;
+const TIME_SIGNATURE_WIDTH: f32 = 22.0;
+
+// Clefs drawn without a music font, as cubic curves in staff spaces from the
+// clef's left edge and the line it names (y grows downwards)
+type ClefCurve = [(f32, f32); 4];
+type ClefDot = (f32, f32, f32); // Centre and radius
+const TREBLE_CLEF_CURVES: [ClefCurve; 9] = [
+    [(1.25, -0.3), (0.6, -0.6), (0.5, 0.5), (1.15, 0.55)], // Curl around the G line
+    [(1.15, 0.55), (1.9, 0.6), (2.0, -0.95), (1.2, -1.0)],
+    [(1.2, -1.0), (0.3, -1.05), (-0.1, 0.3), (0.9, 1.1)],
+    [(0.9, 1.1), (1.8, 1.3), (2.3, 0.0), (1.8, -1.3)],
+    [(1.8, -1.3), (1.3, -2.0), (0.7, -2.4), (0.9, -3.3)], // Up into the top loop
+    [(0.9, -3.3), (1.0, -4.0), (1.7, -3.9), (1.5, -3.1)],
+    [(1.5, -3.1), (1.3, -2.5), (1.2, -1.5), (1.25, 0.0)], // Stem down through the bowl
+    [(1.25, 0.0), (1.3, 1.0), (1.4, 1.8), (1.35, 2.3)],
+    [(1.35, 2.3), (1.35, 2.9), (0.5, 2.9), (0.55, 2.35)],
+];
+const TREBLE_CLEF_DOTS: [ClefDot; 1] = [(0.75, 2.3, 0.3)];
+const BASS_CLEF_CURVES: [ClefCurve; 2] = [
+    [(0.2, 0.0), (0.2, -1.1), (1.9, -1.3), (2.0, 0.1)],
+    [(2.0, 0.1), (2.1, 1.4), (1.0, 2.3), (0.1, 2.9)],
+];
+const BASS_CLEF_DOTS: [ClefDot; 3] = [(0.4, 0.0, 0.3), (2.6, -0.5, 0.15), (2.6, 0.5, 0.15)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Clef {
     Treble,
     Bass,
@@ -12,6 +50,8 @@ pub struct Staff {
     pub position: Pos2,
     pub width: f32,
     pub line_spacing: f32,
+    pub key_signature: KeySignature,
+    pub time_signature: Option<TimeSignature>, // Only shown where the meter starts or changes
 }
 
 impl Staff {
@@ -21,16 +61,38 @@ impl Staff {
             position,
             width,
             line_spacing: 12.0,
+            key_signature: KeySignature::default(),
+            time_signature: None,
         }
     }
     
-    pub fn draw(&self, painter: &Painter) {
-        let stroke = Stroke::new(1.0, Color32::BLACK);
+    pub fn set_key_signature(&mut self, key_signature: KeySignature) {
+        self.key_signature = key_signature;
+    }
+    
+    pub fn set_time_signature(&mut self, time_signature: Option<TimeSignature>) {
+        self.time_signature = time_signature;
+    }
+    
+    fn key_signature_width(&self) -> f32 {
+        self.key_signature.altered_steps().len() as f32 * self.line_spacing * 0.8
+    }
+    
+    // Horizontal space used by the clef, key and time signature at the start of the staff
+    pub fn header_width(&self) -> f32 {
+        let time_signature_width = if self.time_signature.is_some() { TIME_SIGNATURE_WIDTH } else { 0.0 };
+        CLEF_WIDTH + self.key_signature_width() + time_signature_width + 8.0
+    }
+    
+    pub fn draw(&self, canvas: &mut dyn Canvas) {
+        let thickness = glyphs::music_font()
+            .map_or(1.0, |font| font.get_engraving_defaults().staff_line_thickness * self.line_spacing);
+        let stroke = Stroke::new(thickness, Color32::BLACK);
         
         // Draw staff lines
         for i in 0..5 {
             let y = self.position.y + (i as f32) * self.line_spacing;
-            painter.line_segment(
+            canvas.line_segment(
                 [
                     Pos2::new(self.position.x, y),
                     Pos2::new(self.position.x + self.width, y),
@@ -40,52 +102,103 @@ impl Staff {
         }
         
         // Draw clef symbol (simplified)
-        self.draw_clef_symbol(painter);
+        self.draw_clef_symbol(canvas);
+        self.draw_key_signature(canvas);
+        
+        if let Some(time_signature) = self.time_signature {
+            let x = self.position.x + CLEF_WIDTH + self.key_signature_width() + TIME_SIGNATURE_WIDTH / 2.0 + 2.0;
+            self.draw_time_signature(canvas, x, time_signature);
+        }
     }
     
-    fn draw_clef_symbol(&self, painter: &Painter) {
-        let clef_x = self.position.x + 15.0;
-        let center_y = self.position.y + 2.0 * self.line_spacing;
-        
-        match self.clef {
-            Clef::Treble => {
-                // Draw treble clef symbol
-                painter.text(
-                    Pos2::new(clef_x, center_y - 6.0),
-                    egui::Align2::CENTER_CENTER,
-                    "𝄞",
-                    egui::FontId::proportional(32.0),
-                    Color32::BLACK,
-                );
-            }
-            Clef::Bass => {
-                // Draw bass clef symbol
-                painter.text(
-                    Pos2::new(clef_x, center_y - 6.0),
-                    egui::Align2::CENTER_CENTER,
-                    "𝄢",
-                    egui::FontId::proportional(32.0),
-                    Color32::BLACK,
-                );
+    // Numerator in the upper half of the staff, denominator in the lower half
+    pub fn draw_time_signature(&self, canvas: &mut dyn Canvas, center_x: f32, time_signature: TimeSignature) {
+        if let Some(font) = glyphs::music_font() {
+            for (value, line) in [(time_signature.numerator, 1.0), (time_signature.denominator, 3.0)] {
+                let digits = Glyph::time_signature_digits(value);
+                let width: f32 = digits.iter().map(|&digit| font.width(digit, self.line_spacing)).sum();
+                let mut x = center_x - width / 2.0;
+                for digit in digits {
+                    let digit_width = font.width(digit, self.line_spacing);
+                    font.draw_centered(canvas, digit, Pos2::new(x + digit_width / 2.0, self.position.y + line * self.line_spacing), self.line_spacing, Color32::BLACK);
+                    x += digit_width;
+                }
             }
+            return;
+        }
+        
+        for (value, line) in [(time_signature.numerator, 1.0), (time_signature.denominator, 3.0)] {
+            canvas.text(
+                Pos2::new(center_x, self.position.y + line * self.line_spacing),
+                egui::Align2::CENTER_CENTER,
+                &value.to_string(),
+                self.line_spacing * 2.0,
+                Color32::BLACK,
+            );
         }
     }
     
-    pub fn note_y_position(&self, midi_note: u8) -> f32 {
-        match self.clef {
-            Clef::Treble => {
-                // Middle C (60) is below the staff
-                let middle_c_y = self.position.y + 5.0 * self.line_spacing + self.line_spacing / 2.0;
-                middle_c_y - ((midi_note as f32 - 60.0) * self.line_spacing / 2.0)
-            }
-            Clef::Bass => {
-                // Middle C (60) is above the staff
-                let middle_c_y = self.position.y - self.line_spacing / 2.0;
-                middle_c_y - ((midi_note as f32 - 60.0) * self.line_spacing / 2.0)
-            }
+    fn draw_key_signature(&self, canvas: &mut dyn Canvas) {
+        let (accidental, positions) = if self.key_signature.fifths >= 0 {
+            (Accidental::Sharp, TREBLE_SHARP_POSITIONS)
+        } else {
+            (Accidental::Flat, TREBLE_FLAT_POSITIONS)
+        };
+        let octave_offset = match self.clef {
+            Clef::Treble => 0,
+            Clef::Bass => 14,
+        };
+        
+        for (i, position) in positions.iter().take(self.key_signature.altered_steps().len()).enumerate() {
+            let x = self.position.x + CLEF_WIDTH + (i as f32 + 0.5) * self.line_spacing * 0.8;
+            let y = self.diatonic_y(position - octave_offset);
+            accidental.draw(canvas, Pos2::new(x, y), self.line_spacing, Color32::BLACK);
         }
     }
     
+    fn draw_clef_symbol(&self, canvas: &mut dyn Canvas) {
+        let clef_x = self.position.x + 15.0;
+        
+        // A SMuFL clef's origin sits on the line it names: G for treble, F for bass
+        if let Some(font) = glyphs::music_font() {
+            let (glyph, line_y) = match self.clef {
+                Clef::Treble => (glyphs::G_CLEF, self.get_staff_bottom() - self.line_spacing),
+                Clef::Bass => (glyphs::F_CLEF, self.position.y + self.line_spacing),
+            };
+            font.draw_centered(canvas, glyph, Pos2::new(clef_x, line_y), self.line_spacing, Color32::BLACK);
+            return;
+        }
+        
+        // The UI font has no clef symbols, so they are drawn as curves
+        let (curves, dots, line_y): (&[ClefCurve], &[ClefDot], f32) = match self.clef {
+            Clef::Treble => (&TREBLE_CLEF_CURVES, &TREBLE_CLEF_DOTS, self.get_staff_bottom() - self.line_spacing),
+            Clef::Bass => (&BASS_CLEF_CURVES, &BASS_CLEF_DOTS, self.position.y + self.line_spacing),
+        };
+        let left = clef_x - 1.15 * self.line_spacing;
+        let point = |(x, y): (f32, f32)| Pos2::new(left + x * self.line_spacing, line_y + y * self.line_spacing);
+        let stroke = Stroke::new(self.line_spacing * 0.2, Color32::BLACK);
+        for curve in curves {
+            canvas.cubic_bezier(curve.map(point), stroke);
+        }
+        for &(x, y, radius) in dots {
+            canvas.circle_filled(point((x, y)), radius * self.line_spacing, Color32::BLACK);
+        }
+    }
+    
+    // Height of a spelled pitch: each letter step is half a line space, so C and
+    // C# share a position and the accidental tells them apart
+    pub fn note_y_position(&self, pitch: SpelledPitch) -> f32 {
+        self.diatonic_y(pitch.diatonic_index())
+    }
+    
+    fn diatonic_y(&self, diatonic_index: i32) -> f32 {
+        let bottom_line = match self.clef {
+            Clef::Treble => TREBLE_BOTTOM_LINE,
+            Clef::Bass => BASS_BOTTOM_LINE,
+        };
+        self.get_staff_bottom() - (diatonic_index - bottom_line) as f32 * self.line_spacing / 2.0
+    }
+    
     pub fn get_staff_top(&self) -> f32 {
         self.position.y
     }
@@ -94,7 +207,17 @@ impl Staff {
         self.position.y + 4.0 * self.line_spacing
     }
     
+    pub fn get_middle_line(&self) -> f32 {
+        self.position.y + 2.0 * self.line_spacing
+    }
+    
     pub fn get_line_spacing(&self) -> f32 {
         self.line_spacing
     }
+    
+    // Whether a height sits on a staff or ledger line rather than in a space
+    pub fn is_on_line(&self, y: f32) -> bool {
+        let steps = (self.get_staff_bottom() - y) / (self.line_spacing / 2.0);
+        (steps.round() as i32).rem_euclid(2) == 0
+    }
 }
\ No newline at end of file
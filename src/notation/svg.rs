@@ -0,0 +1,123 @@
+use eframe::egui::{Align2, Color32, Pos2, Rect, Stroke};
+use std::fmt::Write;
+
+use super::canvas::Canvas;
+use super::glyphs::{Glyph, MusicFont};
+use super::outline::{self, PathCommand};
+
+// Writes the score as an SVG document. Text and music glyphs are embedded as
+// outlines so the file looks the same without the fonts installed.
+pub struct SvgCanvas {
+    width: f32,
+    height: f32,
+    body: String,
+}
+
+impl SvgCanvas {
+    pub fn new(width: f32, height: f32) -> Self {
+        Self {
+            width,
+            height,
+            body: String::new(),
+        }
+    }
+    
+    pub fn finish(self) -> String {
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n\
+             <rect width=\"100%\" height=\"100%\" fill=\"#ffffff\"/>\n{body}</svg>\n",
+            w = self.width,
+            h = self.height,
+            body = self.body,
+        )
+    }
+    
+    fn path(&mut self, commands: &[PathCommand], color: Color32) {
+        if commands.is_empty() {
+            return;
+        }
+        let mut data = String::new();
+        for command in commands {
+            let _ = match command {
+                PathCommand::MoveTo(p) => write!(data, "M{:.2} {:.2}", p.x, p.y),
+                PathCommand::LineTo(p) => write!(data, "L{:.2} {:.2}", p.x, p.y),
+                PathCommand::QuadTo(c, p) => write!(data, "Q{:.2} {:.2} {:.2} {:.2}", c.x, c.y, p.x, p.y),
+                PathCommand::CubicTo(c1, c2, p) => {
+                    write!(data, "C{:.2} {:.2} {:.2} {:.2} {:.2} {:.2}", c1.x, c1.y, c2.x, c2.y, p.x, p.y)
+                }
+                PathCommand::Close => write!(data, "Z"),
+            };
+        }
+        let _ = writeln!(self.body, "<path d=\"{}\"{}/>", data, fill(color));
+    }
+}
+
+// Colors as `#rrggbb` plus an opacity attribute when not opaque
+fn paint(attribute: &str, color: Color32) -> String {
+    let [r, g, b, a] = color.to_srgba_unmultiplied();
+    let mut paint = format!(" {}=\"#{:02x}{:02x}{:02x}\"", attribute, r, g, b);
+    if a < 255 {
+        let _ = write!(paint, " {}-opacity=\"{:.3}\"", attribute, a as f32 / 255.0);
+    }
+    paint
+}
+
+fn fill(color: Color32) -> String {
+    paint("fill", color)
+}
+
+fn stroke(stroke: Stroke) -> String {
+    format!("{} stroke-width=\"{:.2}\"", paint("stroke", stroke.color), stroke.width)
+}
+
+impl Canvas for SvgCanvas {
+    fn line_segment(&mut self, points: [Pos2; 2], line: Stroke) {
+        let _ = writeln!(
+            self.body,
+            "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\"{}/>",
+            points[0].x, points[0].y, points[1].x, points[1].y, stroke(line),
+        );
+    }
+    
+    fn circle_filled(&mut self, center: Pos2, radius: f32, color: Color32) {
+        let _ = writeln!(self.body, "<circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"{:.2}\"{}/>", center.x, center.y, radius, fill(color));
+    }
+    
+    fn circle_stroke(&mut self, center: Pos2, radius: f32, line: Stroke) {
+        let _ = writeln!(
+            self.body,
+            "<circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"{:.2}\" fill=\"none\"{}/>",
+            center.x, center.y, radius, stroke(line),
+        );
+    }
+    
+    fn rect_filled(&mut self, rect: Rect, color: Color32) {
+        let _ = writeln!(
+            self.body,
+            "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\"{}/>",
+            rect.min.x, rect.min.y, rect.width(), rect.height(), fill(color),
+        );
+    }
+    
+    fn convex_polygon(&mut self, points: Vec<Pos2>, color: Color32) {
+        let points: Vec<String> = points.iter().map(|p| format!("{:.2},{:.2}", p.x, p.y)).collect();
+        let _ = writeln!(self.body, "<polygon points=\"{}\"{}/>", points.join(" "), fill(color));
+    }
+    
+    fn cubic_bezier(&mut self, points: [Pos2; 4], line: Stroke) {
+        let [p0, p1, p2, p3] = points;
+        let _ = writeln!(
+            self.body,
+            "<path d=\"M{:.2} {:.2} C{:.2} {:.2} {:.2} {:.2} {:.2} {:.2}\" fill=\"none\"{}/>",
+            p0.x, p0.y, p1.x, p1.y, p2.x, p2.y, p3.x, p3.y, stroke(line),
+        );
+    }
+    
+    fn text(&mut self, pos: Pos2, anchor: Align2, text: &str, size: f32, color: Color32) {
+        self.path(&outline::text(text, pos, anchor, size), color);
+    }
+    
+    fn glyph(&mut self, font: &MusicFont, glyph: Glyph, origin: Pos2, line_spacing: f32, color: Color32) {
+        self.path(&outline::music_glyph(font, glyph, origin, line_spacing), color);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,44 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const APP_DIR_NAME: &str = "piano";
+
+// Per-user directory for progress and imported songs
+pub fn data_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join(APP_DIR_NAME))
+}
+
+// Song files the library scans, including imported ones
+pub fn library_dir() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("library"))
+}
+
+// Per-user directory for preferences
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(APP_DIR_NAME))
+}
+
+// Writes through a temporary file and renames it into place, so a crash never
+// leaves a half-written file behind
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+    
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    
+    let mut file = File::create(&temp_path)
+        .with_context(|| format!("Failed to create {}", temp_path.display()))?;
+    file.write_all(contents)
+        .and_then(|_| file.sync_all())
+        .with_context(|| format!("Failed to write {}", temp_path.display()))?;
+    fs::rename(&temp_path, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    
+    Ok(())
+}
\ No newline at end of file
@@ -3,6 +3,11 @@ use eframe::egui;
 pub struct MainWindow {
     show_song_browser: bool,
     show_settings: bool,
+    show_device_selector: bool,
+    show_about: bool,
+    import_song_requested: bool,
+    export_song_requested: bool,
+    export_take_requested: bool,
 }
 
 impl MainWindow {
@@ -10,10 +15,17 @@ impl MainWindow {
         Self {
             show_song_browser: false,
             show_settings: false,
+            show_device_selector: false,
+            show_about: false,
+            import_song_requested: false,
+            export_song_requested: false,
+            export_take_requested: false,
         }
     }
     
-    pub fn show_menu_bar(&mut self, ui: &mut egui::Ui) {
+    // Export entries are only enabled when there is a song loaded or a take
+    // recorded to write out
+    pub fn show_menu_bar(&mut self, ui: &mut egui::Ui, has_song: bool, has_take: bool) {
         egui::menu::bar(ui, |ui| {
             ui.menu_button("File", |ui| {
                 if ui.button("Browse Songs").clicked() {
@@ -21,8 +33,18 @@ impl MainWindow {
                     ui.close_menu();
                 }
                 
-                if ui.button("Import MIDI").clicked() {
-                    // TODO: Implement MIDI file import
+                if ui.button("Import Song").clicked() {
+                    self.import_song_requested = true;
+                    ui.close_menu();
+                }
+                
+                if ui.add_enabled(has_song, egui::Button::new("Export Song")).clicked() {
+                    self.export_song_requested = true;
+                    ui.close_menu();
+                }
+                
+                if ui.add_enabled(has_take, egui::Button::new("Export Take")).clicked() {
+                    self.export_take_requested = true;
                     ui.close_menu();
                 }
                 
@@ -40,20 +62,47 @@ impl MainWindow {
                 }
                 
                 if ui.button("MIDI Devices").clicked() {
-                    // TODO: Show MIDI device selection
+                    self.show_device_selector = true;
                     ui.close_menu();
                 }
             });
             
             ui.menu_button("Help", |ui| {
                 if ui.button("About").clicked() {
-                    // TODO: Show about dialog
+                    self.show_about = true;
                     ui.close_menu();
                 }
             });
         });
     }
     
+    pub fn show_about_dialog(&mut self, ctx: &egui::Context) {
+        egui::Window::new("About")
+            .open(&mut self.show_about)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.heading("Piano Sight Reading");
+                ui.label(format!("Version {}", env!("CARGO_PKG_VERSION")));
+                ui.label("Practice reading music with a MIDI keyboard.");
+            });
+    }
+    
+    // True once after "Import Song" was chosen
+    pub fn take_import_song_request(&mut self) -> bool {
+        std::mem::take(&mut self.import_song_requested)
+    }
+    
+    // True once after "Export Song" was chosen
+    pub fn take_export_song_request(&mut self) -> bool {
+        std::mem::take(&mut self.export_song_requested)
+    }
+    
+    // True once after "Export Take" was chosen
+    pub fn take_export_take_request(&mut self) -> bool {
+        std::mem::take(&mut self.export_take_requested)
+    }
+    
     pub fn should_show_song_browser(&self) -> bool {
         self.show_song_browser
     }
@@ -69,4 +118,16 @@ impl MainWindow {
     pub fn close_settings(&mut self) {
         self.show_settings = false;
     }
+    
+    pub fn should_show_device_selector(&self) -> bool {
+        self.show_device_selector
+    }
+    
+    pub fn open_device_selector(&mut self) {
+        self.show_device_selector = true;
+    }
+    
+    pub fn close_device_selector(&mut self) {
+        self.show_device_selector = false;
+    }
 }
\ No newline at end of file
@@ -1,7 +1,9 @@
 pub mod main_window;
 pub mod song_browser;
 pub mod settings;
+pub mod song_import;
 
 pub use main_window::MainWindow;
 pub use song_browser::SongBrowser;
-pub use settings::SettingsWindow;
\ No newline at end of file
+pub use settings::SettingsWindow;
+pub use song_import::{SongImportDialog, ImportAction};
\ No newline at end of file
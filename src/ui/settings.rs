@@ -1,13 +1,33 @@
+use anyhow::{Context, Result};
 use eframe::egui;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone)]
+use crate::notation::ScoreLayout;
+use crate::storage;
+
+const SETTINGS_FILE_NAME: &str = "settings.json";
+
+// Slider ranges, which loaded settings are also held to
+const LATENCY_RANGE_MS: RangeInclusive<f32> = 0.0..=50.0;
+const FEEDBACK_DURATION_RANGE: RangeInclusive<f32> = 0.5..=3.0;
+const CHORD_TOLERANCE_RANGE_MS: RangeInclusive<u64> = 0..=1000;
+const METRONOME_BPM_RANGE: RangeInclusive<u32> = 60..=200;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AppSettings {
     pub midi_latency_compensation: f32,
     pub visual_feedback_duration: f32,
     pub auto_advance: bool,
     pub show_note_names: bool,
+    pub score_layout: ScoreLayout,
+    pub follow_cursor: bool, // Scroll the score along while playing
     pub metronome_enabled: bool,
     pub metronome_bpm: u32,
+    pub chord_tolerance_ms: u64,
 }
 
 impl Default for AppSettings {
@@ -17,25 +37,77 @@ impl Default for AppSettings {
             visual_feedback_duration: 1.0,
             auto_advance: true,
             show_note_names: false,
+            score_layout: ScoreLayout::Page,
+            follow_cursor: true,
             metronome_enabled: false,
             metronome_bpm: 120,
+            chord_tolerance_ms: 250,
         }
     }
 }
 
+impl AppSettings {
+    pub fn default_path() -> Option<PathBuf> {
+        storage::config_dir().map(|dir| dir.join(SETTINGS_FILE_NAME))
+    }
+    
+    // Missing files give the defaults; unknown or missing fields are tolerated,
+    // and out-of-range values are brought back into range
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let settings: Self = serde_json::from_str(&contents)
+            .with_context(|| format!("Malformed settings file {}", path.display()))?;
+        Ok(settings.clamped())
+    }
+    
+    fn clamped(self) -> Self {
+        let defaults = Self::default();
+        // Clamping leaves NaN alone, so that falls back on the default
+        let clamp = |value: f32, range: RangeInclusive<f32>, default: f32| {
+            if value.is_nan() { default } else { value.clamp(*range.start(), *range.end()) }
+        };
+        
+        Self {
+            midi_latency_compensation: clamp(self.midi_latency_compensation, LATENCY_RANGE_MS, defaults.midi_latency_compensation),
+            visual_feedback_duration: clamp(self.visual_feedback_duration, FEEDBACK_DURATION_RANGE, defaults.visual_feedback_duration),
+            chord_tolerance_ms: self.chord_tolerance_ms.clamp(*CHORD_TOLERANCE_RANGE_MS.start(), *CHORD_TOLERANCE_RANGE_MS.end()),
+            metronome_bpm: self.metronome_bpm.clamp(*METRONOME_BPM_RANGE.start(), *METRONOME_BPM_RANGE.end()),
+            ..self
+        }
+    }
+    
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        storage::write_atomic(path, json.as_bytes())
+    }
+}
+
 pub struct SettingsWindow {
     settings: AppSettings,
+    path: Option<PathBuf>,
+    status: Option<(String, egui::Color32)>,
 }
 
 impl SettingsWindow {
-    pub fn new() -> Self {
+    pub fn new(settings: AppSettings, path: Option<PathBuf>) -> Self {
         Self {
-            settings: AppSettings::default(),
+            settings,
+            path,
+            status: None,
         }
     }
     
-    pub fn show(&mut self, ctx: &egui::Context) {
+    // Returns true when a setting changed this frame so it can be applied live
+    pub fn show(&mut self, ctx: &egui::Context, open: &mut bool) -> bool {
+        let previous = self.settings.clone();
+        
         egui::Window::new("Settings")
+            .open(open)
             .default_size([400.0, 300.0])
             .show(ctx, |ui| {
                 ui.heading("Application Settings");
@@ -46,7 +118,7 @@ impl SettingsWindow {
                 ui.collapsing("MIDI Settings", |ui| {
                     ui.horizontal(|ui| {
                         ui.label("Latency Compensation (ms):");
-                        ui.add(egui::Slider::new(&mut self.settings.midi_latency_compensation, 0.0..=50.0));
+                        ui.add(egui::Slider::new(&mut self.settings.midi_latency_compensation, LATENCY_RANGE_MS));
                     });
                 });
                 
@@ -56,10 +128,18 @@ impl SettingsWindow {
                 ui.collapsing("Visual Settings", |ui| {
                     ui.horizontal(|ui| {
                         ui.label("Feedback Duration (s):");
-                        ui.add(egui::Slider::new(&mut self.settings.visual_feedback_duration, 0.5..=3.0));
+                        ui.add(egui::Slider::new(&mut self.settings.visual_feedback_duration, FEEDBACK_DURATION_RANGE));
                     });
                     
                     ui.checkbox(&mut self.settings.show_note_names, "Show note names");
+                    
+                    ui.horizontal(|ui| {
+                        ui.label("Score layout:");
+                        ui.radio_value(&mut self.settings.score_layout, ScoreLayout::Page, "Page");
+                        ui.radio_value(&mut self.settings.score_layout, ScoreLayout::Ticker, "Single line");
+                    });
+                    
+                    ui.checkbox(&mut self.settings.follow_cursor, "Scroll to follow the cursor");
                 });
                 
                 ui.separator();
@@ -67,6 +147,11 @@ impl SettingsWindow {
                 // Gameplay Settings
                 ui.collapsing("Gameplay Settings", |ui| {
                     ui.checkbox(&mut self.settings.auto_advance, "Auto-advance to next note");
+                    
+                    ui.horizontal(|ui| {
+                        ui.label("Rolled chord tolerance (ms):");
+                        ui.add(egui::Slider::new(&mut self.settings.chord_tolerance_ms, CHORD_TOLERANCE_RANGE_MS));
+                    });
                 });
                 
                 ui.separator();
@@ -78,7 +163,7 @@ impl SettingsWindow {
                     if self.settings.metronome_enabled {
                         ui.horizontal(|ui| {
                             ui.label("BPM:");
-                            ui.add(egui::Slider::new(&mut self.settings.metronome_bpm, 60..=200));
+                            ui.add(egui::Slider::new(&mut self.settings.metronome_bpm, METRONOME_BPM_RANGE));
                         });
                     }
                 });
@@ -91,15 +176,101 @@ impl SettingsWindow {
                     }
                     
                     ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
-                        if ui.button("Save").clicked() {
-                            // TODO: Save settings to file
+                        if ui.add_enabled(self.path.is_some(), egui::Button::new("Save")).clicked() {
+                            self.save();
+                        }
+                        
+                        if let Some((message, color)) = &self.status {
+                            ui.colored_label(*color, message);
                         }
                     });
                 });
             });
+        
+        self.settings != previous
+    }
+    
+    fn save(&mut self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        
+        self.status = Some(match self.settings.save(path) {
+            Ok(()) => ("Saved".to_string(), egui::Color32::from_rgb(0, 150, 0)),
+            Err(e) => {
+                log::error!("Failed to save settings: {:#}", e);
+                ("Save failed".to_string(), egui::Color32::from_rgb(200, 0, 0))
+            }
+        });
     }
     
     pub fn get_settings(&self) -> &AppSettings {
         &self.settings
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn scratch_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("piano-settings-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(SETTINGS_FILE_NAME)
+    }
+    
+    fn load_text(name: &str, contents: &str) -> Result<AppSettings> {
+        let path = scratch_file(name);
+        fs::write(&path, contents).unwrap();
+        let settings = AppSettings::load(&path);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        settings
+    }
+    
+    #[test]
+    fn saved_settings_load_back() {
+        let settings = AppSettings {
+            midi_latency_compensation: 22.5,
+            auto_advance: false,
+            score_layout: ScoreLayout::Ticker,
+            metronome_enabled: true,
+            metronome_bpm: 96,
+            chord_tolerance_ms: 400,
+            ..AppSettings::default()
+        };
+        let path = scratch_file("round-trip");
+        settings.save(&path).unwrap();
+        let loaded = AppSettings::load(&path).unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert_eq!(loaded, settings);
+    }
+    
+    #[test]
+    fn missing_fields_take_their_defaults() {
+        let loaded = load_text("partial", r#"{ "show_note_names": true, "retired_setting": 3 }"#).unwrap();
+        assert_eq!(loaded, AppSettings { show_note_names: true, ..AppSettings::default() });
+        
+        assert!(load_text("malformed", "{ show_note_names").is_err());
+    }
+    
+    #[test]
+    fn out_of_range_settings_are_clamped() {
+        let loaded = load_text("out-of-range", r#"{
+            "midi_latency_compensation": -5.0,
+            "visual_feedback_duration": 60.0,
+            "metronome_bpm": 1000,
+            "chord_tolerance_ms": 5000
+        }"#).unwrap();
+        assert_eq!(loaded.midi_latency_compensation, *LATENCY_RANGE_MS.start());
+        assert_eq!(loaded.visual_feedback_duration, *FEEDBACK_DURATION_RANGE.end());
+        assert_eq!(loaded.metronome_bpm, *METRONOME_BPM_RANGE.end());
+        assert_eq!(loaded.chord_tolerance_ms, *CHORD_TOLERANCE_RANGE_MS.end());
+        
+        let low = AppSettings { metronome_bpm: 0, ..AppSettings::default() }.clamped();
+        assert_eq!(low.metronome_bpm, *METRONOME_BPM_RANGE.start());
+        
+        // JSON has no NaN, but a value computed in memory can be
+        let nan = AppSettings { visual_feedback_duration: f32::NAN, ..AppSettings::default() }.clamped();
+        assert_eq!(nan.visual_feedback_duration, AppSettings::default().visual_feedback_duration);
+    }
 }
\ No newline at end of file
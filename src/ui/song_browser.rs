@@ -1,9 +1,11 @@
 use eframe::egui;
-use crate::music::{MusicLibrary, DifficultyLevel};
+use crate::game::ProgressTracker;
+use crate::music::{MusicLibrary, DifficultyLevel, DifficultyClassifier};
 
 pub struct SongBrowser {
     selected_difficulty: Option<DifficultyLevel>,
     selected_song_index: Option<usize>,
+    search: String,
 }
 
 impl SongBrowser {
@@ -11,13 +13,15 @@ impl SongBrowser {
         Self {
             selected_difficulty: None,
             selected_song_index: None,
+            search: String::new(),
         }
     }
     
-    pub fn show(&mut self, ctx: &egui::Context, music_library: &mut MusicLibrary) -> Option<String> {
+    pub fn show(&mut self, ctx: &egui::Context, music_library: &mut MusicLibrary, progress: &ProgressTracker, open: &mut bool) -> Option<String> {
         let mut selected_song_id = None;
         
         egui::Window::new("Song Browser")
+            .open(open)
             .default_size([600.0, 400.0])
             .show(ctx, |ui| {
                 ui.heading("Choose a Song to Practice");
@@ -30,6 +34,7 @@ impl SongBrowser {
                     
                     if ui.selectable_label(self.selected_difficulty.is_none(), "All").clicked() {
                         self.selected_difficulty = None;
+                        self.selected_song_index = None;
                     }
                     
                     for difficulty in [DifficultyLevel::Beginner, DifficultyLevel::Intermediate, DifficultyLevel::Advanced, DifficultyLevel::Expert] {
@@ -38,10 +43,23 @@ impl SongBrowser {
                             difficulty.as_str()
                         ).clicked() {
                             self.selected_difficulty = Some(difficulty);
+                            self.selected_song_index = None;
                         }
                     }
                 });
                 
+                // Text search over title, artist and tags
+                ui.horizontal(|ui| {
+                    ui.label("Search:");
+                    if ui.text_edit_singleline(&mut self.search).changed() {
+                        self.selected_song_index = None;
+                    }
+                });
+                
+                if let Some(dir) = music_library.get_user_library_dir() {
+                    ui.small(format!("Songs added to {} appear here automatically", dir.display()));
+                }
+                
                 ui.separator();
                 
                 // Song list
@@ -51,6 +69,15 @@ impl SongBrowser {
                     } else {
                         music_library.get_songs().iter().collect()
                     };
+                    let search = self.search.trim().to_lowercase();
+                    let songs: Vec<_> = songs.into_iter()
+                        .filter(|song| {
+                            search.is_empty()
+                                || song.title.to_lowercase().contains(&search)
+                                || song.artist.to_lowercase().contains(&search)
+                                || song.tags.iter().any(|tag| tag.to_lowercase().contains(&search))
+                        })
+                        .collect();
                     
                     for (index, song) in songs.iter().enumerate() {
                         let is_selected = self.selected_song_index == Some(index);
@@ -68,8 +95,35 @@ impl SongBrowser {
                         
                         if is_selected {
                             ui.indent("song_details", |ui| {
-                                ui.small(&format!("Duration: {:.1}s", song.duration));
-                                ui.small(&format!("Notes: {}", song.notes.len()));
+                                ui.small(format!("Duration: {:.1}s", song.duration));
+                                let tempo_changes = song.tempo_map.get_changes();
+                                let slowest = tempo_changes.iter().map(|change| change.bpm).fold(f32::MAX, f32::min);
+                                let fastest = tempo_changes.iter().map(|change| change.bpm).fold(0.0, f32::max);
+                                let tempo = if fastest - slowest < 1.0 {
+                                    format!("{:.0} BPM", fastest)
+                                } else {
+                                    format!("{:.0}-{:.0} BPM", slowest, fastest)
+                                };
+                                ui.small(format!(
+                                    "Tempo: {}, {}/{}, {}",
+                                    tempo,
+                                    song.signatures.time_signature_at(0.0).numerator,
+                                    song.signatures.time_signature_at(0.0).denominator,
+                                    song.signatures.key_signature_at(0.0).name()
+                                ));
+                                if !song.tags.is_empty() {
+                                    ui.small(format!("Tags: {}", song.tags.join(", ")));
+                                }
+                                ui.small(format!("Notes: {}", song.notes.len()));
+                                ui.small(format!("Suggested practice: {}", DifficultyClassifier::estimate_practice_time(song.difficulty)));
+                                
+                                if let Some(song_progress) = progress.get_song_progress(&song.id) {
+                                    ui.small(format!(
+                                        "Best accuracy: {:.0}% ({} attempts)",
+                                        song_progress.best_accuracy * 100.0,
+                                        song_progress.attempts
+                                    ));
+                                }
                                 
                                 if ui.button("Start Practice").clicked() {
                                     selected_song_id = Some(song.id.clone());
@@ -0,0 +1,258 @@
+use eframe::egui;
+use std::path::{Path, PathBuf};
+
+use crate::music::instruments::instrument_name;
+use crate::music::scanner::LibraryScanner;
+use crate::music::{ChannelSelection, DifficultyClassifier, ParsedMidi};
+
+pub enum ImportAction {
+    None,
+    Import,
+    Cancel,
+}
+
+// What one (track, channel) pair becomes in the imported song
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChannelRole {
+    Skip,
+    Practice, // Played, with the hand worked out from the notes
+    RightHand,
+    LeftHand,
+    Accompaniment,
+}
+
+impl ChannelRole {
+    const ALL: [ChannelRole; 5] = [
+        ChannelRole::Skip,
+        ChannelRole::Practice,
+        ChannelRole::RightHand,
+        ChannelRole::LeftHand,
+        ChannelRole::Accompaniment,
+    ];
+    
+    fn as_str(&self) -> &'static str {
+        match self {
+            ChannelRole::Skip => "Skip",
+            ChannelRole::Practice => "Practice",
+            ChannelRole::RightHand => "Right hand",
+            ChannelRole::LeftHand => "Left hand",
+            ChannelRole::Accompaniment => "Accompaniment",
+        }
+    }
+}
+
+// Lets the user pick which tracks and channels of a MIDI file (or parts and
+// staves of a MusicXML file, or voices of an ABC tune) become the song, which
+// hand plays each and which are only backing
+pub struct SongImportDialog {
+    source: PathBuf,
+    parsed: Result<ParsedMidi, String>,
+    title: String,
+    artist: String,
+    roles: Vec<((usize, u8), ChannelRole)>,
+    error: Option<String>,
+}
+
+impl SongImportDialog {
+    pub fn open(source: PathBuf) -> Self {
+        let parsed = LibraryScanner::parse_file(&source).map_err(|e| format!("{:#}", e));
+        // Drums are skipped, and the hands start out as the parser guesses them
+        let roles = parsed.as_ref()
+            .map(|parsed| {
+                let practised = parsed.default_channels();
+                let hands = parsed.guess_hands(&practised);
+                parsed.all_channels().into_iter()
+                    .map(|key| {
+                        let role = if !practised.contains(&key) {
+                            ChannelRole::Skip
+                        } else if hands.right.contains(&key) {
+                            ChannelRole::RightHand
+                        } else if hands.left.contains(&key) {
+                            ChannelRole::LeftHand
+                        } else {
+                            ChannelRole::Practice
+                        };
+                        (key, role)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let title = parsed.as_ref().ok()
+            .and_then(|parsed| parsed.title.clone())
+            .or_else(|| source.file_stem().map(|stem| stem.to_string_lossy().replace('_', " ")))
+            .unwrap_or_default();
+        let artist = parsed.as_ref().ok()
+            .and_then(|parsed| parsed.composer.clone())
+            .unwrap_or_default();
+        
+        Self {
+            source,
+            parsed,
+            title,
+            artist,
+            roles,
+            error: None,
+        }
+    }
+    
+    pub fn show(&mut self, ctx: &egui::Context) -> ImportAction {
+        let mut action = ImportAction::None;
+        let mut open = true;
+        
+        egui::Window::new("Import Song")
+            .open(&mut open)
+            .default_size([450.0, 400.0])
+            .show(ctx, |ui| {
+                ui.label(self.source.display().to_string());
+                ui.separator();
+                
+                let parsed = match &self.parsed {
+                    Ok(parsed) => parsed,
+                    Err(e) => {
+                        ui.colored_label(egui::Color32::from_rgb(200, 0, 0), e);
+                        if ui.button("Close").clicked() {
+                            action = ImportAction::Cancel;
+                        }
+                        return;
+                    }
+                };
+                
+                // Notes the parser had to repair, so a mangled file is noticed
+                // before it is imported
+                for warning in &parsed.warnings {
+                    ui.colored_label(egui::Color32::from_rgb(200, 120, 0), warning);
+                }
+                
+                ui.horizontal(|ui| {
+                    ui.label("Title:");
+                    ui.text_edit_singleline(&mut self.title);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Artist:");
+                    ui.text_edit_singleline(&mut self.artist);
+                });
+                
+                ui.separator();
+                let extension = self.source.extension()
+                    .and_then(|extension| extension.to_str())
+                    .map(str::to_lowercase);
+                let (track_label, channel_label) = match extension.as_deref() {
+                    Some("mid" | "midi") => ("Track", "Channel"),
+                    Some("abc") => ("Voice", "Staff"),
+                    _ => ("Part", "Staff"),
+                };
+                ui.label(format!("What each {} and {} is used for:", track_label.to_lowercase(), channel_label.to_lowercase()));
+                
+                egui::ScrollArea::vertical().max_height(220.0).show(ui, |ui| {
+                    for track in &parsed.tracks {
+                        if track.channels.is_empty() {
+                            continue;
+                        }
+                        
+                        let name = track.name.clone().unwrap_or_else(|| format!("{} {}", track_label, track.index + 1));
+                        ui.label(format!("{} ({} notes)", name, track.note_count));
+                        
+                        ui.indent(("track", track.index), |ui| {
+                            for channel in &track.channels {
+                                let key = (track.index, channel.channel);
+                                let Some((_, role)) = self.roles.iter_mut().find(|(k, _)| *k == key) else {
+                                    continue;
+                                };
+                                
+                                let instrument = if channel.percussion {
+                                    Some("Drums")
+                                } else {
+                                    channel.program.map(instrument_name)
+                                };
+                                let mut label = format!("{} {}", channel_label, channel.channel + 1);
+                                if let Some(instrument) = instrument {
+                                    label.push_str(&format!(" · {}", instrument));
+                                }
+                                
+                                ui.horizontal(|ui| {
+                                    egui::ComboBox::from_id_source(("role", key))
+                                        .selected_text(role.as_str())
+                                        .width(120.0)
+                                        .show_ui(ui, |ui| {
+                                            for option in ChannelRole::ALL {
+                                                ui.selectable_value(role, option, option.as_str());
+                                            }
+                                        });
+                                    ui.label(format!("{} ({} notes)", label, channel.note_count));
+                                });
+                            }
+                        });
+                    }
+                });
+                
+                ui.separator();
+                
+                let selection = self.get_selection();
+                let notes = parsed.notes_for(&selection.play, Some(&selection.hands));
+                let difficulty = DifficultyClassifier::classify_song(&notes);
+                ui.horizontal(|ui| {
+                    ui.label(format!("{} notes ·", notes.len()));
+                    ui.colored_label(difficulty.color(), difficulty.as_str());
+                });
+                
+                if let Some(error) = &self.error {
+                    ui.colored_label(egui::Color32::from_rgb(200, 0, 0), error);
+                }
+                
+                ui.horizontal(|ui| {
+                    let can_import = !notes.is_empty() && !self.title.trim().is_empty();
+                    if ui.add_enabled(can_import, egui::Button::new("Import")).clicked() {
+                        action = ImportAction::Import;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        action = ImportAction::Cancel;
+                    }
+                });
+            });
+        
+        if !open {
+            action = ImportAction::Cancel;
+        }
+        action
+    }
+    
+    pub fn get_source(&self) -> &Path {
+        &self.source
+    }
+    
+    pub fn get_parsed(&self) -> Option<&ParsedMidi> {
+        self.parsed.as_ref().ok()
+    }
+    
+    pub fn get_title(&self) -> &str {
+        self.title.trim()
+    }
+    
+    pub fn get_artist(&self) -> &str {
+        self.artist.trim()
+    }
+    
+    pub fn get_selection(&self) -> ChannelSelection {
+        let mut selection = ChannelSelection::default();
+        for &(key, role) in &self.roles {
+            match role {
+                ChannelRole::Skip => {}
+                ChannelRole::Practice => selection.play.push(key),
+                ChannelRole::RightHand => {
+                    selection.play.push(key);
+                    selection.hands.right.push(key);
+                }
+                ChannelRole::LeftHand => {
+                    selection.play.push(key);
+                    selection.hands.left.push(key);
+                }
+                ChannelRole::Accompaniment => selection.accompaniment.push(key),
+            }
+        }
+        selection
+    }
+    
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,51 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Engraves a built-in song through `piano export` and compares the SVG with
+// the one in tests/snapshots. Set UPDATE_SNAPSHOTS=1 to rewrite the snapshot
+// after an intended change to the engraving.
+
+fn snapshot_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("snapshots").join(name)
+}
+
+// An empty home and working directory, so no installed music font or user
+// library changes the output; only a bundled font is used
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("piano-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn export_svg(song: &str) -> String {
+    let dir = scratch_dir(song);
+    let output = dir.join("score.svg");
+    let status = Command::new(env!("CARGO_BIN_EXE_piano"))
+        .args(["export", song])
+        .arg(&output)
+        .current_dir(&dir)
+        .env("HOME", &dir)
+        .env("XDG_DATA_HOME", dir.join("data"))
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .status()
+        .unwrap();
+    assert!(status.success(), "piano export {} failed", song);
+    
+    let svg = fs::read_to_string(&output).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    svg
+}
+
+#[test]
+fn twinkle_svg_matches_snapshot() {
+    let svg = export_svg("twinkle");
+    let path = snapshot_path("twinkle.svg");
+    
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, &svg).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap();
+    assert!(svg == expected, "twinkle.svg differs from {}; rerun with UPDATE_SNAPSHOTS=1 if the change is intended", path.display());
+}
\ No newline at end of file